@@ -12,8 +12,6 @@ echo "Deconfiguring..."
 
 set -x
 
-rm -f kernel/kentry/aarch64/config.S
-rm -f kernel/kentry/x86_64/config.asm
 rm -f kernel/src/config.rs
 rm -f config.mk
 
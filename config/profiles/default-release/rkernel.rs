@@ -3,6 +3,76 @@ pub const MESSAGE_FIRST: &str = "Hello World!\n";
 
 /// Max ammount of characters that fit into the kernel global log, sized in bytes.
 ///
-/// If it overflows , a kernel panic is triggered
+/// If it overflows, the oldest records are overwritten. Must hold at least 2 records of 1042 bytes.
 pub const LOG_STATIC_CAPACITY: usize = 204_800;
 
+/// Max level per module, e.g. `"info,memman=debug,arch::amd64::idt=off"`.
+///
+/// Levels: off, error, warn, info, debug, trace. Entries without a module set the default level.
+pub const LOG_FILTER: &str = "info";
+
+/// Bytes reserved in the kernel image for the function names printed in backtraces.
+///
+/// Filled after linking by `scripts/build/ksymtab.sh`, which warns if the symbols do not fit.
+pub const SYMBOL_TABLE_SIZE: usize = 0x8_0000;
+
+/// Physical address of the RAM that keeps the last crash report across a reboot.
+///
+/// Must lie in usable RAM that the firmware & limine leave alone, the defaults are 16MiB into the
+/// RAM of QEMU `q35`/`pc` (x86_64) & `virt` (aarch64).
+#[cfg(target_arch = "x86_64")]
+pub const CRASH_DUMP_ADDRESS: usize = 0x0100_0000;
+#[cfg(target_arch = "aarch64")]
+pub const CRASH_DUMP_ADDRESS: usize = 0x4100_0000;
+
+/// Bytes reserved for the crash report, the newest log records fill what the panic message leaves.
+pub const CRASH_DUMP_SIZE: usize = 0x1_0000;
+
+/// Reboot after a kernel panic instead of halting, the next boot prints the crash report.
+pub const PANIC_REBOOT: bool = false;
+
+/// Stop in the GDB stub on the serial port at exceptions & panics, see `kernel/src/gdb.rs`.
+pub const GDB_STUB: bool = false;
+
+/// Enter the debug monitor on the serial console & keyboard once `kmain()` is done, see
+/// `kernel/src/monitor.rs`. Set it to true here when debugging, `kmain()` panics otherwise.
+pub const DEBUG_MONITOR: bool = false;
+
+/// Max ammount of log sinks (terminal, serial, ...) that can be registered at the same time.
+pub const LOG_SINK_CAPACITY: usize = 8;
+
+/// Baud rate of the serial port log sink, must divide 115200 on x86_64.
+pub const SERIAL_BAUD_RATE: u32 = 38_400;
+
+/// Physical address of the PL011 UART used as serial port on aarch64, the default is QEMU `virt`.
+/// It is reached through the limine HHDM, which maps it as normal cacheable memory instead of
+/// device memory. This only works on QEMU TCG, which ignores memory attributes.
+pub const SERIAL_PL011_BASE: usize = 0x0900_0000;
+
+/// Lines of text the framebuffer console keeps for scrolling back, including the visible ones.
+pub const CONSOLE_SCROLLBACK: usize = 1000;
+
+/// Draw a boot splash behind the framebuffer console until `kmain()` is done, see
+/// `kernel/src/video/splash.rs`. Every line scrolled over it redraws the whole screen.
+pub const BOOT_SPLASH: bool = false;
+
+/// Limine module used as boot splash if `limine.cfg` loads one with this name, e.g.
+/// `MODULE_PATH=boot:///splash.bmp`. Must be a BMP file, the image embedded in the kernel is used
+/// otherwise.
+pub const BOOT_SPLASH_MODULE: &str = "splash.bmp";
+
+/// Kernel stack size requested from the bootloader, sized in bytes.
+pub const STACK_SIZE: u64 = 0xFFFFFF;
+
+/// Max ammount of cpus the kernel can manage, every cpu gets its own GDT, TSS & interrupt stacks.
+pub const CPU_MAX_COUNT: usize = 8;
+
+/// Ammount of memory reserved for the page tables built at boot, sized in bytes.
+///
+/// Every GiB of physical memory needs about 2 tables (4KiB each) for the HHDM.
+pub const PAGING_BOOTSTRAP_SIZE: usize = 0x20_0000;
+
+/// `MemoryMapper` implementation used for the global physical memory map.
+///
+/// Options: `TableMemoryMapper` (max 400 entries), `BuddyMemoryMapper` (scales with RAM size)
+pub type GlobalMemoryMapper = crate::memman::map::TableMemoryMapper;
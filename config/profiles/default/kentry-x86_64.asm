@@ -1,2 +0,0 @@
-; Kernel stack size offered by the bootloader
-CONFIG_STACK_SIZE  equ 0xFFFFFF
@@ -0,0 +1,128 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// amd64 part of the GDB stub, see gdb.rs
+// main source: gdb/features/i386/64bit-core.xml in the gdb sources
+
+use super::idt::InterruptFrame;
+use super::serial::Uart16550;
+use crate::gdb::{copy_memory, Connection, Target};
+use crate::limine;
+use crate::memman::paging::AddressSpace;
+
+/// RFLAGS trap flag, raises #DB after the next instruction
+const RFLAGS_TRAP: u64 = 1 << 8;
+
+/// 17 u64 registers (rax - r15, rip) & 7 u32 registers (eflags, cs, ss, ds, es, fs, gs)
+const REGISTERS_SIZE: usize = 17 * 8 + 7 * 4;
+
+/// nothing to set up, #BP & #DB are handled by the IDT
+pub fn init() {}
+
+impl Connection for Uart16550 {
+    fn get_byte(&mut self) -> u8 {
+        self.receive()
+    }
+
+    fn put_byte(&mut self, byte: u8) {
+        self.write_byte(byte)
+    }
+}
+
+impl InterruptFrame {
+    /// the u64 registers in gdb order
+    fn registers_mut(&mut self) -> [&mut u64; 17] {
+        [
+            &mut self.rax,
+            &mut self.rbx,
+            &mut self.rcx,
+            &mut self.rdx,
+            &mut self.rsi,
+            &mut self.rdi,
+            &mut self.rbp,
+            &mut self.rsp,
+            &mut self.r8,
+            &mut self.r9,
+            &mut self.r10,
+            &mut self.r11,
+            &mut self.r12,
+            &mut self.r13,
+            &mut self.r14,
+            &mut self.r15,
+            &mut self.rip,
+        ]
+    }
+}
+
+impl Target for InterruptFrame {
+    const REGISTERS_SIZE: usize = REGISTERS_SIZE;
+    /// int3
+    const BREAKPOINT: &'static [u8] = &[0xCC];
+    /// #BP is a trap, gdb moves RIP back itself
+    const BREAKPOINT_STOPS_AT: bool = false;
+
+    fn read_registers(&self, buf: &mut [u8]) {
+        let mut frame = *self;
+        for (i, register) in frame.registers_mut().into_iter().enumerate() {
+            buf[i * 8..i * 8 + 8].copy_from_slice(&register.to_le_bytes());
+        }
+        // the data segments are not used in long mode
+        let segments = [self.rflags, self.cs, self.ss, 0, 0, 0, 0];
+        for (i, segment) in segments.into_iter().enumerate() {
+            let offset = 17 * 8 + i * 4;
+            buf[offset..offset + 4].copy_from_slice(&(segment as u32).to_le_bytes());
+        }
+    }
+
+    fn write_registers(&mut self, buf: &[u8]) {
+        for (i, register) in self.registers_mut().into_iter().enumerate() {
+            *register = u64::from_le_bytes(buf[i * 8..i * 8 + 8].try_into().unwrap());
+        }
+        // the segments are kept, iretq would fault on an invalid selector
+        self.rflags = u32::from_le_bytes(buf[17 * 8..17 * 8 + 4].try_into().unwrap()) as u64;
+    }
+
+    fn program_counter(&self) -> usize {
+        self.rip as usize
+    }
+
+    fn set_program_counter(&mut self, address: usize) {
+        self.rip = address as u64;
+    }
+
+    fn set_single_step(&mut self, enabled: bool) {
+        if enabled {
+            self.rflags |= RFLAGS_TRAP;
+        } else {
+            self.rflags &= !RFLAGS_TRAP;
+        }
+    }
+
+    fn read_memory(&self, address: usize, buf: &mut [u8]) -> bool {
+        copy_memory(address, buf.len(), hhdm_address, |virt, offset, count| {
+            // SAFETY: the page is mapped
+            let src = unsafe { core::slice::from_raw_parts(virt as *const u8, count) };
+            buf[offset..offset + count].copy_from_slice(src);
+        })
+    }
+
+    fn write_memory(&mut self, address: usize, data: &[u8]) -> bool {
+        copy_memory(address, data.len(), hhdm_address, |virt, offset, count| {
+            // SAFETY: the HHDM is writable, read-only pages like the kernel code included
+            let dst = unsafe { core::slice::from_raw_parts_mut(virt as *mut u8, count) };
+            dst.copy_from_slice(&data[offset..offset + count]);
+        })
+    }
+}
+
+/// HHDM address of `virt` in the current address space
+fn hhdm_address(virt: usize) -> Option<usize> {
+    // SAFETY: the tables are only read
+    let space = unsafe { AddressSpace::current() };
+    space
+        .translate(virt)
+        .map(|(phys, _, _)| phys + limine::hhdm())
+}
@@ -7,32 +7,176 @@
 // loads the Global Descriptor Table
 // main source: https://wiki.osdev.org/Global_Descriptor_Table
 
-use x86::dtables::{lgdt, sgdt, DescriptorTablePointer};
-#[macro_use]
-use crate::tools::{bin_extract, bin_insert};
 use crate::bitfield;
+use crate::config::CPU_MAX_COUNT;
+use crate::tools::{bin_extract, bin_insert};
+use core::mem;
+use spin::{Mutex, Once};
+use x86::dtables::{lgdt, DescriptorTablePointer};
+use x86::segmentation::SegmentSelector;
+use x86::task::load_tr;
+
+pub const PRIVILEGE_KERNEL: u8 = 0;
+pub const PRIVILEGE_USER: u8 = 3;
+
+/// selector of `new_kernel_code64()` in `GDT`, limine loads the same one into CS
+pub const SELECTOR_KERNEL_CODE64: u16 = 5 << 3;
+/// selector of `new_kernel_data64()`, SYSCALL loads it into SS
+pub const SELECTOR_KERNEL_DATA64: u16 = 6 << 3;
+/// selector of the TSS descriptor, it takes up 2 slots
+const SELECTOR_TSS: u16 = 7 << 3;
+/// base selector for SYSRET, it loads `base + 8` into SS and `base + 16` into CS
+pub const SELECTOR_USER_BASE: u16 = 9 << 3 | PRIVILEGE_USER as u16;
+/// selector of `new_user_data64()`
+pub const SELECTOR_USER_DATA64: u16 = 10 << 3 | PRIVILEGE_USER as u16;
+/// selector of `new_user_code64()`
+pub const SELECTOR_USER_CODE64: u16 = 11 << 3 | PRIVILEGE_USER as u16;
+
+/// Number of 8 byte slots in every GDT
+const GDT_SIZE: usize = 12;
+
+/// Builds the GDT of one cpu, `tss` must point to its own `TaskStateSegment`
+const fn new_gdt(tss: u64) -> [SegmentDescriptor; GDT_SIZE] {
+    let [tss_low, tss_high] =
+        SegmentDescriptor::new_tss(tss, mem::size_of::<TaskStateSegment>() as u32 - 1);
+    [
+        // this exact structure must be preserved for limine facilities to work
+        SegmentDescriptor::null(),
+        SegmentDescriptor::new_kernel_code16(),
+        SegmentDescriptor::new_kernel_data16(),
+        SegmentDescriptor::new_kernel_code32(),
+        SegmentDescriptor::new_kernel_data32(),
+        SegmentDescriptor::new_kernel_code64(),
+        SegmentDescriptor::new_kernel_data64(),
+        // after this anything can be loaded
+        tss_low,
+        tss_high,
+        // SYSRET requires this exact order
+        SegmentDescriptor::new_user_code32(),
+        SegmentDescriptor::new_user_data64(),
+        SegmentDescriptor::new_user_code64(),
+    ]
+}
+
+/// Every cpu needs its own GDT, because `ltr` marks the TSS descriptor as busy.
+/// It is stored in writable memory for the same reason.
+static CPU_GDT: [Once<[SegmentDescriptor; GDT_SIZE]>; CPU_MAX_COUNT] = [Once::INIT; CPU_MAX_COUNT];
+/// Task State Segment of every cpu, the cpu reads it when switching stacks
+static CPU_TSS: [Once<Mutex<TaskStateSegment>>; CPU_MAX_COUNT] = [Once::INIT; CPU_MAX_COUNT];
+
+// ========== Stacks
+
+/// Size of the guard area below every stack
+const STACK_GUARD_SIZE: usize = 4096;
+/// Usable size of every stack referenced by the TSS
+const STACK_SIZE: usize = 4096 * 4;
+
+/// Interrupt Stack Table slot used by the double fault handler (#DF)
+pub const IST_DOUBLE_FAULT: u8 = 1;
+/// Interrupt Stack Table slot used by the non-maskable interrupt handler
+pub const IST_NMI: u8 = 2;
+/// Interrupt Stack Table slot used by the machine check handler (#MC)
+pub const IST_MACHINE_CHECK: u8 = 3;
+/// Interrupt Stack Table slot used by the debug handler (#DB)
+pub const IST_DEBUG: u8 = 4;
+
+/// Stacks per cpu: slot 0 backs RSP0, the rest back the IST entries above
+const STACK_COUNT: usize = 5;
+
+/// Stack with a page sized guard area at its lowest addresses. The guard is never written to,
+/// once it is unmapped a stack overflow causes a page fault instead of silently corrupting memory.
+#[repr(C, align(4096))]
+struct GuardedStack {
+    guard: [u8; STACK_GUARD_SIZE],
+    stack: [u8; STACK_SIZE],
+}
 
-const PRIVILEGE_KERNEL: u8 = 0;
-const PRIVILEGE_USER: u8 = 3;
-
-static GDT: &[SegmentDescriptor] = &[
-    // this exact structure must be preserved for limine facilities to work
-    SegmentDescriptor::null(),
-    SegmentDescriptor::new_kernel_code16(),
-    SegmentDescriptor::new_kernel_data16(),
-    SegmentDescriptor::new_kernel_code32(),
-    SegmentDescriptor::new_kernel_data32(),
-    SegmentDescriptor::new_kernel_code64(),
-    SegmentDescriptor::new_kernel_data64(),
-    // after this anything can be loaded
-];
+impl GuardedStack {
+    const EMPTY: Self = Self {
+        guard: [0; STACK_GUARD_SIZE],
+        stack: [0; STACK_SIZE],
+    };
+
+    /// first address above the stack, stacks grow down
+    fn top(&self) -> u64 {
+        self.stack.as_ptr_range().end as u64
+    }
+
+    /// guard region as (start, end)
+    fn guard(&self) -> (usize, usize) {
+        let range = self.guard.as_ptr_range();
+        (range.start as usize, range.end as usize)
+    }
+}
+
+const EMPTY_STACKS: [GuardedStack; STACK_COUNT] = [GuardedStack::EMPTY; STACK_COUNT];
+
+/// Backing memory of the stacks referenced by `CPU_TSS`, only ever accessed through raw pointers
+static mut CPU_STACKS: [[GuardedStack; STACK_COUNT]; CPU_MAX_COUNT] = [EMPTY_STACKS; CPU_MAX_COUNT];
+
+/// Iterate through the guard regions of all the stacks of a cpu
+pub fn stack_guards(cpu: usize) -> impl Iterator<Item = (usize, usize)> {
+    // SAFETY: only the address is read
+    unsafe { CPU_STACKS[cpu].iter() }.map(GuardedStack::guard)
+}
+
+// ========== Task State Segment
+
+/// 64 bit Task State Segment
+/// main source: https://wiki.osdev.org/Task_State_Segment
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    reserved0: u32,
+    /// stack pointers loaded when entering ring 0-2 from a lower privilege
+    rsp: [u64; 3],
+    reserved1: u64,
+    /// Interrupt Stack Table, IDT entries refer to these with a 1-based index
+    ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    const fn new() -> Self {
+        Self {
+            reserved0: 0,
+            rsp: [0; 3],
+            reserved1: 0,
+            ist: [0; 7],
+            reserved2: 0,
+            reserved3: 0,
+            // no I/O permission bitmap
+            iomap_base: mem::size_of::<Self>() as u16,
+        }
+    }
+}
+
+/// Set the stack the cpu switches to when an interrupt arrives in ring 3
+pub fn set_kernel_stack(cpu: usize, rsp: u64) {
+    CPU_TSS[cpu]
+        .get()
+        .expect("TSS not setup for this cpu!")
+        .lock()
+        .rsp[0] = rsp;
+}
+
+/// Get the stack the cpu switches to when an interrupt arrives in ring 3
+pub fn kernel_stack(cpu: usize) -> u64 {
+    CPU_TSS[cpu]
+        .get()
+        .expect("TSS not setup for this cpu!")
+        .lock()
+        .rsp[0]
+}
 
 /* const_bitfield implementation of SegmentDescriptor
 
 use const_bitfield::bitfield;
 bitfield! {
     #[derive(Debug)]
-    struct SegmentDescriptor(u64);
+    #[derive(Clone, Copy)]
+struct SegmentDescriptor(u64);
     u16, limit0, set_limit0: 15, 0;
     u16, base0,  set_base0: 31, 16;
     u8,  base1,  set_base1: 39, 32;
@@ -58,6 +202,7 @@ bitfield! {
 }
 */
 
+#[derive(Clone, Copy)]
 struct SegmentDescriptor(u64);
 
 impl SegmentDescriptor {
@@ -174,10 +319,65 @@ impl SegmentDescriptor {
         sd.set_access_a(true);
         return sd;
     }
+
+    // same as the kernel descriptors, but accessible from ring 3
+    const fn new_user_code32() -> Self {
+        let mut sd = Self::new_kernel_code32();
+        sd.set_access_dpl(PRIVILEGE_USER);
+        return sd;
+    }
+
+    const fn new_user_data64() -> Self {
+        let mut sd = Self::new_kernel_data64();
+        sd.set_access_dpl(PRIVILEGE_USER);
+        return sd;
+    }
+
+    const fn new_user_code64() -> Self {
+        let mut sd = Self::new_kernel_code64();
+        sd.set_access_dpl(PRIVILEGE_USER);
+        return sd;
+    }
+
+    // system descriptor for an available 64 bit TSS, takes up 2 slots
+    const fn new_tss(base: u64, limit: u32) -> [Self; 2] {
+        let mut sd = Self::null();
+        sd.set_whole_limit(limit);
+        sd.set_whole_base(bin_extract(base, 31, 0) as u32);
+        sd.set_access_p(true);
+        sd.set_access_dpl(PRIVILEGE_KERNEL);
+        sd.set_access_s(false); // system segment
+                                // type 0x9 -> available 64 bit TSS
+        sd.set_access_e(true);
+        sd.set_access_a(true);
+        // the upper slot only holds the rest of the base address
+        [sd, Self(bin_extract(base, 63, 32))]
+    }
 }
 
+/// Loads the GDT & TSS of the boot cpu
 pub fn init() {
-    let mut loaded: DescriptorTablePointer<SegmentDescriptor> = DescriptorTablePointer::default();
-    let gdt = DescriptorTablePointer::new_from_slice(GDT);
-    unsafe { lgdt(&gdt) };
+    init_cpu(0);
+}
+
+/// Sets up and loads the GDT & TSS of a cpu, must be executed on the cpu itself
+pub fn init_cpu(cpu: usize) {
+    let tss = CPU_TSS[cpu].call_once(|| {
+        let mut tss = TaskStateSegment::new();
+        // SAFETY: only the address is read
+        let stacks = unsafe { &CPU_STACKS[cpu] };
+        tss.rsp[0] = stacks[0].top();
+        for (i, stack) in stacks[1..].iter().enumerate() {
+            tss.ist[i] = stack.top();
+        }
+        Mutex::new(tss)
+    });
+    // the address of the data inside the Mutex, which is what the cpu reads
+    let tss_base = &*tss.lock() as *const TaskStateSegment as u64;
+    let gdt = CPU_GDT[cpu].call_once(|| new_gdt(tss_base));
+
+    unsafe {
+        lgdt(&DescriptorTablePointer::new_from_slice(gdt));
+        load_tr(SegmentSelector::from_raw(SELECTOR_TSS));
+    }
 }
@@ -0,0 +1,359 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// loads the Interrupt Descriptor Table and handles CPU exceptions
+// main source: https://wiki.osdev.org/Interrupt_Descriptor_Table
+
+use super::gdt;
+use crate::backtrace;
+use crate::bitfield;
+use crate::config::GDB_STUB;
+use crate::emergency;
+use crate::gdb;
+use crate::tools::{bin_extract, bin_insert};
+use core::arch::global_asm;
+use lazy_static::lazy_static;
+use x86::dtables::{lidt, DescriptorTablePointer};
+
+/// Number of architecturally defined exception vectors
+const EXCEPTION_COUNT: usize = 32;
+
+/// Number of gates the IDT can hold
+const IDT_SIZE: usize = 256;
+
+/// #DB, raised by single steps
+const VECTOR_DEBUG: u64 = 1;
+
+/// #BP, raised by int3
+const VECTOR_BREAKPOINT: u64 = 3;
+
+/// Gate type for interrupt gates, clears IF on entry
+const GATE_INTERRUPT: u8 = 0xE;
+
+/// (mnemonic, name) of every exception vector, indexed by the vector number
+const EXCEPTION_NAMES: [(&str, &str); EXCEPTION_COUNT] = [
+    ("#DE", "Divide Error"),
+    ("#DB", "Debug"),
+    ("NMI", "Non-maskable Interrupt"),
+    ("#BP", "Breakpoint"),
+    ("#OF", "Overflow"),
+    ("#BR", "Bound Range Exceeded"),
+    ("#UD", "Invalid Opcode"),
+    ("#NM", "Device Not Available"),
+    ("#DF", "Double Fault"),
+    ("---", "Coprocessor Segment Overrun"),
+    ("#TS", "Invalid TSS"),
+    ("#NP", "Segment Not Present"),
+    ("#SS", "Stack-Segment Fault"),
+    ("#GP", "General Protection Fault"),
+    ("#PF", "Page Fault"),
+    ("---", "Reserved"),
+    ("#MF", "x87 Floating-Point Exception"),
+    ("#AC", "Alignment Check"),
+    ("#MC", "Machine Check"),
+    ("#XM", "SIMD Floating-Point Exception"),
+    ("#VE", "Virtualization Exception"),
+    ("#CP", "Control Protection Exception"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("#HV", "Hypervisor Injection Exception"),
+    ("#VC", "VMM Communication Exception"),
+    ("#SX", "Security Exception"),
+    ("---", "Reserved"),
+];
+
+// Every exception vector gets a small stub that pushes a dummy error code (if the CPU does not
+// push one) and the vector number, so that all of them share the same stack layout.
+// `isr_common` then saves the general purpose registers and passes them to `exception_dispatch`.
+global_asm!(
+    r#"
+.macro isr_stub_noerr vector
+isr_stub_\vector:
+    push 0
+    push \vector
+    jmp isr_common
+.endm
+
+.macro isr_stub_err vector
+isr_stub_\vector:
+    push \vector
+    jmp isr_common
+.endm
+
+.section .text
+isr_common:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+    cld
+    mov rdi, rsp
+    call exception_dispatch
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    // drop vector & error code
+    add rsp, 16
+    iretq
+
+isr_stub_noerr 0
+isr_stub_noerr 1
+isr_stub_noerr 2
+isr_stub_noerr 3
+isr_stub_noerr 4
+isr_stub_noerr 5
+isr_stub_noerr 6
+isr_stub_noerr 7
+isr_stub_err   8
+isr_stub_noerr 9
+isr_stub_err   10
+isr_stub_err   11
+isr_stub_err   12
+isr_stub_err   13
+isr_stub_err   14
+isr_stub_noerr 15
+isr_stub_noerr 16
+isr_stub_err   17
+isr_stub_noerr 18
+isr_stub_noerr 19
+isr_stub_noerr 20
+isr_stub_err   21
+isr_stub_noerr 22
+isr_stub_noerr 23
+isr_stub_noerr 24
+isr_stub_noerr 25
+isr_stub_noerr 26
+isr_stub_noerr 27
+isr_stub_noerr 28
+isr_stub_err   29
+isr_stub_err   30
+isr_stub_noerr 31
+
+.pushsection .rodata
+.global isr_stub_table
+.balign 8
+isr_stub_table:
+.irp vector, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+    .quad isr_stub_\vector
+.endr
+.popsection
+"#
+);
+
+extern "C" {
+    /// addresses of the exception stubs above, indexed by the vector number
+    static isr_stub_table: [u64; EXCEPTION_COUNT];
+}
+
+lazy_static! {
+    static ref IDT: [GateDescriptor; IDT_SIZE] = {
+        let mut idt = [GateDescriptor::missing(); IDT_SIZE];
+        for (vector, gate) in idt.iter_mut().take(EXCEPTION_COUNT).enumerate() {
+            let handler = unsafe { isr_stub_table[vector] };
+            *gate = GateDescriptor::new_interrupt(handler);
+        }
+        // these handlers must not depend on the (possibly corrupted) current stack
+        idt[1].low.set_ist(gdt::IST_DEBUG);
+        idt[2].low.set_ist(gdt::IST_NMI);
+        idt[8].low.set_ist(gdt::IST_DOUBLE_FAULT);
+        idt[18].low.set_ist(gdt::IST_MACHINE_CHECK);
+        idt
+    };
+}
+
+/// Lower half of a gate, offset 63:32 is stored separately in `GateDescriptor::high`
+#[derive(Clone, Copy)]
+struct GateDescriptorLow(u64);
+
+impl GateDescriptorLow {
+    bitfield!(set_offset0, u16, 15, 0);
+    bitfield!(set_selector, u16, 31, 16);
+    bitfield!(set_ist, u8, 34, 32);
+    bitfield!(set_gate_type, u8, 43, 40);
+    bitfield!(set_dpl, u8, 46, 45);
+    bitfield!(set_present, bool, 47);
+    bitfield!(set_offset1, u16, 63, 48);
+}
+
+/// 16 byte long mode IDT entry
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GateDescriptor {
+    low: GateDescriptorLow,
+    high: u64,
+}
+
+impl GateDescriptor {
+    /// not present gate, raises #NP (or #GP) when triggered
+    const fn missing() -> Self {
+        Self {
+            low: GateDescriptorLow(0),
+            high: 0,
+        }
+    }
+
+    const fn new_interrupt(handler: u64) -> Self {
+        let mut low = GateDescriptorLow(0);
+        low.set_offset0(bin_extract(handler, 15, 0) as u16);
+        low.set_offset1(bin_extract(handler, 31, 16) as u16);
+        low.set_selector(gdt::SELECTOR_KERNEL_CODE64);
+        low.set_ist(0);
+        low.set_gate_type(GATE_INTERRUPT);
+        low.set_dpl(gdt::PRIVILEGE_KERNEL);
+        low.set_present(true);
+        Self {
+            low,
+            high: bin_extract(handler, 63, 32),
+        }
+    }
+}
+
+/// CPU state saved by `isr_common`, in the order it is found on the stack
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    // pushed by the stub
+    pub vector: u64,
+    pub error_code: u64,
+    // pushed by the cpu
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl InterruptFrame {
+    /// logs every saved register, with `emergency!()` as it is used by the exception handlers
+    pub fn dump(&self) {
+        emergency!(
+            "RAX={:016X} RBX={:016X} RCX={:016X}\n",
+            self.rax,
+            self.rbx,
+            self.rcx
+        );
+        emergency!(
+            "RDX={:016X} RSI={:016X} RDI={:016X}\n",
+            self.rdx,
+            self.rsi,
+            self.rdi
+        );
+        emergency!(
+            "RBP={:016X} RSP={:016X} R8 ={:016X}\n",
+            self.rbp,
+            self.rsp,
+            self.r8
+        );
+        emergency!(
+            "R9 ={:016X} R10={:016X} R11={:016X}\n",
+            self.r9,
+            self.r10,
+            self.r11
+        );
+        emergency!(
+            "R12={:016X} R13={:016X} R14={:016X}\n",
+            self.r12,
+            self.r13,
+            self.r14
+        );
+        emergency!(
+            "R15={:016X} RIP={:016X} RFL={:016X}\n",
+            self.r15,
+            self.rip,
+            self.rflags
+        );
+        emergency!("CS ={:04X} SS ={:04X}\n", self.cs, self.ss);
+    }
+}
+
+/// Common rust entry point of all exception stubs
+#[no_mangle]
+extern "C" fn exception_dispatch(frame: &mut InterruptFrame) {
+    // debug traps continue once gdb resumes
+    if GDB_STUB && (frame.vector == VECTOR_DEBUG || frame.vector == VECTOR_BREAKPOINT) {
+        gdb::enter(frame, gdb::SIGTRAP);
+        return;
+    }
+
+    let (mnemonic, name) = EXCEPTION_NAMES
+        .get(frame.vector as usize)
+        .copied()
+        .unwrap_or(("---", "Unknown"));
+    let cr2 = unsafe { x86::controlregs::cr2() };
+
+    emergency!("\nCPU EXCEPTION!!!\n");
+    emergency!("Vector: {} {} ({})\n", frame.vector, mnemonic, name);
+    emergency!("Error code: 0x{:X}\n", frame.error_code);
+    match backtrace::resolve(frame.rip as usize) {
+        Some(symbol) => emergency!("RIP: 0x{:016X} {}\n", frame.rip, symbol.name),
+        None => emergency!("RIP: 0x{:016X}\n", frame.rip),
+    }
+    emergency!("CR2: 0x{:016X}\n", cr2);
+    frame.dump();
+
+    // inspect the faulting state before the panic
+    if GDB_STUB {
+        gdb::enter(frame, signal(frame.vector));
+    }
+    panic!("Unhandled CPU exception {} ({})", mnemonic, name);
+}
+
+/// gdb signal of an exception vector
+fn signal(vector: u64) -> u8 {
+    match vector {
+        0 | 16 | 19 => gdb::SIGFPE,
+        6 => gdb::SIGILL,
+        17 => gdb::SIGBUS,
+        _ => gdb::SIGSEGV,
+    }
+}
+
+pub fn init() {
+    let idt = DescriptorTablePointer::new_from_slice(&IDT[..]);
+    unsafe { lidt(&idt) };
+}
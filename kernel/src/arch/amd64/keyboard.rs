@@ -0,0 +1,81 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// polled PS/2 keyboard with a US layout, used by the debug monitor
+// main source: https://wiki.osdev.org/PS/2_Keyboard (scan code set 1)
+
+use super::portio;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+
+/// status register: a byte waits in the data port
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// status register: the waiting byte comes from the mouse
+const STATUS_AUX: u8 = 1 << 5;
+
+/// set in the break code of a released key
+const RELEASED: u8 = 0x80;
+/// prefix of the keys added after the XT keyboard, e.g. the arrows & keypad enter
+const EXTENDED: u8 = 0xE0;
+
+const LEFT_SHIFT: u8 = 0x2A;
+const RIGHT_SHIFT: u8 = 0x36;
+const ENTER: u8 = 0x1C;
+
+/// ascii of the make codes 0x00 - 0x39, 0 for keys without one
+const KEYMAP: &[u8; 0x3A] =
+    b"\0\x1B1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+const KEYMAP_SHIFT: &[u8; 0x3A] =
+    b"\0\x1B!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+static SHIFT: AtomicBool = AtomicBool::new(false);
+
+/// the previous byte was `EXTENDED`
+static EXTENDED_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Ascii of the next pressed key, None if no key is waiting. The controller must translate to
+/// scan code set 1, which the firmware sets up.
+pub fn read_byte() -> Option<u8> {
+    // SAFETY: the i8042 ports are only read
+    let status = unsafe { portio::input_byte(STATUS_PORT) };
+    if status & STATUS_OUTPUT_FULL == 0 {
+        return None;
+    }
+    let code = unsafe { portio::input_byte(DATA_PORT) };
+    if status & STATUS_AUX != 0 {
+        return None;
+    }
+    if code == EXTENDED {
+        EXTENDED_PENDING.store(true, Ordering::Relaxed);
+        return None;
+    }
+
+    let extended = EXTENDED_PENDING.swap(false, Ordering::Relaxed);
+    let key = code & !RELEASED;
+    let released = code & RELEASED != 0;
+    if key == LEFT_SHIFT || key == RIGHT_SHIFT {
+        // extended shift codes are sent around the print screen key
+        if !extended {
+            SHIFT.store(!released, Ordering::Relaxed);
+        }
+        return None;
+    }
+    // only keypad enter of the extended keys is an ascii character
+    if released || (extended && key != ENTER) {
+        return None;
+    }
+
+    let keymap = match SHIFT.load(Ordering::Relaxed) {
+        true => KEYMAP_SHIFT,
+        false => KEYMAP,
+    };
+    match keymap.get(key as usize) {
+        Some(&0) | None => None,
+        Some(&ascii) => Some(ascii),
+    }
+}
@@ -8,7 +8,12 @@ use super::ArchType;
 use x86;
 use x86_64;
 
+pub mod gdb;
 pub mod gdt;
+pub mod idt;
+pub mod keyboard;
+pub mod serial;
+pub mod syscall;
 
 #[inline]
 pub const fn get_arch() -> ArchType {
@@ -18,6 +23,10 @@ pub const fn get_arch() -> ArchType {
 pub fn init() {
     // load our GDT
     gdt::init();
+    // load our IDT with the exception handlers
+    idt::init();
+    // enable SYSCALL/SYSRET
+    syscall::init();
 }
 
 pub mod portio {
@@ -56,4 +65,79 @@ pub mod cpu {
     pub unsafe fn read_id() -> u64 {
         x86::rdpid()
     }
+
+    /// true if maskable interrupts are enabled on this cpu
+    pub fn interrupts_enabled() -> bool {
+        x86::bits64::rflags::read().contains(x86::bits64::rflags::RFlags::FLAGS_IF)
+    }
+
+    // WARNING: Will cause a general protection fault if used outside of ring 0.
+    pub unsafe fn disable_interrupts() {
+        x86::irq::disable();
+    }
+
+    // WARNING: Will cause a general protection fault if used outside of ring 0.
+    pub unsafe fn enable_interrupts() {
+        x86::irq::enable();
+    }
+
+    /// frame pointer of the calling function, see `backtrace.rs`
+    #[inline(always)]
+    pub fn frame_pointer() -> usize {
+        let rbp: usize;
+        unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp) };
+        rbp
+    }
+
+    /// Raises #BP, which enters the GDB stub when it is enabled
+    pub fn breakpoint() {
+        // SAFETY: int3 only traps into the IDT
+        unsafe { core::arch::asm!("int3") };
+    }
+
+    /// physical address of `virt` if ring 3 may access it with the current tables
+    pub fn translate_user(virt: usize) -> Option<usize> {
+        // SAFETY: the tables are only read
+        let space = unsafe { crate::memman::paging::AddressSpace::current() };
+        space.translate_user(virt)
+    }
+
+    /// CPU cycle counter, only use it to order & compare times on the same cpu
+    pub fn read_timestamp() -> u64 {
+        // SAFETY: rdtsc is available on every x86_64 cpu
+        unsafe { x86::time::rdtsc() }
+    }
+
+    /// logs the control registers with `emergency!()`, used by the panic handler
+    pub fn dump_registers() {
+        use x86::controlregs;
+        // SAFETY: the kernel runs in ring 0
+        let (cr0, cr2, cr3, cr4) = unsafe {
+            (
+                controlregs::cr0().bits(),
+                controlregs::cr2(),
+                controlregs::cr3(),
+                controlregs::cr4().bits(),
+            )
+        };
+        let rflags = x86::bits64::rflags::read().bits();
+        let rsp: usize;
+        unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp) };
+        crate::emergency!("CR0={:016X} CR2={:016X} CR3={:016X}\n", cr0, cr2, cr3);
+        crate::emergency!("CR4={:016X} RSP={:016X} RFL={:016X}\n", cr4, rsp, rflags);
+    }
+
+    // WARNING: Will cause a general protection fault if used outside of ring 0.
+    /// Resets the machine with the keyboard controller, falls back to a triple fault
+    pub unsafe fn reset() -> ! {
+        // pulse the cpu reset line
+        x86::io::outb(0x64, 0xFE);
+        // without an IDT the next exception is a triple fault
+        let idt = x86::dtables::DescriptorTablePointer::<u64> {
+            limit: 0,
+            base: core::ptr::null(),
+        };
+        x86::dtables::lidt(&idt);
+        core::arch::asm!("int3", options(noreturn));
+    }
 }
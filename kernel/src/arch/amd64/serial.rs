@@ -0,0 +1,256 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Polling driver for 16550 compatible UARTs, used as a log sink that does not depend on the
+//! bootloader. QEMU forwards COM1 with `-serial stdio`.
+//!
+//! See more about the registers: `https://wiki.osdev.org/Serial_Ports`
+
+use super::portio;
+use crate::config::SERIAL_BAUD_RATE;
+use crate::sync::IrqMutex;
+use core::fmt;
+use core::fmt::Write;
+use lazy_static::lazy_static;
+
+/// I/O port of the first serial port
+pub const COM1: u16 = 0x3F8;
+
+/// clock of the UART, the baud rate is set as a divisor of it
+const UART_CLOCK: u32 = 115_200;
+
+/// Max ammount of `line_status()` polls before a byte is dropped, prevents a hang if no UART is
+/// present
+const TRANSMIT_TIMEOUT: usize = 100_000;
+
+// register offsets from the base port
+/// receive buffer (read) / transmit holding register (write), divisor low byte if DLAB is set
+const DATA: u16 = 0;
+/// interrupt enable register, divisor high byte if DLAB is set
+const INTERRUPT_ENABLE: u16 = 1;
+/// FIFO control register
+const FIFO_CONTROL: u16 = 2;
+/// line control register, bit 7 is the divisor latch access bit (DLAB)
+const LINE_CONTROL: u16 = 3;
+/// modem control register
+const MODEM_CONTROL: u16 = 4;
+/// line status register
+const LINE_STATUS: u16 = 5;
+/// scratch register, used to detect the UART
+const SCRATCH: u16 = 7;
+
+// line status bits
+/// a received byte is waiting in `DATA`
+const STATUS_DATA_READY: u8 = 1 << 0;
+/// the transmit holding register is empty
+const STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+lazy_static! {
+    /// COM1, initialised on first use so it is available before `arch::init()`
+    pub static ref SERIAL0: IrqMutex<Uart16550> =
+        IrqMutex::new(unsafe { Uart16550::new(COM1, SERIAL_BAUD_RATE) });
+}
+
+/// public interface to print to `SERIAL0`, line feeds are expanded to CR LF
+pub fn print_bytes(s: &[u8]) {
+    SERIAL0.lock().write_bytes(s);
+}
+
+/// Unlocked handle to COM1 for the panic & exception handlers, which can not wait for `SERIAL0`.
+/// Its output may interleave with the holder of the lock, the UART must be initialised already.
+pub fn emergency_writer() -> Uart16550 {
+    Uart16550 {
+        port: COM1,
+        present: true,
+    }
+}
+
+/// Errors while setting up a `Uart16550`
+///
+/// ## Variants:
+/// - `Missing` the scratch register did not keep its value, no UART is present at the port
+/// - `InvalidBaudRate` the baud rate is 0 or does not divide `UART_CLOCK`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SerialError {
+    Missing,
+    InvalidBaudRate(u32),
+}
+
+/// 16550 UART at an I/O port
+pub struct Uart16550 {
+    port: u16,
+    /// false if the UART is missing, writes are dropped
+    present: bool,
+}
+
+impl Uart16550 {
+    /// Sets up the UART at `port` with 8 data bits, no parity, 1 stop bit & enabled FIFOs.
+    /// A missing UART or an invalid baud rate is logged and leaves a `Uart16550` that drops all
+    /// writes.
+    ///
+    /// # Safety
+    /// `port` must be the base port of a 16550 UART, or unused.
+    pub unsafe fn new(port: u16, baud_rate: u32) -> Self {
+        let mut uart = Self {
+            port,
+            present: false,
+        };
+        // can not use `log!()` as the UART may be a log sink itself
+        if let Err(e) = uart.init(baud_rate) {
+            crate::limine::print_bytes(b"[WARNING] Serial port could not be initialised: ");
+            crate::limine::print_bytes(match e {
+                SerialError::Missing => b"Missing\n",
+                SerialError::InvalidBaudRate(_) => b"InvalidBaudRate\n",
+            });
+        }
+        uart
+    }
+
+    /// (Re)initialises the UART
+    ///
+    /// # Safety
+    /// `self.port` must be the base port of a 16550 UART, or unused.
+    pub unsafe fn init(&mut self, baud_rate: u32) -> Result<(), SerialError> {
+        if baud_rate == 0 || UART_CLOCK % baud_rate != 0 {
+            return Err(SerialError::InvalidBaudRate(baud_rate));
+        }
+        // an unused port reads back as 0xFF
+        self.output(SCRATCH, 0x5A);
+        if self.input(SCRATCH) != 0x5A {
+            self.present = false;
+            return Err(SerialError::Missing);
+        }
+
+        let divisor = (UART_CLOCK / baud_rate) as u16;
+        // no interrupts, polling only
+        self.output(INTERRUPT_ENABLE, 0x00);
+        // set DLAB, then the divisor
+        self.output(LINE_CONTROL, 0x80);
+        self.output(DATA, divisor as u8);
+        self.output(INTERRUPT_ENABLE, (divisor >> 8) as u8);
+        // clear DLAB, 8 bits, no parity, one stop bit
+        self.output(LINE_CONTROL, 0x03);
+        // enable & clear the FIFOs, 14 byte receive threshold
+        self.output(FIFO_CONTROL, 0xC7);
+        // DTR, RTS & OUT2
+        self.output(MODEM_CONTROL, 0x0B);
+        self.present = true;
+        Ok(())
+    }
+
+    /// true if a UART was detected at the port
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    /// Blocks until the transmitter is ready & writes `byte`, dropped if the UART is missing or
+    /// does not become ready
+    pub fn write_byte(&mut self, byte: u8) {
+        if !self.present {
+            return;
+        }
+        for _ in 0..TRANSMIT_TIMEOUT {
+            if self.line_status() & STATUS_TRANSMIT_EMPTY != 0 {
+                unsafe { self.output(DATA, byte) };
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Writes all bytes, line feeds are expanded to CR LF
+    pub fn write_bytes(&mut self, s: &[u8]) {
+        for &byte in s {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+    }
+
+    /// Returns a received byte, does not block
+    pub fn read_byte(&mut self) -> Option<u8> {
+        if !self.present || self.line_status() & STATUS_DATA_READY == 0 {
+            return None;
+        }
+        Some(unsafe { self.input(DATA) })
+    }
+
+    /// Blocks until a byte is received, never returns if the UART is missing
+    pub fn receive(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.read_byte() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn line_status(&self) -> u8 {
+        unsafe { self.input(LINE_STATUS) }
+    }
+
+    unsafe fn output(&self, register: u16, value: u8) {
+        portio::output_byte(self.port + register, value)
+    }
+
+    unsafe fn input(&self, register: u16) -> u8 {
+        portio::input_byte(self.port + register)
+    }
+}
+
+impl Write for Uart16550 {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+// kernel tests, see `make test-x86_64`
+#[cfg(all(test, target_os = "none"))]
+mod tests {
+    use super::*;
+
+    /// loopback bit of the modem control register
+    const MODEM_LOOPBACK: u8 = 1 << 4;
+
+    #[test_case]
+    fn com1_is_present() {
+        assert!(SERIAL0.lock().is_present());
+    }
+
+    #[test_case]
+    fn loopback_receive() {
+        let mut uart = SERIAL0.lock();
+        unsafe { uart.output(MODEM_CONTROL, 0x0B | MODEM_LOOPBACK) };
+        // drop stale input
+        while uart.read_byte().is_some() {}
+        uart.write_byte(0xAE);
+        let mut received = None;
+        for _ in 0..TRANSMIT_TIMEOUT {
+            received = uart.read_byte();
+            if received.is_some() {
+                break;
+            }
+        }
+        unsafe { uart.output(MODEM_CONTROL, 0x0B) };
+        assert_eq!(received, Some(0xAE));
+    }
+
+    #[test_case]
+    fn invalid_baud_rate() {
+        let mut uart = SERIAL0.lock();
+        assert_eq!(
+            unsafe { uart.init(0) },
+            Err(SerialError::InvalidBaudRate(0))
+        );
+        assert_eq!(
+            unsafe { uart.init(7) },
+            Err(SerialError::InvalidBaudRate(7))
+        );
+        assert!(uart.is_present());
+    }
+}
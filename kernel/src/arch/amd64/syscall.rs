@@ -0,0 +1,197 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// sets up the SYSCALL/SYSRET instructions used to enter the kernel from ring 3
+// main source: https://wiki.osdev.org/SYSENTER#AMD:_SYSCALL.2FSYSRET
+
+use super::gdt;
+use crate::config::CPU_MAX_COUNT;
+use crate::syscall;
+use core::arch::{asm, global_asm};
+use x86::msr::{
+    rdmsr, wrmsr, IA32_EFER, IA32_FMASK, IA32_GS_BASE, IA32_KERNEL_GSBASE, IA32_LSTAR, IA32_STAR,
+};
+
+/// EFER.SCE, enables the SYSCALL/SYSRET instructions
+const EFER_SYSCALL_ENABLE: u64 = 1 << 0;
+
+/// RFLAGS bits cleared on SYSCALL: TF, IF, DF, AC
+const SYSCALL_FLAGS_MASK: u64 = (1 << 8) | (1 << 9) | (1 << 10) | (1 << 18);
+
+/// RFLAGS of a fresh user program, only IF and the always-one bit 1 are set
+const USER_INITIAL_FLAGS: u64 = (1 << 9) | (1 << 1);
+
+/// Data reached through GS while in the kernel, `syscall_entry` depends on the field offsets
+#[repr(C)]
+struct CpuLocal {
+    /// gs:0, stack loaded on syscall entry
+    kernel_rsp: u64,
+    /// gs:8, scratch slot for the user stack pointer
+    user_rsp: u64,
+}
+
+impl CpuLocal {
+    const EMPTY: Self = Self {
+        kernel_rsp: 0,
+        user_rsp: 0,
+    };
+}
+
+/// Per cpu syscall data, only ever accessed through GS or raw pointers
+static mut CPU_LOCAL: [CpuLocal; CPU_MAX_COUNT] = [CpuLocal::EMPTY; CPU_MAX_COUNT];
+
+// SYSCALL leaves the user RIP in RCX, RFLAGS in R11 and does not touch RSP, so the first thing
+// to do is to switch to the kernel stack. GS points to `CpuLocal` after `swapgs`.
+// The registers are saved as a `SyscallFrame`, the return value is written to its `rax` field.
+global_asm!(
+    r#"
+.section .text
+.global syscall_entry
+syscall_entry:
+    swapgs
+    mov gs:[8], rsp
+    mov rsp, gs:[0]
+    push qword ptr gs:[8]
+    push r11
+    push rcx
+    push rax
+    push rdi
+    push rsi
+    push rdx
+    push r10
+    push r8
+    push r9
+    mov rdi, rsp
+    call syscall_dispatch
+    pop r9
+    pop r8
+    pop r10
+    pop rdx
+    pop rsi
+    pop rdi
+    pop rax
+    // on Intel cpus SYSRET raises #GP in ring 0 on the user stack if RCX is not canonical
+    mov rcx, {user_limit}
+    cmp [rsp], rcx
+    jae 1f
+    pop rcx
+    pop r11
+    // back on the user stack, interrupts are still masked by SFMASK
+    pop rsp
+    swapgs
+    sysretq
+1:
+    // IRETQ checks the RIP while still on the kernel stack
+    pop rcx
+    pop r11
+    pop qword ptr gs:[8]
+    push {user_data}
+    push qword ptr gs:[8]
+    push r11
+    push {user_code}
+    push rcx
+    swapgs
+    iretq
+"#,
+    user_limit = const syscall::USER_ADDRESS_LIMIT,
+    user_data = const gdt::SELECTOR_USER_DATA64,
+    user_code = const gdt::SELECTOR_USER_CODE64,
+);
+
+extern "C" {
+    fn syscall_entry();
+}
+
+/// User state saved by `syscall_entry`, in the order it is found on the stack
+#[repr(C)]
+#[derive(Debug)]
+pub struct SyscallFrame {
+    pub r9: u64,
+    pub r8: u64,
+    pub r10: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rax: u64,
+    /// user RIP
+    pub rcx: u64,
+    /// user RFLAGS
+    pub r11: u64,
+    pub rsp: u64,
+}
+
+/// Rust entry point of `syscall_entry`, follows the linux calling convention:
+/// RAX holds the syscall number, RDI, RSI, RDX, R10, R8, R9 the arguments.
+#[no_mangle]
+extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) {
+    let args = [
+        frame.rdi as usize,
+        frame.rsi as usize,
+        frame.rdx as usize,
+        frame.r10 as usize,
+        frame.r8 as usize,
+        frame.r9 as usize,
+    ];
+    frame.rax = syscall::dispatch(frame.rax as usize, &args) as u64;
+}
+
+/// Set the stack used by syscalls & interrupts arriving from ring 3 on a cpu
+pub fn set_kernel_stack(cpu: usize, rsp: u64) {
+    gdt::set_kernel_stack(cpu, rsp);
+    // SAFETY: the entry code only reads this while in a syscall on the same cpu
+    unsafe { CPU_LOCAL[cpu].kernel_rsp = rsp };
+}
+
+/// Sets up the SYSCALL MSRs of a cpu, must be executed on the cpu itself after `gdt::init_cpu()`
+pub fn init_cpu(cpu: usize) {
+    set_kernel_stack(cpu, gdt::kernel_stack(cpu));
+    unsafe {
+        wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_SYSCALL_ENABLE);
+        wrmsr(
+            IA32_STAR,
+            (gdt::SELECTOR_USER_BASE as u64) << 48 | (gdt::SELECTOR_KERNEL_CODE64 as u64) << 32,
+        );
+        wrmsr(IA32_LSTAR, syscall_entry as u64);
+        wrmsr(IA32_FMASK, SYSCALL_FLAGS_MASK);
+        // the kernel runs with GS pointing to CpuLocal, `swapgs` exchanges it with the user GS
+        wrmsr(IA32_GS_BASE, &CPU_LOCAL[cpu] as *const CpuLocal as u64);
+        wrmsr(IA32_KERNEL_GSBASE, 0);
+    }
+}
+
+/// Sets up the SYSCALL MSRs of the boot cpu
+pub fn init() {
+    init_cpu(0);
+}
+
+/// Leave the kernel and start executing unprivileged code in ring 3
+///
+/// ## SAFETY: `entry` and `stack` must be mapped with user access
+pub unsafe fn enter_user(entry: u64, stack: u64) -> ! {
+    asm!(
+        "mov rsp, {stack}",
+        // nothing of the kernel may leak to ring 3, RCX & R11 are used by SYSRET
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        "swapgs",
+        "sysretq",
+        stack = in(reg) stack,
+        in("rcx") entry,
+        in("r11") USER_INITIAL_FLAGS,
+        options(noreturn),
+    );
+}
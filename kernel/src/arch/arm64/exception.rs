@@ -0,0 +1,235 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// installs the EL1 exception vector table and handles CPU exceptions
+// main source: Arm Architecture Reference Manual, D1.10 "Exception entry"
+
+use crate::backtrace;
+use crate::config::GDB_STUB;
+use crate::emergency;
+use crate::gdb;
+use core::arch::{asm, global_asm};
+
+// `FRAME_SIZE` in the assembly below, it keeps the stack 16 byte aligned
+const _: () = assert!(core::mem::size_of::<ExceptionFrame>() == 304);
+
+// exception classes of ESR_EL1.EC
+const CLASS_UNKNOWN: u64 = 0x00;
+const CLASS_INSTRUCTION_ABORT: u64 = 0x21;
+const CLASS_PC_ALIGNMENT: u64 = 0x22;
+const CLASS_DATA_ABORT: u64 = 0x25;
+const CLASS_SP_ALIGNMENT: u64 = 0x26;
+const CLASS_SOFTWARE_STEP: u64 = 0x33;
+const CLASS_BRK: u64 = 0x3C;
+
+/// (origin, type) of the 16 vectors, indexed by `ExceptionFrame::kind`
+const VECTOR_NAMES: [(&str, &str); 16] = [
+    ("EL1 SP_EL0", "Synchronous"),
+    ("EL1 SP_EL0", "IRQ"),
+    ("EL1 SP_EL0", "FIQ"),
+    ("EL1 SP_EL0", "SError"),
+    ("EL1 SP_EL1", "Synchronous"),
+    ("EL1 SP_EL1", "IRQ"),
+    ("EL1 SP_EL1", "FIQ"),
+    ("EL1 SP_EL1", "SError"),
+    ("EL0 AArch64", "Synchronous"),
+    ("EL0 AArch64", "IRQ"),
+    ("EL0 AArch64", "FIQ"),
+    ("EL0 AArch64", "SError"),
+    ("EL0 AArch32", "Synchronous"),
+    ("EL0 AArch32", "IRQ"),
+    ("EL0 AArch32", "FIQ"),
+    ("EL0 AArch32", "SError"),
+];
+
+// Every vector saves x0 & x1, then jumps to `exception_common` with its index in x0, which saves
+// the rest of the cpu state as `ExceptionFrame` and passes it to `exception_dispatch`.
+// The vectors taken while the kernel runs on SP_EL0 switch back to it, so the frame ends up on
+// the interrupted stack in every case.
+global_asm!(
+    r#"
+.equ FRAME_SIZE, 304
+
+.macro vector kind, spsel
+.balign 0x80
+    .if \spsel
+    msr spsel, #0
+    .endif
+    sub sp, sp, #FRAME_SIZE
+    stp x0, x1, [sp, #0]
+    mov x0, #\kind
+    b exception_common
+.endm
+
+.section .text
+.balign 0x800
+.global exception_vectors
+exception_vectors:
+vector 0, 1
+vector 1, 1
+vector 2, 1
+vector 3, 1
+.irp kind, 4,5,6,7,8,9,10,11,12,13,14,15
+vector \kind, 0
+.endr
+
+exception_common:
+    stp x2, x3, [sp, #16]
+    stp x4, x5, [sp, #32]
+    stp x6, x7, [sp, #48]
+    stp x8, x9, [sp, #64]
+    stp x10, x11, [sp, #80]
+    stp x12, x13, [sp, #96]
+    stp x14, x15, [sp, #112]
+    stp x16, x17, [sp, #128]
+    stp x18, x19, [sp, #144]
+    stp x20, x21, [sp, #160]
+    stp x22, x23, [sp, #176]
+    stp x24, x25, [sp, #192]
+    stp x26, x27, [sp, #208]
+    stp x28, x29, [sp, #224]
+    add x1, sp, #FRAME_SIZE
+    stp x30, x1, [sp, #240]
+    mrs x1, elr_el1
+    mrs x2, spsr_el1
+    stp x1, x2, [sp, #256]
+    mrs x1, esr_el1
+    mrs x2, far_el1
+    stp x1, x2, [sp, #272]
+    str x0, [sp, #288]
+    mov x0, sp
+    bl exception_dispatch
+    // the handler may have changed the return state
+    ldp x1, x2, [sp, #256]
+    msr elr_el1, x1
+    msr spsr_el1, x2
+    ldp x2, x3, [sp, #16]
+    ldp x4, x5, [sp, #32]
+    ldp x6, x7, [sp, #48]
+    ldp x8, x9, [sp, #64]
+    ldp x10, x11, [sp, #80]
+    ldp x12, x13, [sp, #96]
+    ldp x14, x15, [sp, #112]
+    ldp x16, x17, [sp, #128]
+    ldp x18, x19, [sp, #144]
+    ldp x20, x21, [sp, #160]
+    ldp x22, x23, [sp, #176]
+    ldp x24, x25, [sp, #192]
+    ldp x26, x27, [sp, #208]
+    ldp x28, x29, [sp, #224]
+    ldr x30, [sp, #240]
+    ldp x0, x1, [sp, #0]
+    add sp, sp, #FRAME_SIZE
+    // also restores SPSel
+    eret
+"#
+);
+
+extern "C" {
+    /// the vector table above
+    static exception_vectors: u8;
+}
+
+/// CPU state saved by `exception_common`, in the order it is found on the stack
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ExceptionFrame {
+    pub x: [u64; 31],
+    /// stack pointer of the interrupted code, changes are not restored
+    pub sp: u64,
+    pub elr: u64,
+    pub spsr: u64,
+    pub esr: u64,
+    pub far: u64,
+    /// index of the vector
+    pub kind: u64,
+    _padding: u64,
+}
+
+impl ExceptionFrame {
+    /// logs every saved register, with `emergency!()` as it is used by the exception handlers
+    pub fn dump(&self) {
+        for (i, registers) in self.x.chunks(3).enumerate() {
+            for (j, register) in registers.iter().enumerate() {
+                emergency!("X{:<2}={:016X} ", i * 3 + j, register);
+            }
+            emergency!("\n");
+        }
+        emergency!(
+            "SP ={:016X} ELR={:016X} SPSR={:08X}\n",
+            self.sp,
+            self.elr,
+            self.spsr
+        );
+    }
+
+    /// ESR_EL1.EC
+    pub fn class(&self) -> u64 {
+        (self.esr >> 26) & 0x3F
+    }
+}
+
+/// Common rust entry point of all exception vectors
+#[no_mangle]
+extern "C" fn exception_dispatch(frame: &mut ExceptionFrame) {
+    let class = frame.class();
+    // debug traps continue once gdb resumes
+    if GDB_STUB && (class == CLASS_BRK || class == CLASS_SOFTWARE_STEP) {
+        gdb::enter(frame, gdb::SIGTRAP);
+        return;
+    }
+
+    let (origin, typ) = VECTOR_NAMES[frame.kind as usize];
+    emergency!("\nCPU EXCEPTION!!!\n");
+    emergency!("Vector: {} {} ({})\n", frame.kind, typ, origin);
+    emergency!("Class: 0x{:02X} ({})\n", class, class_name(class));
+    emergency!("ESR: 0x{:016X}\n", frame.esr);
+    match backtrace::resolve(frame.elr as usize) {
+        Some(symbol) => emergency!("ELR: 0x{:016X} {}\n", frame.elr, symbol.name),
+        None => emergency!("ELR: 0x{:016X}\n", frame.elr),
+    }
+    emergency!("FAR: 0x{:016X}\n", frame.far);
+    frame.dump();
+
+    // inspect the faulting state before the panic
+    if GDB_STUB {
+        gdb::enter(frame, signal(class));
+    }
+    panic!("Unhandled CPU exception {} ({})", typ, class_name(class));
+}
+
+fn class_name(class: u64) -> &'static str {
+    match class {
+        CLASS_UNKNOWN => "Unknown Reason",
+        CLASS_INSTRUCTION_ABORT => "Instruction Abort",
+        CLASS_PC_ALIGNMENT => "PC Alignment Fault",
+        CLASS_DATA_ABORT => "Data Abort",
+        CLASS_SP_ALIGNMENT => "SP Alignment Fault",
+        CLASS_SOFTWARE_STEP => "Software Step",
+        CLASS_BRK => "BRK Instruction",
+        _ => "Other",
+    }
+}
+
+/// gdb signal of an exception class
+fn signal(class: u64) -> u8 {
+    match class {
+        CLASS_UNKNOWN => gdb::SIGILL,
+        CLASS_PC_ALIGNMENT | CLASS_SP_ALIGNMENT => gdb::SIGBUS,
+        _ => gdb::SIGSEGV,
+    }
+}
+
+pub fn init() {
+    // SAFETY: the table handles every exception the kernel can take
+    unsafe {
+        asm!(
+            "msr vbar_el1, {}",
+            "isb",
+            in(reg) &exception_vectors as *const u8,
+        );
+    }
+}
@@ -0,0 +1,137 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// arm64 part of the GDB stub, see gdb.rs
+// main source: gdb/features/aarch64-core.xml in the gdb sources
+
+use super::cpu::translate;
+use super::exception::ExceptionFrame;
+use super::serial::Pl011;
+use crate::gdb::{copy_memory, Connection, Target};
+use crate::limine;
+use core::arch::asm;
+
+/// MDSCR_EL1.SS, enables software steps
+const MDSCR_SOFTWARE_STEP: u64 = 1 << 0;
+
+/// MDSCR_EL1.KDE, enables debug exceptions at EL1
+const MDSCR_KERNEL_DEBUG: u64 = 1 << 13;
+
+/// SPSR_EL1.SS, the returned to instruction is stepped
+const SPSR_SOFTWARE_STEP: u64 = 1 << 21;
+
+/// SPSR_EL1.D, masks debug exceptions
+const SPSR_DEBUG_MASK: u64 = 1 << 9;
+
+/// 33 u64 registers (x0 - x30, sp, pc) & cpsr as u32
+const REGISTERS_SIZE: usize = 33 * 8 + 4;
+
+/// unlocks the debug registers, the OS lock is set after a cold reset
+pub fn init() {
+    // SAFETY: only affects debug exceptions
+    unsafe {
+        asm!("msr oslar_el1, xzr", "isb");
+    }
+}
+
+impl Connection for Pl011 {
+    fn get_byte(&mut self) -> u8 {
+        self.receive()
+    }
+
+    fn put_byte(&mut self, byte: u8) {
+        self.write_byte(byte)
+    }
+}
+
+impl Target for ExceptionFrame {
+    const REGISTERS_SIZE: usize = REGISTERS_SIZE;
+    /// brk #0
+    const BREAKPOINT: &'static [u8] = &[0x00, 0x00, 0x20, 0xD4];
+    /// ELR_EL1 points to the brk instruction
+    const BREAKPOINT_STOPS_AT: bool = true;
+
+    fn read_registers(&self, buf: &mut [u8]) {
+        let registers = self.x.iter().copied().chain([self.sp, self.elr]);
+        for (i, register) in registers.enumerate() {
+            buf[i * 8..i * 8 + 8].copy_from_slice(&register.to_le_bytes());
+        }
+        buf[33 * 8..].copy_from_slice(&(self.spsr as u32).to_le_bytes());
+    }
+
+    fn write_registers(&mut self, buf: &[u8]) {
+        let register = |i: usize| u64::from_le_bytes(buf[i * 8..i * 8 + 8].try_into().unwrap());
+        for (i, x) in self.x.iter_mut().enumerate() {
+            *x = register(i);
+        }
+        // the stack pointer is not restored by the vectors
+        self.elr = register(32);
+        let cpsr = u32::from_le_bytes(buf[33 * 8..33 * 8 + 4].try_into().unwrap());
+        // the mode & stack selection are kept, eret would end up at another level
+        self.spsr = (self.spsr & 0xF) | (cpsr as u64 & !0xF);
+    }
+
+    fn program_counter(&self) -> usize {
+        self.elr as usize
+    }
+
+    fn set_program_counter(&mut self, address: usize) {
+        self.elr = address as u64;
+    }
+
+    fn set_single_step(&mut self, enabled: bool) {
+        let mut mdscr: u64;
+        // SAFETY: MDSCR_EL1 is accessible at EL1
+        unsafe { asm!("mrs {}, mdscr_el1", out(reg) mdscr) };
+        if enabled {
+            mdscr |= MDSCR_SOFTWARE_STEP | MDSCR_KERNEL_DEBUG;
+            self.spsr |= SPSR_SOFTWARE_STEP;
+            self.spsr &= !SPSR_DEBUG_MASK;
+        } else {
+            mdscr &= !MDSCR_SOFTWARE_STEP;
+            self.spsr &= !SPSR_SOFTWARE_STEP;
+        }
+        unsafe { asm!("msr mdscr_el1, {}", "isb", in(reg) mdscr) };
+    }
+
+    fn read_memory(&self, address: usize, buf: &mut [u8]) -> bool {
+        copy_memory(address, buf.len(), hhdm_address, |virt, offset, count| {
+            // SAFETY: the page is mapped
+            let src = unsafe { core::slice::from_raw_parts(virt as *const u8, count) };
+            buf[offset..offset + count].copy_from_slice(src);
+        })
+    }
+
+    fn write_memory(&mut self, address: usize, data: &[u8]) -> bool {
+        let line = data_cache_line();
+        let written = copy_memory(address, data.len(), hhdm_address, |virt, offset, count| {
+            // SAFETY: the HHDM is writable, read-only pages like the kernel code included
+            let dst = unsafe { core::slice::from_raw_parts_mut(virt as *mut u8, count) };
+            dst.copy_from_slice(&data[offset..offset + count]);
+            // the written bytes may be code, clean every line they touch
+            let mut clean = virt & !(line - 1);
+            while clean < virt + count {
+                unsafe { asm!("dc cvau, {}", in(reg) clean) };
+                clean += line;
+            }
+        });
+        unsafe { asm!("dsb ish", "ic iallu", "dsb ish", "isb") };
+        written
+    }
+}
+
+/// Size of the smallest data cache line, CTR_EL0.DminLine is its log2 in words
+fn data_cache_line() -> usize {
+    let ctr: usize;
+    // SAFETY: CTR_EL0 is readable at EL1
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr) };
+    4 << ((ctr >> 16) & 0xF)
+}
+
+/// HHDM address of `virt`, translated by the mmu with the current tables
+fn hhdm_address(virt: usize) -> Option<usize> {
+    translate(virt).map(|phys| phys + limine::hhdm())
+}
@@ -6,9 +6,146 @@
 
 use super::ArchType;
 
+pub mod exception;
+pub mod gdb;
+pub mod serial;
+
 #[inline]
 pub const fn get_arch() -> ArchType {
     ArchType::AArch64
 }
 
-pub fn init() {}
+pub fn init() {
+    // load our vector table with the exception handlers
+    exception::init();
+}
+
+pub mod keyboard {
+    /// QEMU `virt` has no PS/2 keyboard, the debug monitor only reads the serial port
+    pub fn read_byte() -> Option<u8> {
+        None
+    }
+}
+
+pub mod cpu {
+    use core::arch::asm;
+
+    /// Waits for the next interrupt
+    pub unsafe fn halt() {
+        asm!("wfi");
+    }
+
+    /// true if IRQs are not masked in DAIF on this cpu
+    pub fn interrupts_enabled() -> bool {
+        let daif: u64;
+        unsafe { asm!("mrs {}, daif", out(reg) daif) };
+        daif & (1 << 7) == 0
+    }
+
+    // WARNING: Must run at EL1.
+    pub unsafe fn disable_interrupts() {
+        asm!("msr daifset, #2");
+    }
+
+    // WARNING: Must run at EL1.
+    pub unsafe fn enable_interrupts() {
+        asm!("msr daifclr, #2");
+    }
+
+    /// frame pointer of the calling function, see `backtrace.rs`
+    #[inline(always)]
+    pub fn frame_pointer() -> usize {
+        let x29: usize;
+        unsafe { asm!("mov {}, x29", out(reg) x29) };
+        x29
+    }
+
+    /// Raises a BRK exception, which enters the GDB stub when it is enabled
+    pub fn breakpoint() {
+        // SAFETY: brk only traps into the vector table
+        unsafe { asm!("brk #0") };
+    }
+
+    /// physical address of `virt`, translated by the mmu with the current tables
+    pub fn translate(virt: usize) -> Option<usize> {
+        let par: u64;
+        // SAFETY: address translation instructions do not fault
+        unsafe {
+            asm!(
+                "at s1e1r, {}",
+                "isb",
+                "mrs {}, par_el1",
+                in(reg) virt,
+                out(reg) par,
+            )
+        };
+        par_address(par, virt)
+    }
+
+    /// physical address of `virt` if EL0 may read it with the current tables
+    pub fn translate_user(virt: usize) -> Option<usize> {
+        let par: u64;
+        // SAFETY: address translation instructions do not fault
+        unsafe {
+            asm!(
+                "at s1e0r, {}",
+                "isb",
+                "mrs {}, par_el1",
+                in(reg) virt,
+                out(reg) par,
+            )
+        };
+        par_address(par, virt)
+    }
+
+    /// physical address of `virt` from the result of an `at` instruction
+    fn par_address(par: u64, virt: usize) -> Option<usize> {
+        // PAR_EL1.F
+        if par & 1 != 0 {
+            return None;
+        }
+        Some((par as usize & 0x0000_FFFF_FFFF_F000) | (virt & 0xFFF))
+    }
+
+    /// virtual counter of the generic timer, only use it to order & compare times
+    pub fn read_timestamp() -> u64 {
+        let ticks: u64;
+        // SAFETY: the virtual counter is readable at EL1
+        unsafe { asm!("mrs {}, cntvct_el0", out(reg) ticks) };
+        ticks
+    }
+
+    /// logs the system registers with `emergency!()`, used by the panic handler
+    pub fn dump_registers() {
+        let mut registers = [0u64; 8];
+        // SAFETY: all of them are readable at EL1
+        unsafe {
+            asm!("mrs {}, sctlr_el1", out(reg) registers[0]);
+            asm!("mrs {}, ttbr0_el1", out(reg) registers[1]);
+            asm!("mrs {}, ttbr1_el1", out(reg) registers[2]);
+            asm!("mrs {}, esr_el1", out(reg) registers[3]);
+            asm!("mrs {}, far_el1", out(reg) registers[4]);
+            asm!("mrs {}, elr_el1", out(reg) registers[5]);
+            asm!("mrs {}, daif", out(reg) registers[6]);
+            asm!("mov {}, sp", out(reg) registers[7]);
+        }
+        let [sctlr, ttbr0, ttbr1, esr, far, elr, daif, sp] = registers;
+        crate::emergency!(
+            "SCTLR={:016X} TTBR0={:016X} TTBR1={:016X}\n",
+            sctlr,
+            ttbr0,
+            ttbr1
+        );
+        crate::emergency!("ESR  ={:016X} FAR  ={:016X} ELR  ={:016X}\n", esr, far, elr);
+        crate::emergency!("DAIF ={:016X} SP   ={:016X}\n", daif, sp);
+    }
+
+    // WARNING: Must run at EL1.
+    /// Resets the machine with PSCI SYSTEM_RESET, QEMU `virt` uses the hvc conduit
+    pub unsafe fn reset() -> ! {
+        asm!("hvc #0", in("x0") 0x8400_0009u64);
+        loop {
+            asm!("wfi");
+        }
+    }
+}
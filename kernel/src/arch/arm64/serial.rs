@@ -0,0 +1,244 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Polling driver for the ARM PL011 UART, used as a log sink that does not depend on the
+//! bootloader. The physical base address is `config::SERIAL_PL011_BASE`, QEMU `virt` forwards it
+//! with `-serial stdio`.
+//!
+//! LIMITATION: the registers are accessed at `SERIAL_PL011_BASE + limine::hhdm()`. The kernel has
+//! no aarch64 page tables of its own yet, so the UART uses the normal cacheable mapping limine
+//! created for the HHDM instead of a Device-nGnRE one. QEMU TCG ignores memory attributes, on
+//! real hardware or with KVM writes may be merged, reordered or stay in the cache.
+//!
+//! See more about the registers: `https://developer.arm.com/documentation/ddi0183/latest/`
+
+use crate::config::{SERIAL_BAUD_RATE, SERIAL_PL011_BASE};
+use crate::limine;
+use crate::sync::IrqMutex;
+use core::fmt;
+use core::fmt::Write;
+use core::ptr;
+use lazy_static::lazy_static;
+
+/// reference clock of the UART on QEMU `virt`, the baud rate is set as a divisor of it
+const UART_CLOCK: u32 = 24_000_000;
+
+/// Max ammount of `flags()` polls before a byte is dropped, prevents a hang if the UART is stuck
+const TRANSMIT_TIMEOUT: usize = 100_000;
+
+// register offsets from the base address
+/// data register
+const DATA: usize = 0x00;
+/// flag register
+const FLAGS: usize = 0x18;
+/// integer baud rate divisor
+const INTEGER_BAUD: usize = 0x24;
+/// fractional baud rate divisor, in 1/64
+const FRACTIONAL_BAUD: usize = 0x28;
+/// line control register
+const LINE_CONTROL: usize = 0x2C;
+/// control register
+const CONTROL: usize = 0x30;
+/// interrupt mask set/clear register
+const INTERRUPT_MASK: usize = 0x38;
+/// interrupt clear register
+const INTERRUPT_CLEAR: usize = 0x44;
+/// first of the 4 PrimeCell identification registers, used to detect the UART
+const CELL_ID: usize = 0xFF0;
+
+/// expected values of the PrimeCell identification registers
+const CELL_ID_VALUE: [u32; 4] = [0x0D, 0xF0, 0x05, 0xB1];
+
+// flag bits
+/// the UART is transmitting
+const FLAG_BUSY: u32 = 1 << 3;
+/// the receive FIFO is empty
+const FLAG_RECEIVE_EMPTY: u32 = 1 << 4;
+/// the transmit FIFO is full
+const FLAG_TRANSMIT_FULL: u32 = 1 << 5;
+
+// line control bits
+/// FIFOs enabled
+const LINE_FIFO: u32 = 1 << 4;
+/// 8 data bits
+const LINE_WORD_8: u32 = 0b11 << 5;
+
+// control bits
+const CONTROL_ENABLE: u32 = 1 << 0;
+const CONTROL_TRANSMIT: u32 = 1 << 8;
+const CONTROL_RECEIVE: u32 = 1 << 9;
+
+lazy_static! {
+    /// PL011 at `config::SERIAL_PL011_BASE`, initialised on first use so it is available before
+    /// `arch::init()`
+    pub static ref SERIAL0: IrqMutex<Pl011> =
+        IrqMutex::new(unsafe { Pl011::new(SERIAL_PL011_BASE + limine::hhdm(), SERIAL_BAUD_RATE) });
+}
+
+/// public interface to print to `SERIAL0`, line feeds are expanded to CR LF
+pub fn print_bytes(s: &[u8]) {
+    SERIAL0.lock().write_bytes(s);
+}
+
+/// Unlocked handle to the PL011 for the panic & exception handlers, which can not wait for
+/// `SERIAL0`. Its output may interleave with the holder of the lock.
+pub fn emergency_writer() -> Pl011 {
+    Pl011 {
+        base: SERIAL_PL011_BASE + limine::hhdm(),
+        present: true,
+    }
+}
+
+/// Errors while setting up a `Pl011`
+///
+/// ## Variants:
+/// - `Missing` the identification registers do not belong to a PrimeCell UART
+/// - `InvalidBaudRate` the baud rate is 0 or too high for `UART_CLOCK`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SerialError {
+    Missing,
+    InvalidBaudRate(u32),
+}
+
+/// PL011 UART at a virtual address
+pub struct Pl011 {
+    base: usize,
+    /// false if the UART is missing, writes are dropped
+    present: bool,
+}
+
+impl Pl011 {
+    /// Sets up the UART at `base` with 8 data bits, no parity, 1 stop bit & enabled FIFOs.
+    /// A missing UART or an invalid baud rate is logged and leaves a `Pl011` that drops all
+    /// writes.
+    ///
+    /// # Safety
+    /// `base` must be the mapped register block of a PL011 UART.
+    pub unsafe fn new(base: usize, baud_rate: u32) -> Self {
+        let mut uart = Self {
+            base,
+            present: false,
+        };
+        // can not use `log!()` as the UART may be a log sink itself
+        if let Err(e) = uart.init(baud_rate) {
+            limine::print_bytes(b"[WARNING] Serial port could not be initialised: ");
+            limine::print_bytes(match e {
+                SerialError::Missing => b"Missing\n",
+                SerialError::InvalidBaudRate(_) => b"InvalidBaudRate\n",
+            });
+        }
+        uart
+    }
+
+    /// (Re)initialises the UART
+    ///
+    /// # Safety
+    /// `self.base` must be the mapped register block of a PL011 UART.
+    pub unsafe fn init(&mut self, baud_rate: u32) -> Result<(), SerialError> {
+        // the integer divisor is 16 bits & must not be 0
+        let divisor = match baud_rate {
+            0 => return Err(SerialError::InvalidBaudRate(baud_rate)),
+            _ => (UART_CLOCK as u64 * 4) / baud_rate as u64,
+        };
+        if divisor >> 6 == 0 || divisor >> 6 > 0xFFFF {
+            return Err(SerialError::InvalidBaudRate(baud_rate));
+        }
+        for (i, value) in CELL_ID_VALUE.iter().enumerate() {
+            if self.input(CELL_ID + i * 4) & 0xFF != *value {
+                self.present = false;
+                return Err(SerialError::Missing);
+            }
+        }
+
+        // disable the UART & finish the current transmission before changing the setup
+        self.output(CONTROL, 0);
+        while self.flags() & FLAG_BUSY != 0 {
+            core::hint::spin_loop();
+        }
+        // flush the transmit FIFO
+        self.output(LINE_CONTROL, 0);
+
+        // divisor = UART_CLOCK / (16 * baud_rate), the lower 6 bits are the fraction
+        self.output(INTEGER_BAUD, (divisor >> 6) as u32);
+        self.output(FRACTIONAL_BAUD, (divisor & 0x3F) as u32);
+        // 8 bits, no parity, one stop bit, written after the divisors to latch them
+        self.output(LINE_CONTROL, LINE_WORD_8 | LINE_FIFO);
+        // no interrupts, polling only
+        self.output(INTERRUPT_MASK, 0);
+        self.output(INTERRUPT_CLEAR, 0x7FF);
+        self.output(CONTROL, CONTROL_ENABLE | CONTROL_TRANSMIT | CONTROL_RECEIVE);
+        self.present = true;
+        Ok(())
+    }
+
+    /// true if a UART was detected at the address
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    /// Blocks until the transmit FIFO has space & writes `byte`, dropped if the UART is missing
+    /// or does not become ready
+    pub fn write_byte(&mut self, byte: u8) {
+        if !self.present {
+            return;
+        }
+        for _ in 0..TRANSMIT_TIMEOUT {
+            if self.flags() & FLAG_TRANSMIT_FULL == 0 {
+                unsafe { self.output(DATA, byte as u32) };
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Writes all bytes, line feeds are expanded to CR LF
+    pub fn write_bytes(&mut self, s: &[u8]) {
+        for &byte in s {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+    }
+
+    /// Returns a received byte, does not block
+    pub fn read_byte(&mut self) -> Option<u8> {
+        if !self.present || self.flags() & FLAG_RECEIVE_EMPTY != 0 {
+            return None;
+        }
+        // the upper bits hold the error flags of the byte
+        Some(unsafe { self.input(DATA) } as u8)
+    }
+
+    /// Blocks until a byte is received, never returns if the UART is missing
+    pub fn receive(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.read_byte() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn flags(&self) -> u32 {
+        unsafe { self.input(FLAGS) }
+    }
+
+    unsafe fn output(&self, register: usize, value: u32) {
+        ptr::write_volatile((self.base + register) as *mut u32, value)
+    }
+
+    unsafe fn input(&self, register: usize) -> u32 {
+        ptr::read_volatile((self.base + register) as *const u32)
+    }
+}
+
+impl Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
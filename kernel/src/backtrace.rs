@@ -0,0 +1,206 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Stack walking & symbol lookup for the panic and exception handlers.
+//!
+//! Every function keeps a frame pointer (`"frame-pointer": "always"` in `triple/`), the frame
+//! record it points to holds the previous frame pointer followed by the return address on both
+//! x86_64 (rbp) and aarch64 (x29).
+//!
+//! The symbol table is a reserved `.ksymtab` section that `scripts/build/ksymtab.sh` fills after linking
+//! with one `ADDRESS SIZE NAME` line per function, addresses & sizes in hex, sorted by address.
+//! Kernels that were not post processed (e.g. the test binary) print plain addresses.
+
+#[cfg(target_os = "none")]
+use crate::config::SYMBOL_TABLE_SIZE;
+use crate::emergency;
+
+/// Max ammount of frames printed by `print()`
+const BACKTRACE_MAX_DEPTH: usize = 32;
+
+/// Reserves the symbol table in the image, it is read through the linker script symbols as the
+/// compiler would assume it holds only zeros
+#[cfg(target_os = "none")]
+#[used]
+#[link_section = ".ksymtab"]
+static SYMBOL_TABLE: [u8; SYMBOL_TABLE_SIZE] = [0; SYMBOL_TABLE_SIZE];
+
+#[cfg(target_os = "none")]
+extern "C" {
+    static __kernel_symtab_start: u8;
+    static __kernel_symtab_end: u8;
+}
+
+/// A function of the kernel image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub address: usize,
+    /// 0 if unknown
+    pub size: usize,
+}
+
+/// Returns the function that contains `address`
+pub fn resolve(address: usize) -> Option<Symbol> {
+    lookup(table(), address)
+}
+
+/// Prints the return addresses of the calling stack with `emergency!()`
+#[inline(always)]
+pub fn print() {
+    print_from(crate::arch::cpu::frame_pointer());
+}
+
+/// Prints the return addresses of the stack that starts at the frame record `frame_pointer`
+pub fn print_from(frame_pointer: usize) {
+    emergency!("Backtrace:\n");
+    let mut count = 0;
+    walk(frame_pointer, |address| {
+        // the return address points after the call, it may already be the next function
+        match resolve(address - 1) {
+            Some(symbol) => emergency!(
+                "{}: 0x{:016X} {}+0x{:X}\n",
+                count,
+                address,
+                symbol.name,
+                address - symbol.address
+            ),
+            None => emergency!("{}: 0x{:016X} ??\n", count, address),
+        }
+        count += 1;
+    });
+}
+
+/// Calls `f` with the return address of every frame record in the chain that starts at
+/// `frame_pointer`, stops at a null or invalid frame pointer
+pub fn walk(mut frame_pointer: usize, mut f: impl FnMut(usize)) {
+    for _ in 0..BACKTRACE_MAX_DEPTH {
+        if !valid_frame(frame_pointer) {
+            return;
+        }
+        // SAFETY: checked by `valid_frame()`, a corrupted stack may still fault
+        let (next, address) = unsafe {
+            let record = frame_pointer as *const usize;
+            (*record, *record.add(1))
+        };
+        if address == 0 {
+            return;
+        }
+        f(address);
+        // the stack grows down, callers have higher frames
+        if next <= frame_pointer {
+            return;
+        }
+        frame_pointer = next;
+    }
+}
+
+/// frame records are aligned & only found in the kernel half
+fn valid_frame(frame_pointer: usize) -> bool {
+    frame_pointer != 0
+        && frame_pointer % core::mem::size_of::<usize>() == 0
+        && frame_pointer >= 0xFFFF_8000_0000_0000
+}
+
+/// filled part of the symbol table
+#[cfg(target_os = "none")]
+fn table() -> &'static [u8] {
+    // SAFETY: the linker script places both symbols around `SYMBOL_TABLE`
+    let table = unsafe {
+        let start = &__kernel_symtab_start as *const u8;
+        let end = &__kernel_symtab_end as *const u8;
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    };
+    let length = table.iter().position(|&b| b == 0).unwrap_or(table.len());
+    &table[..length]
+}
+
+/// host unit tests have no kernel image
+#[cfg(not(target_os = "none"))]
+fn table() -> &'static [u8] {
+    &[]
+}
+
+/// searches `table` for the last symbol at or below `address`
+fn lookup(table: &'static [u8], address: usize) -> Option<Symbol> {
+    let mut found = None;
+    for line in table.split(|&b| b == b'\n') {
+        let symbol = match parse(line) {
+            Some(symbol) => symbol,
+            None => continue,
+        };
+        // sorted by address
+        if symbol.address > address {
+            break;
+        }
+        found = Some(symbol);
+    }
+    found.filter(|s| s.size == 0 || address < s.address + s.size)
+}
+
+/// parses a single `ADDRESS SIZE NAME` line
+fn parse(line: &'static [u8]) -> Option<Symbol> {
+    let line = core::str::from_utf8(line).ok()?;
+    let mut fields = line.splitn(3, ' ');
+    let address = usize::from_str_radix(fields.next()?, 16).ok()?;
+    let size = usize::from_str_radix(fields.next()?, 16).ok()?;
+    let name = fields.next()?;
+    Some(Symbol {
+        name,
+        address,
+        size,
+    })
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+
+    const TABLE: &[u8] = b"ffffffff80000000 10 _start\n\
+        ffffffff80000010 0 kmain\n\
+        ffffffff80000100 20 kernel::log::print\n\
+        ffffffff80000200 8 <kernel::log::SerialSink as kernel::log::Sink>::write\n";
+
+    #[test]
+    fn lookup_symbols() {
+        let start = lookup(TABLE, 0xFFFF_FFFF_8000_0004).unwrap();
+        assert_eq!(start.name, "_start");
+        assert_eq!(start.size, 0x10);
+        // unknown size reaches to the next symbol
+        assert_eq!(lookup(TABLE, 0xFFFF_FFFF_8000_00FF).unwrap().name, "kmain");
+        assert_eq!(
+            lookup(TABLE, 0xFFFF_FFFF_8000_011F).unwrap().name,
+            "kernel::log::print"
+        );
+        // names may contain spaces
+        assert_eq!(
+            lookup(TABLE, 0xFFFF_FFFF_8000_0200).unwrap().name,
+            "<kernel::log::SerialSink as kernel::log::Sink>::write"
+        );
+    }
+
+    #[test]
+    fn lookup_misses() {
+        assert!(lookup(TABLE, 0xFFFF_FFFF_7FFF_FFFF).is_none());
+        // behind a sized symbol
+        assert!(lookup(TABLE, 0xFFFF_FFFF_8000_0120).is_none());
+        assert!(lookup(TABLE, 0xFFFF_FFFF_8000_0208).is_none());
+        assert!(lookup(&[], 0xFFFF_FFFF_8000_0000).is_none());
+        assert!(lookup(b"garbage\n", 0xFFFF_FFFF_8000_0000).is_none());
+    }
+
+    #[test]
+    fn frame_validation() {
+        assert!(!valid_frame(0));
+        assert!(!valid_frame(0xFFFF_8000_0000_0001));
+        assert!(!valid_frame(0x1000));
+        assert!(valid_frame(0xFFFF_8000_0000_0010));
+        let mut count = 0;
+        walk(0, |_| count += 1);
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,366 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Crash reports that survive a reboot.
+//!
+//! A reserved piece of RAM (`CRASH_DUMP_ADDRESS` in `config/`) is registered as log sink that only
+//! receives the `emergency!()` output of the exception & panic handlers: registers, panic message
+//! & backtrace. The panic handler then adds the newest log records from before the crash & seals
+//! the report with a checksum. QEMU & most firmware keep the RAM content across a warm reset, so
+//! the next boot finds the report & prints it once.
+
+use crate::config::{CRASH_DUMP_ADDRESS, CRASH_DUMP_SIZE};
+use crate::limine;
+use crate::log;
+use crate::log::{LevelFilter, Sink, SinkError};
+use crate::memman::map::{self, MapArea, MemoryMapperError};
+use crate::sync::IrqMutex;
+use core::fmt::{self, Write};
+
+/// First bytes of a sealed report
+const DUMP_MAGIC: u64 = u64::from_le_bytes(*b"RKCRASH1");
+
+/// Bytes of the area header: magic, checksum, length, boot time, timestamp & reported (u64 each)
+const HEADER_SIZE: usize = 48;
+
+/// The reserved area, `None` until `init()` succeeded
+static DUMP: IrqMutex<Option<CrashDump>> = IrqMutex::new(None);
+
+/// Error returned by `init()`
+///
+/// ## Variants:
+/// - `NotUsable` : The area is not completely inside usable RAM, contains the area region
+/// - `Claim` : The area could not be claimed in the global map, contains the cause
+/// - `Sink` : The log sink could not be registered, contains the cause
+#[derive(Debug)]
+pub enum CrashDumpError {
+    NotUsable((usize, usize)), // contains the area region
+    Claim(MemoryMapperError),
+    Sink(SinkError),
+}
+
+/// Metadata of a crash report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Report {
+    /// `limine::boot_time_stamp()` of the boot that crashed, 0 if it is unknown
+    pub boot_time: i64,
+    /// `arch::cpu::read_timestamp()` when the report was sealed, compare it to the log records
+    pub timestamp: u64,
+    /// length of the text in bytes
+    pub length: usize,
+}
+
+/// Claims the area, prints the report of the previous boot & starts catching `emergency!()`.
+///
+/// WARNING: must be called before anything else claims usable memory, e.g. the frame allocator
+pub fn init() -> Result<(), CrashDumpError> {
+    let region = (CRASH_DUMP_ADDRESS, CRASH_DUMP_ADDRESS + CRASH_DUMP_SIZE);
+    // bootloader memory gets reclaimed & reused, only plain RAM survives
+    let usable = limine::memory_map().any(|r| {
+        matches!(r.typ, limine::MemmapEntryType::Usable)
+            && r.range.0 <= region.0
+            && region.1 <= r.range.1
+    });
+    if !usable {
+        return Err(CrashDumpError::NotUsable(region));
+    }
+    let area = map::claim_global(region).map_err(CrashDumpError::Claim)?;
+
+    let mut dump = CrashDump::new(area);
+    if let Some(report) = dump.previous {
+        log!("[ Previous Crash ]\n");
+        log!("boot time: {}\n", report.boot_time);
+        log!("{}", dump.text());
+        dump.set_reported();
+    }
+    *DUMP.lock() = Some(dump);
+
+    // `write()` is never used, `LevelFilter::Off` keeps the sink out of the leveled macros
+    log::add_sink("crashdump", &CrashDumpSink, LevelFilter::Off).map_err(CrashDumpError::Sink)
+}
+
+/// The report found at boot, it stays readable until the next crash overwrites it
+pub fn previous() -> Option<Report> {
+    DUMP.lock().as_ref()?.previous
+}
+
+/// Writes the text of the report found at boot to `sink`, returns false if there is none
+pub fn print_previous(sink: &dyn Sink) -> bool {
+    match DUMP.lock().as_ref() {
+        Some(dump) if dump.previous.is_some() => {
+            sink.write(dump.text());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Completes the report started by the first `emergency!()` output with the newest log records
+/// from before it & seals it. Used by the panic handler, it never waits for a lock.
+pub fn finish() {
+    let mut guard = match DUMP.try_lock() {
+        Some(guard) => guard,
+        None => return,
+    };
+    let dump = match guard.as_mut() {
+        Some(dump) if dump.open => dump,
+        _ => return,
+    };
+    if let Some(end) = dump.log_end {
+        dump.write_str("\nLast log records:\n").ok();
+        let space = dump.capacity() - dump.length;
+        log::emergency_tail(end, space, dump);
+    }
+    dump.seal();
+}
+
+/// Catches the `emergency!()` output, see the module docs
+struct CrashDumpSink;
+
+impl Sink for CrashDumpSink {
+    fn write(&self, _s: &str) {}
+
+    fn write_emergency(&self, s: &str) {
+        if let Some(mut guard) = DUMP.try_lock() {
+            if let Some(dump) = guard.as_mut() {
+                if !dump.open {
+                    // the records from here on are part of the report
+                    dump.begin(log::try_stats().map(|stats| stats.next_sequence));
+                }
+                dump.write_str(s).ok();
+            }
+        }
+    }
+}
+
+/// decoded area header, all fields are stored as little endian u64
+#[derive(Debug, Default, Clone, Copy)]
+struct Header {
+    magic: u64,
+    /// `checksum()` of the text
+    checksum: u64,
+    length: u64,
+    boot_time: i64,
+    timestamp: u64,
+    /// not 0 once a later boot printed the report
+    reported: u64,
+}
+
+/// The area: a header followed by the report text
+struct CrashDump {
+    area: MapArea,
+    /// unreported report found at boot
+    previous: Option<Report>,
+    /// a report is being written
+    open: bool,
+    /// bytes of text written to the open report
+    length: usize,
+    /// log records before this sequence number are older than the report, `None` if the log
+    /// was locked when the report started
+    log_end: Option<u64>,
+}
+
+impl CrashDump {
+    fn new(area: MapArea) -> Self {
+        let mut dump = Self {
+            area,
+            previous: None,
+            open: false,
+            length: 0,
+            log_end: None,
+        };
+        dump.previous = dump.check();
+        dump
+    }
+
+    /// Returns the sealed & unreported report in the area
+    fn check(&self) -> Option<Report> {
+        let header = self.header();
+        let length = header.length as usize;
+        if header.magic != DUMP_MAGIC || header.reported != 0 || length > self.capacity() {
+            return None;
+        }
+        let text = &self.area.as_bytes()[HEADER_SIZE..HEADER_SIZE + length];
+        if checksum(text) != header.checksum {
+            return None;
+        }
+        Some(Report {
+            boot_time: header.boot_time,
+            timestamp: header.timestamp,
+            length,
+        })
+    }
+
+    /// text of the report found at boot, cut at the first invalid character
+    fn text(&self) -> &str {
+        let length = self.previous.map_or(0, |report| report.length);
+        let bytes = &self.area.as_bytes()[HEADER_SIZE..HEADER_SIZE + length];
+        match core::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+        }
+    }
+
+    fn set_reported(&mut self) {
+        let mut header = self.header();
+        header.reported = 1;
+        self.write_header(header);
+    }
+
+    /// Starts a new report, the previous one is invalid from here on
+    fn begin(&mut self, log_end: Option<u64>) {
+        self.write_header(Header::default());
+        self.previous = None;
+        self.open = true;
+        self.length = 0;
+        self.log_end = log_end;
+    }
+
+    /// Writes the header that makes the open report valid
+    fn seal(&mut self) {
+        let text = &self.area.as_bytes()[HEADER_SIZE..HEADER_SIZE + self.length];
+        let header = Header {
+            magic: DUMP_MAGIC,
+            checksum: checksum(text),
+            length: self.length as u64,
+            boot_time: boot_time(),
+            timestamp: crate::arch::cpu::read_timestamp(),
+            reported: 0,
+        };
+        self.write_header(header);
+        self.open = false;
+    }
+
+    /// Max ammount of text bytes
+    fn capacity(&self) -> usize {
+        self.area.size() - HEADER_SIZE
+    }
+
+    fn header(&self) -> Header {
+        let raw = &self.area.as_bytes()[..HEADER_SIZE];
+        let field = |i: usize| u64::from_le_bytes(raw[i * 8..i * 8 + 8].try_into().unwrap());
+        Header {
+            magic: field(0),
+            checksum: field(1),
+            length: field(2),
+            boot_time: field(3) as i64,
+            timestamp: field(4),
+            reported: field(5),
+        }
+    }
+
+    fn write_header(&mut self, header: Header) {
+        let fields = [
+            header.magic,
+            header.checksum,
+            header.length,
+            header.boot_time as u64,
+            header.timestamp,
+            header.reported,
+        ];
+        let raw = &mut self.area.as_bytes_mut()[..HEADER_SIZE];
+        for (i, field) in fields.iter().enumerate() {
+            raw[i * 8..i * 8 + 8].copy_from_slice(&field.to_le_bytes());
+        }
+    }
+}
+
+impl Write for CrashDump {
+    /// appends to the open report, text that does not fit is dropped
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(self.capacity() - self.length);
+        let start = HEADER_SIZE + self.length;
+        self.area.as_bytes_mut()[start..start + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.length += count;
+        Ok(())
+    }
+}
+
+/// 64 bit FNV-1a, detects reports that were partially overwritten by the firmware
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+#[cfg(target_os = "none")]
+fn boot_time() -> i64 {
+    // 0 marks an unknown time
+    limine::boot_time_stamp().unwrap_or(0)
+}
+
+/// host unit tests are not booted by limine
+#[cfg(not(target_os = "none"))]
+fn boot_time() -> i64 {
+    0
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+    use std::vec;
+
+    /// the host has no physical memory map, the area is a leaked heap buffer
+    fn area(size: usize) -> MapArea {
+        let buffer = vec![0u8; size].leak();
+        let start = buffer.as_ptr() as usize;
+        // SAFETY: the buffer is never freed & only owned by this area
+        unsafe { MapArea::from_raw((start, start + size)) }
+    }
+
+    /// recreates the dump like a reboot does
+    fn reboot(dump: CrashDump) -> CrashDump {
+        CrashDump::new(unsafe { MapArea::from_raw(dump.area.into_raw()) })
+    }
+
+    #[test]
+    fn report_survives_reboot() {
+        let mut dump = CrashDump::new(area(0x1000));
+        assert!(dump.previous.is_none());
+        dump.begin(None);
+        write!(dump, "KERNEL PANIC {}\n", 42).unwrap();
+        // unsealed reports are not valid
+        assert!(dump.check().is_none());
+        dump.seal();
+
+        let mut dump = reboot(dump);
+        let report = dump.previous.unwrap();
+        assert_eq!(report.length, 16);
+        assert_eq!(dump.text(), "KERNEL PANIC 42\n");
+
+        // printed once
+        dump.set_reported();
+        let dump = reboot(dump);
+        assert!(dump.previous.is_none());
+        dump.area.into_raw();
+    }
+
+    #[test]
+    fn corrupted_reports_are_dropped() {
+        let mut dump = CrashDump::new(area(0x1000));
+        dump.begin(None);
+        dump.write_str("overwritten by the firmware\n").unwrap();
+        dump.seal();
+        dump.area.as_bytes_mut()[HEADER_SIZE + 3] ^= 0xFF;
+        let dump = reboot(dump);
+        assert!(dump.previous.is_none());
+        assert_eq!(dump.text(), "");
+        dump.area.into_raw();
+    }
+
+    #[test]
+    fn long_reports_are_cut() {
+        let mut dump = CrashDump::new(area(HEADER_SIZE + 10));
+        dump.begin(None);
+        dump.write_str("0123456").unwrap();
+        dump.write_str("789abc").unwrap();
+        dump.seal();
+        let dump = reboot(dump);
+        assert_eq!(dump.text(), "0123456789");
+        dump.area.into_raw();
+    }
+}
@@ -0,0 +1,835 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! GDB Remote Serial Protocol stub on the serial port.
+//! <br> main source: https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+//!
+//! With `GDB_STUB` set in `config/` the exception handlers stop in the stub instead of panicking,
+//! breakpoint & single step traps resume once gdb continues. A kernel panic stops in the stub with
+//! `breakpoint()`. Attach with `target remote` to the serial port of the VM, e.g.
+//! `QEMU_ARGS="-serial tcp::1234,server"` & `target remote :1234`.
+//!
+//! Supported: registers (`g`, `G`), memory (`m`, `M`), software breakpoints (`Z0`, `z0`),
+//! `c`, `s`, `?` & `D`. The architecture specific parts are behind `Target`.
+
+use crate::emergency;
+use crate::sync::IrqMutex;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Max ammount of bytes in a packet, announced to gdb in `qSupported`
+const PACKET_SIZE: usize = 0x1000;
+
+/// Max ammount of software breakpoints set at the same time
+const BREAKPOINT_CAPACITY: usize = 32;
+
+/// Max ammount of bytes of a breakpoint instruction
+const BREAKPOINT_MAX_LENGTH: usize = 4;
+
+/// Max ammount of bytes in the `g` packet of any `Target`
+const REGISTERS_MAX_SIZE: usize = 512;
+
+/// Bytes of memory copied at once by `m` & `M`
+const MEMORY_CHUNK: usize = 64;
+
+/// Page size of the translations checked by `copy_memory()`
+const PAGE_SIZE: usize = 4096;
+
+// signal numbers reported to gdb, as used by gdb on every host
+pub const SIGILL: u8 = 4;
+pub const SIGTRAP: u8 = 5;
+pub const SIGBUS: u8 = 7;
+pub const SIGFPE: u8 = 8;
+pub const SIGSEGV: u8 = 11;
+
+/// errno sent in the error replies, gdb only shows the number
+const EFAULT: u8 = 14;
+const ENOSPC: u8 = 28;
+
+/// The stub of the kernel, it holds large buffers & is only entered from exception handlers
+static STUB: IrqMutex<Stub> = IrqMutex::new(Stub::new());
+
+/// Set by `init()`, breakpoints before it would not reach an exception handler
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Byte stream to gdb, e.g. a serial port
+pub trait Connection {
+    /// Waits for the next byte
+    fn get_byte(&mut self) -> u8;
+
+    fn put_byte(&mut self, byte: u8);
+}
+
+/// Architecture specific state of a stopped cpu, implemented by the exception frames
+pub trait Target {
+    /// bytes of the `g` packet, the register layout gdb uses for the architecture
+    const REGISTERS_SIZE: usize;
+    /// the software breakpoint instruction
+    const BREAKPOINT: &'static [u8];
+    /// true if the program counter of a hit breakpoint points to it, not behind it
+    const BREAKPOINT_STOPS_AT: bool;
+
+    /// Writes the registers in the `g` packet layout to `buf`
+    fn read_registers(&self, buf: &mut [u8]);
+
+    /// Loads the registers from `buf` in the `g` packet layout
+    fn write_registers(&mut self, buf: &[u8]);
+
+    fn program_counter(&self) -> usize;
+
+    fn set_program_counter(&mut self, address: usize);
+
+    /// Makes the cpu trap again after a single instruction once resumed
+    fn set_single_step(&mut self, enabled: bool);
+
+    /// Copies the memory at `address`, false if any of it is not mapped
+    fn read_memory(&self, address: usize, buf: &mut [u8]) -> bool;
+
+    /// Writes `data` to `address`, code included. False if any of it is not mapped
+    fn write_memory(&mut self, address: usize, data: &[u8]) -> bool;
+}
+
+/// Prepares the cpu for debugging & lets `breakpoint()` enter the stub, call it once the
+/// exception handlers are installed
+pub fn init() {
+    crate::arch::gdb::init();
+    READY.store(true, Ordering::SeqCst);
+}
+
+/// Stops in the stub through a breakpoint trap, used by the panic handler. Does nothing before
+/// `init()`.
+pub fn breakpoint() {
+    if READY.load(Ordering::SeqCst) {
+        crate::arch::cpu::breakpoint();
+    }
+}
+
+/// Talks to gdb on the serial port until it continues, `signal` tells gdb why the cpu stopped
+pub fn enter<T: Target>(target: &mut T, signal: u8) {
+    // a fault inside the stub itself
+    let mut stub = match STUB.try_lock() {
+        Some(stub) => stub,
+        None => return,
+    };
+    if !stub.running {
+        emergency!("Waiting for gdb on the serial port...\n");
+    }
+    let mut connection = crate::arch::serial::emergency_writer();
+    stub.run(&mut connection, target, signal);
+}
+
+/// What the stub does after a packet
+enum Action {
+    /// sends the reply, of the given length
+    Reply(usize),
+    /// returns to the stopped code, gdb waits for the next stop
+    Resume { step: bool },
+    /// returns to the stopped code without gdb, the reply is sent if there is one
+    Detach(Option<usize>),
+}
+
+/// A software breakpoint & the bytes it replaced
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    address: usize,
+    original: [u8; BREAKPOINT_MAX_LENGTH],
+}
+
+/// Protocol state, kept across stops
+pub struct Stub {
+    packet: [u8; PACKET_SIZE],
+    reply: [u8; PACKET_SIZE],
+    breakpoints: [Option<Breakpoint>; BREAKPOINT_CAPACITY],
+    /// gdb resumed the target & waits for a stop reply
+    running: bool,
+}
+
+impl Stub {
+    pub const fn new() -> Self {
+        Self {
+            packet: [0; PACKET_SIZE],
+            reply: [0; PACKET_SIZE],
+            breakpoints: [None; BREAKPOINT_CAPACITY],
+            running: false,
+        }
+    }
+
+    /// Handles packets until gdb resumes `target`
+    pub fn run<C: Connection, T: Target>(
+        &mut self,
+        connection: &mut C,
+        target: &mut T,
+        signal: u8,
+    ) {
+        target.set_single_step(false);
+        if self.running {
+            let length = stop_reply(&mut self.reply, signal);
+            send(connection, &self.reply[..length]);
+        }
+        self.running = false;
+
+        loop {
+            let length = receive(connection, &mut self.packet);
+            let action = handle(
+                &self.packet[..length],
+                &mut self.reply,
+                &mut self.breakpoints,
+                target,
+                signal,
+            );
+            match action {
+                Action::Reply(length) => send(connection, &self.reply[..length]),
+                Action::Resume { step } => {
+                    skip_embedded_breakpoint(&self.breakpoints, target);
+                    target.set_single_step(step);
+                    self.running = true;
+                    return;
+                }
+                Action::Detach(reply) => {
+                    if let Some(length) = reply {
+                        send(connection, &self.reply[..length]);
+                    }
+                    skip_embedded_breakpoint(&self.breakpoints, target);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// ========== Packets
+
+/// Waits for a packet with a valid checksum, acknowledges it & returns its length
+fn receive<C: Connection>(connection: &mut C, packet: &mut [u8]) -> usize {
+    loop {
+        // anything outside of packets (acks, Ctrl-C) is ignored
+        while connection.get_byte() != b'$' {}
+
+        let mut length = 0;
+        let mut sum: u8 = 0;
+        let mut overflow = false;
+        loop {
+            let byte = connection.get_byte();
+            match byte {
+                b'#' => break,
+                // gdb started over
+                b'$' => {
+                    length = 0;
+                    sum = 0;
+                    overflow = false;
+                }
+                _ => {
+                    match packet.get_mut(length) {
+                        Some(slot) => *slot = byte,
+                        None => overflow = true,
+                    }
+                    length += 1;
+                    sum = sum.wrapping_add(byte);
+                }
+            }
+        }
+        let checksum = [connection.get_byte(), connection.get_byte()];
+        if !overflow && parse_hex(&checksum) == Some(sum as usize) {
+            connection.put_byte(b'+');
+            return length;
+        }
+        connection.put_byte(b'-');
+    }
+}
+
+/// Sends `data` as packet until gdb acknowledges it
+fn send<C: Connection>(connection: &mut C, data: &[u8]) {
+    let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    loop {
+        connection.put_byte(b'$');
+        for &byte in data {
+            connection.put_byte(byte);
+        }
+        connection.put_byte(b'#');
+        connection.put_byte(HEX_DIGITS[(sum >> 4) as usize]);
+        connection.put_byte(HEX_DIGITS[(sum & 0xF) as usize]);
+        loop {
+            match connection.get_byte() {
+                b'+' => return,
+                b'-' => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Executes a single packet, the reply is written to `reply`
+fn handle<T: Target>(
+    packet: &[u8],
+    reply: &mut [u8],
+    breakpoints: &mut [Option<Breakpoint>],
+    target: &mut T,
+    signal: u8,
+) -> Action {
+    let mut out = Reply { buf: reply, len: 0 };
+    let (command, args) = match packet.split_first() {
+        Some((&command, args)) => (command, args),
+        None => return Action::Reply(0),
+    };
+    match command {
+        b'?' => return Action::Reply(stop_reply(out.buf, signal)),
+        b'g' => {
+            let mut registers = [0; REGISTERS_MAX_SIZE];
+            target.read_registers(&mut registers[..T::REGISTERS_SIZE]);
+            out.push_hex(&registers[..T::REGISTERS_SIZE]);
+        }
+        b'G' => {
+            let mut registers = [0; REGISTERS_MAX_SIZE];
+            match decode_hex(args, &mut registers[..T::REGISTERS_SIZE]) {
+                Some(length) if length == T::REGISTERS_SIZE => {
+                    target.write_registers(&registers[..length]);
+                    out.push(b"OK");
+                }
+                _ => out.push_error(EFAULT),
+            }
+        }
+        b'm' => match parse_range(args) {
+            Some((address, length)) => read_memory(&mut out, target, address, length),
+            None => out.push_error(EFAULT),
+        },
+        b'M' => {
+            let (range, data) = split(args, b':');
+            match parse_range(range) {
+                Some((address, length)) if write_memory(target, address, length, data) => {
+                    out.push(b"OK")
+                }
+                _ => out.push_error(EFAULT),
+            }
+        }
+        b'c' | b's' => {
+            if let Some(address) = parse_hex(args) {
+                target.set_program_counter(address);
+            }
+            return Action::Resume {
+                step: command == b's',
+            };
+        }
+        b'Z' | b'z' => {
+            let (kind, args) = split(args, b',');
+            // only software breakpoints, gdb falls back to writing them with `M`
+            if kind != b"0" {
+                return Action::Reply(0);
+            }
+            let address = match parse_range(args) {
+                Some((address, _)) => address,
+                None => {
+                    out.push_error(EFAULT);
+                    return Action::Reply(out.len);
+                }
+            };
+            let result = if command == b'Z' {
+                insert_breakpoint(breakpoints, target, address)
+            } else {
+                remove_breakpoint(breakpoints, target, address)
+            };
+            match result {
+                Ok(()) => out.push(b"OK"),
+                Err(errno) => out.push_error(errno),
+            }
+        }
+        b'D' | b'k' => {
+            // gdb removes its breakpoints first, this covers a lost connection
+            for slot in breakpoints.iter_mut() {
+                if let Some(breakpoint) = slot.take() {
+                    let length = T::BREAKPOINT.len();
+                    target.write_memory(breakpoint.address, &breakpoint.original[..length]);
+                }
+            }
+            if command == b'k' {
+                return Action::Detach(None);
+            }
+            out.push(b"OK");
+            return Action::Detach(Some(out.len));
+        }
+        b'H' => out.push(b"OK"),
+        b'q' => {
+            if args.starts_with(b"Supported") {
+                out.push(b"PacketSize=");
+                out.push_hex_number(PACKET_SIZE);
+            } else if args.starts_with(b"Attached") {
+                // detaching leaves the kernel running
+                out.push(b"1");
+            }
+        }
+        // unsupported, an empty reply
+        _ => {}
+    }
+    Action::Reply(out.len)
+}
+
+/// `Sxx`, the stop reply with the signal number
+fn stop_reply(reply: &mut [u8], signal: u8) -> usize {
+    let mut out = Reply { buf: reply, len: 0 };
+    out.push(b"S");
+    out.push_hex(&[signal]);
+    out.len
+}
+
+fn read_memory<T: Target>(out: &mut Reply, target: &T, address: usize, length: usize) {
+    // the reply is hex, it holds half a packet of memory
+    let length = length.min(PACKET_SIZE / 2);
+    let mut chunk = [0; MEMORY_CHUNK];
+    let mut offset = 0;
+    while offset < length {
+        let count = (length - offset).min(MEMORY_CHUNK);
+        if !target.read_memory(address + offset, &mut chunk[..count]) {
+            // fewer bytes are allowed, an error only if nothing could be read
+            if offset == 0 {
+                out.push_error(EFAULT);
+            }
+            return;
+        }
+        out.push_hex(&chunk[..count]);
+        offset += count;
+    }
+}
+
+fn write_memory<T: Target>(target: &mut T, address: usize, length: usize, hex: &[u8]) -> bool {
+    if hex.len() != 2 * length {
+        return false;
+    }
+    let mut chunk = [0; MEMORY_CHUNK];
+    for (i, hex) in hex.chunks(2 * MEMORY_CHUNK).enumerate() {
+        let count = match decode_hex(hex, &mut chunk) {
+            Some(count) => count,
+            None => return false,
+        };
+        if !target.write_memory(address + i * MEMORY_CHUNK, &chunk[..count]) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Helper for `Target::read_memory()` & `Target::write_memory()`: calls
+/// `copy(accessible_address, offset, count)` for every page in `address..address + length`.
+/// `translate` returns the address a byte of the page is accessed at, e.g. through the HHDM,
+/// all pages are checked before anything is copied.
+pub fn copy_memory(
+    address: usize,
+    length: usize,
+    translate: impl Fn(usize) -> Option<usize>,
+    mut copy: impl FnMut(usize, usize, usize),
+) -> bool {
+    let end = match address.checked_add(length) {
+        Some(end) => end,
+        None => return false,
+    };
+    let mut page = address & !(PAGE_SIZE - 1);
+    while page < end {
+        if translate(page).is_none() {
+            return false;
+        }
+        page += PAGE_SIZE;
+    }
+
+    let mut offset = 0;
+    while offset < length {
+        let virt = address + offset;
+        let count = (PAGE_SIZE - virt % PAGE_SIZE).min(length - offset);
+        copy(translate(virt).unwrap(), offset, count);
+        offset += count;
+    }
+    true
+}
+
+// ========== Breakpoints
+
+fn insert_breakpoint<T: Target>(
+    breakpoints: &mut [Option<Breakpoint>],
+    target: &mut T,
+    address: usize,
+) -> Result<(), u8> {
+    if breakpoints.iter().flatten().any(|b| b.address == address) {
+        return Ok(());
+    }
+    let slot = breakpoints
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(ENOSPC)?;
+    let length = T::BREAKPOINT.len();
+    let mut original = [0; BREAKPOINT_MAX_LENGTH];
+    if !target.read_memory(address, &mut original[..length])
+        || !target.write_memory(address, T::BREAKPOINT)
+    {
+        return Err(EFAULT);
+    }
+    *slot = Some(Breakpoint { address, original });
+    Ok(())
+}
+
+fn remove_breakpoint<T: Target>(
+    breakpoints: &mut [Option<Breakpoint>],
+    target: &mut T,
+    address: usize,
+) -> Result<(), u8> {
+    let slot = breakpoints
+        .iter_mut()
+        .find(|slot| matches!(slot, Some(b) if b.address == address));
+    if let Some(slot) = slot {
+        let breakpoint = slot.take().unwrap();
+        if !target.write_memory(address, &breakpoint.original[..T::BREAKPOINT.len()]) {
+            return Err(EFAULT);
+        }
+    }
+    Ok(())
+}
+
+/// A breakpoint instruction compiled into the code (e.g. `breakpoint()`) would trap forever on
+/// architectures that stop at the instruction, the stopped code continues behind it
+fn skip_embedded_breakpoint<T: Target>(breakpoints: &[Option<Breakpoint>], target: &mut T) {
+    if !T::BREAKPOINT_STOPS_AT {
+        return;
+    }
+    let pc = target.program_counter();
+    let length = T::BREAKPOINT.len();
+    let mut current = [0; BREAKPOINT_MAX_LENGTH];
+    let inserted = breakpoints.iter().flatten().any(|b| b.address == pc);
+    if !inserted
+        && target.read_memory(pc, &mut current[..length])
+        && current[..length] == *T::BREAKPOINT
+    {
+        target.set_program_counter(pc + length);
+    }
+}
+
+// ========== Encoding
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// builds a reply, bytes that do not fit are dropped
+struct Reply<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Reply<'_> {
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.len < self.buf.len() {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            }
+        }
+    }
+
+    /// every byte as 2 hex digits
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(&[
+                HEX_DIGITS[(byte >> 4) as usize],
+                HEX_DIGITS[(byte & 0xF) as usize],
+            ]);
+        }
+    }
+
+    /// a number in hex without leading zeros
+    fn push_hex_number(&mut self, number: usize) {
+        let digits = ((usize::BITS - number.leading_zeros()).max(1) + 3) / 4;
+        for i in (0..digits).rev() {
+            self.push(&[HEX_DIGITS[(number >> (4 * i)) & 0xF]]);
+        }
+    }
+
+    /// `Exx`
+    fn push_error(&mut self, errno: u8) {
+        self.push(b"E");
+        self.push_hex(&[errno]);
+    }
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// a hex number without prefix
+fn parse_hex(digits: &[u8]) -> Option<usize> {
+    if digits.is_empty() || digits.len() > 2 * core::mem::size_of::<usize>() {
+        return None;
+    }
+    digits
+        .iter()
+        .try_fold(0, |n, &d| Some(n << 4 | hex_value(d)? as usize))
+}
+
+/// `ADDRESS,LENGTH`
+fn parse_range(args: &[u8]) -> Option<(usize, usize)> {
+    let (address, length) = split(args, b',');
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+/// decodes hex pairs into `buf`, returns the decoded length
+fn decode_hex(hex: &[u8], buf: &mut [u8]) -> Option<usize> {
+    if hex.len() % 2 != 0 || hex.len() / 2 > buf.len() {
+        return None;
+    }
+    for (byte, pair) in buf.iter_mut().zip(hex.chunks(2)) {
+        *byte = hex_value(pair[0])? << 4 | hex_value(pair[1])?;
+    }
+    Some(hex.len() / 2)
+}
+
+/// splits at the first `separator`, the second part is empty without one
+fn split(bytes: &[u8], separator: u8) -> (&[u8], &[u8]) {
+    match bytes.iter().position(|&b| b == separator) {
+        Some(i) => (&bytes[..i], &bytes[i + 1..]),
+        None => (bytes, &[]),
+    }
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::string::String;
+    use std::vec::Vec;
+
+    /// gdb, with its packets queued up front
+    struct TestConnection {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl TestConnection {
+        fn new(packets: &[String]) -> Self {
+            let mut input = VecDeque::new();
+            for packet in packets {
+                input.extend(packet.bytes());
+                // acknowledges the reply
+                input.push_back(b'+');
+            }
+            Self {
+                input,
+                output: Vec::new(),
+            }
+        }
+
+        fn output(&self) -> &str {
+            std::str::from_utf8(&self.output).unwrap()
+        }
+    }
+
+    impl Connection for TestConnection {
+        fn get_byte(&mut self) -> u8 {
+            self.input
+                .pop_front()
+                .expect("the stub waits for more input")
+        }
+
+        fn put_byte(&mut self, byte: u8) {
+            self.output.push(byte);
+        }
+    }
+
+    /// a cpu with 2 registers & 256 bytes of memory at 0x1000, breakpoints stop at the
+    /// instruction like on aarch64
+    struct TestTarget {
+        registers: [u32; 2],
+        memory: [u8; 256],
+        step: bool,
+    }
+
+    const BASE: usize = 0x1000;
+
+    impl Target for TestTarget {
+        const REGISTERS_SIZE: usize = 8;
+        const BREAKPOINT: &'static [u8] = &[0xAA, 0xBB];
+        const BREAKPOINT_STOPS_AT: bool = true;
+
+        fn read_registers(&self, buf: &mut [u8]) {
+            buf[..4].copy_from_slice(&self.registers[0].to_le_bytes());
+            buf[4..].copy_from_slice(&self.registers[1].to_le_bytes());
+        }
+
+        fn write_registers(&mut self, buf: &[u8]) {
+            self.registers[0] = u32::from_le_bytes(buf[..4].try_into().unwrap());
+            self.registers[1] = u32::from_le_bytes(buf[4..].try_into().unwrap());
+        }
+
+        fn program_counter(&self) -> usize {
+            self.registers[1] as usize
+        }
+
+        fn set_program_counter(&mut self, address: usize) {
+            self.registers[1] = address as u32;
+        }
+
+        fn set_single_step(&mut self, enabled: bool) {
+            self.step = enabled;
+        }
+
+        fn read_memory(&self, address: usize, buf: &mut [u8]) -> bool {
+            match address.checked_sub(BASE) {
+                Some(offset) if offset + buf.len() <= self.memory.len() => {
+                    buf.copy_from_slice(&self.memory[offset..offset + buf.len()]);
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        fn write_memory(&mut self, address: usize, data: &[u8]) -> bool {
+            match address.checked_sub(BASE) {
+                Some(offset) if offset + data.len() <= self.memory.len() => {
+                    self.memory[offset..offset + data.len()].copy_from_slice(data);
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+
+    fn target() -> TestTarget {
+        let mut memory = [0; 256];
+        for (i, byte) in memory.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        TestTarget {
+            registers: [0x1234_5678, 0x1010],
+            memory,
+            step: false,
+        }
+    }
+
+    /// `$data#checksum`
+    fn packet(data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        std::format!("${}#{:02x}", data, sum)
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let packets = [
+            packet("?"),
+            packet("g"),
+            packet("G0100000020100000"),
+            packet("m1002,4"),
+            packet("M1000,2:cafe"),
+            packet("m1000,2"),
+            packet("m2000,2"),
+            packet("qSupported:swbreak+"),
+            packet("vMustReplyEmpty"),
+            packet("c"),
+        ];
+        let mut connection = TestConnection::new(&packets);
+        let mut target = target();
+        Stub::new().run(&mut connection, &mut target, SIGTRAP);
+
+        let expected = [
+            "S05",
+            "7856341210100000",
+            "OK",
+            "02030405",
+            "OK",
+            "cafe",
+            "E0e",
+            "PacketSize=1000",
+            "",
+        ];
+        let mut output = String::new();
+        for reply in expected {
+            output += "+";
+            output += &packet(reply);
+        }
+        // `c` is acknowledged but has no reply
+        output += "+";
+        assert_eq!(connection.output(), output);
+        assert_eq!(target.registers, [1, 0x1020]);
+        assert!(!target.step);
+    }
+
+    #[test]
+    fn checksums_and_retransmits() {
+        let mut connection = TestConnection::new(&[]);
+        // a corrupted packet, its retransmit & a nack of the reply
+        connection.input.extend(b"junk$?#00".iter());
+        connection.input.extend(packet("?").bytes());
+        connection.input.extend(b"-+".iter());
+        connection.input.extend(packet("s").bytes());
+        let mut target = target();
+        let mut stub = Stub::new();
+        stub.run(&mut connection, &mut target, SIGSEGV);
+        let reply = packet("S0b");
+        assert_eq!(connection.output(), std::format!("-+{}{}+", reply, reply));
+        assert!(target.step);
+
+        // the next stop is reported without a request
+        let mut connection = TestConnection::new(&[packet("c")]);
+        connection.input.push_front(b'+');
+        stub.run(&mut connection, &mut target, SIGTRAP);
+        assert!(connection.output().starts_with(&packet("S05")));
+        assert!(!target.step);
+    }
+
+    #[test]
+    fn software_breakpoints() {
+        let packets = [
+            packet("Z0,1008,2"),
+            packet("Z0,3000,2"),
+            packet("Z1,1008,2"),
+            packet("c"),
+        ];
+        let mut connection = TestConnection::new(&packets);
+        let mut target = target();
+        let mut stub = Stub::new();
+        stub.run(&mut connection, &mut target, SIGTRAP);
+        assert!(connection.output().contains(&packet("E0e")));
+        assert_eq!(target.memory[8..10], [0xAA, 0xBB]);
+
+        // a hit of an inserted breakpoint stays at it
+        target.set_program_counter(0x1008);
+        let mut connection = TestConnection::new(&[packet("z0,1008,2"), packet("c")]);
+        connection.input.push_front(b'+');
+        stub.run(&mut connection, &mut target, SIGTRAP);
+        assert_eq!(target.memory[8..10], [8, 9]);
+        assert_eq!(target.program_counter(), 0x1008);
+
+        // one compiled into the code is skipped
+        target.write_memory(0x1010, &[0xAA, 0xBB]);
+        target.set_program_counter(0x1010);
+        let mut connection = TestConnection::new(&[packet("D")]);
+        connection.input.push_front(b'+');
+        stub.run(&mut connection, &mut target, SIGTRAP);
+        assert_eq!(target.program_counter(), 0x1012);
+    }
+
+    #[test]
+    fn hex_encoding() {
+        assert_eq!(parse_hex(b"ffffffff80001000"), Some(0xFFFF_FFFF_8000_1000));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"12x"), None);
+        assert_eq!(parse_range(b"10,4"), Some((0x10, 4)));
+        let mut buf = [0; 2];
+        assert_eq!(decode_hex(b"0aFf", &mut buf), Some(2));
+        assert_eq!(buf, [0x0A, 0xFF]);
+        assert_eq!(decode_hex(b"0aF", &mut buf), None);
+        assert_eq!(decode_hex(b"000000", &mut buf), None);
+    }
+
+    #[test]
+    fn copy_memory_splits_at_pages() {
+        // page 0x2000 is not mapped, the others are accessed 0x10000 higher
+        let translate = |virt: usize| (virt & !(PAGE_SIZE - 1) != 0x2000).then(|| virt + 0x10000);
+        let mut calls = std::vec::Vec::new();
+        let record = |virt, offset, count| calls.push((virt, offset, count));
+        assert!(copy_memory(0xFF0, 0x20, translate, record));
+        assert_eq!(calls, [(0x10FF0, 0, 0x10), (0x11000, 0x10, 0x10)]);
+
+        // nothing is copied if a page is missing
+        assert!(!copy_memory(0x1FF0, 0x20, translate, |_, _, _| panic!()));
+        assert!(!copy_memory(usize::MAX, 2, translate, |_, _, _| panic!()));
+    }
+}
@@ -7,12 +7,14 @@
 //!
 //! <br> See more about the protocol: `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md`
 
+use crate::config;
 use crate::enum_names;
+use crate::sync::IrqMutex;
+use core::cell::UnsafeCell;
 use core::convert::TryFrom;
 use core::ffi::CStr;
 use core::iter::Iterator;
 use lazy_static::lazy_static;
-use spin::Mutex;
 
 /// simple pointer wrapper that can be replaced in the future for something like `NonNull<T>`
 type Ptr<T> = *const T;
@@ -25,33 +27,54 @@ type TerminalCallbackFunction = extern "C" fn(Ptr<Terminal>, u64, u64, u64, u64)
 /// function provided by limine to simplify display access.
 ///
 /// WARNING: The function is NOT thread-safe, NOT reentrant, per-terminal. <br>
-/// We access it from a `Mutex<TerminalWriter>` e.g. `TERM0`.
+/// We access it from an `IrqMutex<TerminalWriter>` e.g. `TERM0`.
 type TerminalWriteFunction = extern "C" fn(Ptr<Terminal>, *const [u8], usize);
 
-// Linked from kentry/limine.asm
-extern "C" {
-    static LIMINE_REQUEST_BOOT_INFO: RequestBootInfo;
-    static LIMINE_REQUEST_TERMINAL: RequestTerminal;
-    static LIMINE_REQUEST_MEMORY_MAP: RequestMemoryMap;
-    static LIMINE_REQUEST_BOOT_TIME: RequestBootTime;
-    static LIMINE_REQUEST_KERNEL_ADDRESS: RequestKernelAddress;
-    static LIMINE_REQUEST_HHDM: RequestHHDM;
-    static LIMINE_REQUEST_STACK_SIZE: RequestStackSize;
-}
+/// first half of the id of every request
+const COMMON_MAGIC: [u64; 2] = [0xc7b1dd30df4c8b88, 0x0a82e883a194f07b];
+
+/// Bootloaders that know the markers only look for requests between them, the linker scripts
+/// place `.limine_requests` in between
+#[used]
+#[link_section = ".limine_requests_start"]
+static LIMINE_REQUESTS_START: [u64; 4] = [
+    0xf6b8f4b39de7d1ae,
+    0xfab91a6940fcb9cf,
+    0x785c6ed015d3e316,
+    0x181e920a7852b9d9,
+];
+
+#[used]
+#[link_section = ".limine_requests_end"]
+static LIMINE_REQUESTS_END: [u64; 2] = [0xadc0e0531bb10d03, 0x9572709f31764c62];
 
 lazy_static! {
-    /// handles concurrent `terminal.write()` calls
-    static ref TERM0: Mutex<TerminalWriter> =
-        Mutex::new(TerminalWriter::new(0).expect("Could not open limine terminal"));
+    /// handles concurrent `terminal.write()` calls, None if limine provides no terminal
+    static ref TERM0: Option<IrqMutex<TerminalWriter>> = TerminalWriter::new(0).map(IrqMutex::new);
 }
 
 /// public interface to print to TERM0
 /// , accepts ASCII (non utf8 strings) e.g. `b"Hello"`
 ///
-/// It is not adviced to use this, as the bootloader facilities may be reclaimed.
+/// It is not adviced to use this, as the bootloader facilities may be reclaimed. Does nothing
+/// without a terminal.
 pub fn print_bytes(s: &[u8]) {
-    let access = TERM0.lock();
-    ((access).write)(access.get_terminal(), s, s.len());
+    if let Some(term) = &*TERM0 {
+        let access = term.lock();
+        ((access).write)(access.get_terminal(), s, s.len());
+    }
+}
+
+/// Like `print_bytes` but gives up & returns false if `TERM0` is in use, for the panic & exception
+/// handlers. The terminal is not reentrant, so it can not be written without the lock.
+pub fn try_print_bytes(s: &[u8]) -> bool {
+    match TERM0.as_ref().and_then(|term| term.try_lock()) {
+        Some(access) => {
+            ((access).write)(access.get_terminal(), s, s.len());
+            true
+        }
+        None => false,
+    }
 }
 
 /// outdated function
@@ -68,8 +91,7 @@ pub fn print_hex(mut n: usize) {
         }
         n /= 16;
     }
-    let access = TERM0.lock();
-    ((access).write)(access.get_terminal(), &x, x.len());
+    print_bytes(&x);
 }
 
 /// outdated function
@@ -79,19 +101,95 @@ pub fn print_dec(mut n: usize) {
         x[19 - i] = (n % 10 + 48) as u8;
         n /= 10;
     }
-    let access = TERM0.lock();
-    ((access).write)(access.get_terminal(), &x, x.len());
+    print_bytes(&x);
+}
+
+/// Error returned when the kernel can not use the response to a request
+///
+/// ## Variants:
+/// - `NoResponse` : The bootloader does not support the feature, contains the feature name
+/// - `Revision` : The response is older than the kernel requires, contains the feature name & the
+/// revision of the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimineError {
+    NoResponse(&'static str),
+    Revision(&'static str, u64),
+}
+
+/// Implemented by the requests of `limine_feature!`, see `response()`
+trait Request {
+    type Response: 'static;
+    /// feature name used by `LimineError` & `features()`
+    const NAME: &'static str;
+    /// lowest response revision the kernel can read
+    const RESPONSE_REVISION: u64;
+
+    /// the response pointer written by the bootloader, null if it does not support the feature
+    fn raw_response(&self) -> Ptr<Self::Response>;
+
+    fn revision(response: &Self::Response) -> u64;
+}
+
+/// Checks the response to `request` before handing it out. Use this instead of dereferencing
+/// `Request::raw_response()`, the bootloader may be older or configured differently.
+fn response<R: Request>(request: &R) -> Result<&'static R::Response, LimineError> {
+    let ptr = request.raw_response();
+    if ptr.is_null() {
+        return Err(LimineError::NoResponse(R::NAME));
+    }
+    // SAFETY: responses live in bootloader reclaimable memory, which the kernel keeps claimed
+    let response = unsafe { &*ptr };
+    match R::revision(response) {
+        revision if revision < R::RESPONSE_REVISION => {
+            Err(LimineError::Revision(R::NAME, revision))
+        }
+        _ => Ok(response),
+    }
+}
+
+/// `response()` of a feature the kernel can not run without, panics if it is missing
+fn required<R: Request>(request: &R) -> &'static R::Response {
+    match response(request) {
+        Ok(response) => response,
+        Err(e) => panic!("Required limine feature unavailable: {:?}", e),
+    }
+}
+
+/// (feature name, response revision) of `request`, see `features()`
+fn feature<R: Request>(request: &R) -> Result<(&'static str, u64), LimineError> {
+    response(request).map(|response| (R::NAME, R::revision(response)))
+}
+
+/// (feature name, response revision) of every feature the kernel requests, or why the kernel can
+/// not use it. Used for the boot report.
+pub fn features() -> [Result<(&'static str, u64), LimineError>; 9] {
+    [
+        feature(&LIMINE_REQUEST_BOOT_INFO),
+        feature(&LIMINE_REQUEST_TERMINAL),
+        feature(&LIMINE_REQUEST_MEMORY_MAP),
+        feature(&LIMINE_REQUEST_BOOT_TIME),
+        feature(&LIMINE_REQUEST_KERNEL_ADDRESS),
+        feature(&LIMINE_REQUEST_HHDM),
+        feature(&LIMINE_REQUEST_STACK_SIZE),
+        feature(&LIMINE_REQUEST_FRAMEBUFFER),
+        feature(&LIMINE_REQUEST_MODULE),
+    ]
 }
 
-/// macro that completes a request and response struct with all the default fields.
+/// macro that completes a request and response struct with all the default fields & places the
+/// request as `$static` into the kernel binary, where the bootloader finds it.
 ///
 /// The workings of this macro can be deduced from the source code or context. <br>
 /// See more about limine features: `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#features`
 macro_rules! limine_feature {
     (
         #[doc = $doc:expr]
-        struct $request:ident {
-            $($req_field_key:ident : $req_field_type:ty,)*
+        static $static:ident = $request:ident {
+            name: $name:expr,
+            id: [$id_a:expr, $id_b:expr],
+            revision: $revision:expr,
+            response_revision: $response_revision:expr,
+            $($req_field_key:ident : $req_field_type:ty = $req_field_value:expr,)*
         }
 
         struct $response:ident {
@@ -103,10 +201,29 @@ macro_rules! limine_feature {
         struct $request {
             id: [u64; 4],
             revision: u64,
-            response: Ptr<$response>,
+            // written by the bootloader, see `Request::raw_response()`
+            response: UnsafeCell<Ptr<$response>>,
             $($req_field_key : $req_field_type,)*
         }
 
+        // SAFETY: the bootloader writes the response before the kernel runs, it is read only after
+        unsafe impl Sync for $request {}
+
+        impl Request for $request {
+            type Response = $response;
+            const NAME: &'static str = $name;
+            const RESPONSE_REVISION: u64 = $response_revision;
+
+            fn raw_response(&self) -> Ptr<$response> {
+                // volatile, the compiler only knows the null the static starts with
+                unsafe { core::ptr::read_volatile(self.response.get()) }
+            }
+
+            fn revision(response: &$response) -> u64 {
+                response.revision
+            }
+        }
+
         #[repr(C)]
         #[derive(Clone)]
         #[doc = $doc]
@@ -114,6 +231,16 @@ macro_rules! limine_feature {
             revision: u64,
             $($res_field_key : $res_field_type,)*
         }
+
+        #[used]
+        #[link_section = ".limine_requests"]
+        #[doc = $doc]
+        static $static: $request = $request {
+            id: [COMMON_MAGIC[0], COMMON_MAGIC[1], $id_a, $id_b],
+            revision: $revision,
+            response: UnsafeCell::new(core::ptr::null()),
+            $($req_field_key : $req_field_value,)*
+        };
     };
 }
 
@@ -121,22 +248,25 @@ macro_rules! limine_feature {
 // See: https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#terminal-feature
 
 /// returns the bootloaders name and version
-///
-/// WARNING: assumes the response has static lifetime
-pub fn bootloader_info() -> (&'static [u8], &'static [u8]) {
-    let response = unsafe { &*(LIMINE_REQUEST_BOOT_INFO.response) };
-    (
-        // SAFETY: ptr must not be null and must hold null terminated string
+pub fn bootloader_info() -> Result<(&'static [u8], &'static [u8]), LimineError> {
+    let response = response(&LIMINE_REQUEST_BOOT_INFO)?;
+    Ok((
+        // SAFETY: limine provides null terminated strings
         unsafe { CStr::from_ptr(response.name as *const i8).to_bytes() },
         unsafe { CStr::from_ptr(response.version as *const i8).to_bytes() },
-    )
+    ))
 }
 
 limine_feature! {
 
     /// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#terminal-feature`
 
-    struct RequestBootInfo{}
+    static LIMINE_REQUEST_BOOT_INFO = RequestBootInfo {
+        name: "Bootloader Info",
+        id: [0xf55038d8e2a1202f, 0x279426fcf5f59740],
+        revision: 0,
+        response_revision: 0,
+    }
 
     struct ResponseBootInfo {
         name: *const u8,
@@ -153,7 +283,12 @@ limine_feature! {
 
     /// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#memory-map-feature`
 
-    struct RequestMemoryMap {}
+    static LIMINE_REQUEST_MEMORY_MAP = RequestMemoryMap {
+        name: "Memory Map",
+        id: [0x67cf3d9d378a806f, 0xe304acdfc50c3c62],
+        revision: 0,
+        response_revision: 0,
+    }
 
     struct ResponseMemoryMap {
         entry_count: u64,
@@ -223,8 +358,10 @@ impl Into<&'static [u8]> for MemmapEntryType {
 }*/
 
 /// extern interface function used by the rest of the kernel
+///
+/// WARNING: panics without a memory map, the kernel can not run without one
 pub fn memory_map() -> MemoryMap {
-    MemoryMap::new(unsafe { &*(LIMINE_REQUEST_MEMORY_MAP.response) })
+    MemoryMap::new(required(&LIMINE_REQUEST_MEMORY_MAP))
 }
 
 /// rust-friendly version of `ResponseMemoryMap`
@@ -273,7 +410,12 @@ limine_feature! {
 
     /// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#boot-time-feature`
 
-    struct RequestBootTime{}
+    static LIMINE_REQUEST_BOOT_TIME = RequestBootTime {
+        name: "Boot Time",
+        id: [0x502746e184c088aa, 0xfbc5ec83e6327893],
+        revision: 0,
+        response_revision: 0,
+    }
 
     struct ResponseBootTime {
         time: i64,
@@ -281,8 +423,8 @@ limine_feature! {
 }
 
 /// Gets the unix time at boot
-pub fn boot_time_stamp() -> i64 {
-    unsafe { (*LIMINE_REQUEST_BOOT_TIME.response).time }
+pub fn boot_time_stamp() -> Result<i64, LimineError> {
+    Ok(response(&LIMINE_REQUEST_BOOT_TIME)?.time)
 }
 
 // ======= Kernel Address feature
@@ -292,7 +434,12 @@ limine_feature! {
 
     /// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#kernel-address-feature`
 
-    struct RequestKernelAddress {}
+    static LIMINE_REQUEST_KERNEL_ADDRESS = RequestKernelAddress {
+        name: "Kernel Address",
+        id: [0x71ba76863cc55f63, 0xb2644a48c516a487],
+        revision: 0,
+        response_revision: 0,
+    }
 
     struct ResponseKernelAddress {
         physical_base: u64,
@@ -301,13 +448,15 @@ limine_feature! {
 }
 
 /// Get the physical base address for the kernel
+///
+/// WARNING: panics without a response, the kernel can not build its page tables without it
 pub fn kernel_address_physical() -> usize {
-    (unsafe { (*LIMINE_REQUEST_KERNEL_ADDRESS.response).physical_base }) as usize
+    required(&LIMINE_REQUEST_KERNEL_ADDRESS).physical_base as usize
 }
 
-/// Get the virtual base address for the kernel
+/// Get the virtual base address for the kernel, see `kernel_address_physical()`
 pub fn kernel_address_virtual() -> usize {
-    (unsafe { (*LIMINE_REQUEST_KERNEL_ADDRESS.response).virtual_base }) as usize
+    required(&LIMINE_REQUEST_KERNEL_ADDRESS).virtual_base as usize
 }
 
 // ======= HHDM (higher half direct map) feature
@@ -317,7 +466,12 @@ limine_feature! {
 
     /// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#hhdm-higher-half-direct-map-feature`
 
-    struct RequestHHDM {}
+    static LIMINE_REQUEST_HHDM = RequestHHDM {
+        name: "HHDM",
+        id: [0x48dcf1cb8ad2b852, 0x63984e959a98244b],
+        revision: 0,
+        response_revision: 0,
+    }
 
     struct ResponseHHDM {
         offset: u64,
@@ -325,8 +479,10 @@ limine_feature! {
 }
 
 /// Get the higher half direct map
+///
+/// WARNING: panics without a response, the kernel can not reach physical memory without it
 pub fn hhdm() -> usize {
-    (unsafe { (*LIMINE_REQUEST_HHDM.response).offset }) as usize
+    required(&LIMINE_REQUEST_HHDM).offset as usize
 }
 
 // ======= Stack Size feature
@@ -336,16 +492,269 @@ limine_feature! {
 
     /// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#stack-size-feature`
 
-    struct RequestStackSize {
-        size: u64,
+    static LIMINE_REQUEST_STACK_SIZE = RequestStackSize {
+        name: "Stack Size",
+        id: [0x224ef0460a8e8926, 0xe1cb0fc25f46ea3d],
+        revision: 0,
+        response_revision: 0,
+        size: u64 = config::STACK_SIZE,
     }
 
     struct ResponseStackSize {}
 }
 
+// ======= Framebuffer feature
+// See: https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#framebuffer-feature
+
+// private
+
+limine_feature! {
+
+    /// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#framebuffer-feature`
+
+    static LIMINE_REQUEST_FRAMEBUFFER = RequestFramebuffer {
+        name: "Framebuffer",
+        id: [0x9d5827dcd881dd75, 0xa3148604f6fab11b],
+        revision: 0,
+        response_revision: 0,
+    }
+
+    struct ResponseFramebuffer {
+        framebuffer_count: u64,
+        // has length of framebuffer_count
+        framebuffers: Ptr<Ptr<Framebuffer>>,
+    }
+}
+
+/// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#framebuffer-feature`
+#[repr(C)]
+struct VideoModeEntry {
+    pitch: u64,
+    width: u64,
+    height: u64,
+    bpp: u16,
+    memory_model: u8,
+    red_mask_size: u8,
+    red_mask_shift: u8,
+    green_mask_size: u8,
+    green_mask_shift: u8,
+    blue_mask_size: u8,
+    blue_mask_shift: u8,
+}
+
+// public
+
+/// `VideoMode::memory_model` of RGB framebuffers, the only one the protocol defines
+pub const MEMORY_MODEL_RGB: u8 = 1;
+
+/// rust-friendly version of `VideoModeEntry`, also describes the current mode of a framebuffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoMode {
+    pub width: usize,
+    pub height: usize,
+    /// bytes per line, may be more than `width` pixels
+    pub pitch: usize,
+    /// bits per pixel
+    pub bpp: u16,
+    pub memory_model: u8,
+    /// (size, shift) in bits of the color channels in a pixel
+    pub red_mask: (u8, u8),
+    pub green_mask: (u8, u8),
+    pub blue_mask: (u8, u8),
+}
+
+impl VideoModeEntry {
+    fn mode(&self) -> VideoMode {
+        VideoMode {
+            width: self.width as usize,
+            height: self.height as usize,
+            pitch: self.pitch as usize,
+            bpp: self.bpp,
+            memory_model: self.memory_model,
+            red_mask: (self.red_mask_size, self.red_mask_shift),
+            green_mask: (self.green_mask_size, self.green_mask_shift),
+            blue_mask: (self.blue_mask_size, self.blue_mask_shift),
+        }
+    }
+}
+
+impl Framebuffer {
+    /// the current mode, framebuffers repeat the fields of `VideoModeEntry`
+    fn mode(&self) -> VideoMode {
+        VideoMode {
+            width: self.width as usize,
+            height: self.height as usize,
+            pitch: self.pitch as usize,
+            bpp: self.bpp,
+            memory_model: self.memory_model,
+            red_mask: (self.red_mask_size, self.red_mask_shift),
+            green_mask: (self.green_mask_size, self.green_mask_shift),
+            blue_mask: (self.blue_mask_size, self.blue_mask_shift),
+        }
+    }
+}
+
+/// extern interface function used by the rest of the kernel, see `video::FrameBuffer` for drawing
+pub fn framebuffers() -> Result<Framebuffers, LimineError> {
+    Ok(Framebuffers {
+        index: 0,
+        response: response(&LIMINE_REQUEST_FRAMEBUFFER)?,
+    })
+}
+
+/// Iterates through the framebuffers of the response
+pub struct Framebuffers {
+    index: u64,
+    response: &'static ResponseFramebuffer,
+}
+
+impl Iterator for Framebuffers {
+    type Item = FramebufferItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.response.framebuffer_count {
+            return None;
+        }
+
+        // SAFETY: the response holds framebuffer_count valid pointers
+        let f = unsafe { &**self.response.framebuffers.offset(self.index as isize) };
+        self.index += 1;
+
+        let edid: &[u8] = match f.edid.is_null() {
+            true => &[],
+            // SAFETY: limine provides edid_size bytes
+            false => unsafe { core::slice::from_raw_parts(f.edid, f.edid_size as usize) },
+        };
+        let modes: &[Ptr<VideoModeEntry>] = match self.response.revision >= 1 && !f.modes.is_null()
+        {
+            // SAFETY: limine provides mode_count valid pointers
+            true => unsafe { core::slice::from_raw_parts(f.modes, f.mode_count as usize) },
+            false => &[],
+        };
+        Some(Self::Item {
+            address: f.address as usize,
+            mode: f.mode(),
+            edid,
+            modes,
+        })
+    }
+}
+
+/// rust-friendly version of `Framebuffer`
+pub struct FramebufferItem {
+    /// virtual address of the first pixel, `mode.pitch * mode.height` bytes are mapped
+    pub address: usize,
+    /// the mode the framebuffer is set to
+    pub mode: VideoMode,
+    /// EDID of the display, empty if limine did not find one
+    pub edid: &'static [u8],
+    modes: &'static [Ptr<VideoModeEntry>],
+}
+
+impl FramebufferItem {
+    /// the modes the display supports, empty if limine is older than response revision 1
+    pub fn modes(&self) -> impl Iterator<Item = VideoMode> + '_ {
+        // SAFETY: checked by `Framebuffers::next()`
+        self.modes.iter().map(|&mode| unsafe { &*mode }.mode())
+    }
+}
+
+// ======= Module feature
+// See: https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#module-feature
+
+// private
+
+limine_feature! {
+
+    /// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#module-feature`
+
+    static LIMINE_REQUEST_MODULE = RequestModule {
+        name: "Module",
+        id: [0x3e7e279702be32af, 0xca1c4f3bd1280cee],
+        revision: 0,
+        response_revision: 0,
+    }
+
+    struct ResponseModule {
+        module_count: u64,
+        // has length of module_count
+        modules: Ptr<Ptr<File>>,
+    }
+}
+
+/// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#file-structure`
+///
+/// Only the fields in front of the media & partition information, which the kernel does not read.
+#[repr(C)]
+struct File {
+    revision: u64,
+    address: Ptr<u8>,
+    size: u64,
+    path: Ptr<u8>,
+    cmdline: Ptr<u8>,
+}
+
+// public
+
+/// extern interface function used by the rest of the kernel, the modules are the `MODULE_PATH`
+/// entries of `limine.cfg`
+pub fn modules() -> Result<Modules, LimineError> {
+    Ok(Modules {
+        index: 0,
+        response: response(&LIMINE_REQUEST_MODULE)?,
+    })
+}
+
+/// The module whose path ends with `name`, e.g. `"splash.bmp"` for `boot:///splash.bmp`
+pub fn module(name: &str) -> Result<Option<ModuleItem>, LimineError> {
+    Ok(modules()?.find(|module| module.path.ends_with(name.as_bytes())))
+}
+
+/// Iterates through the modules of the response
+pub struct Modules {
+    index: u64,
+    response: &'static ResponseModule,
+}
+
+impl Iterator for Modules {
+    type Item = ModuleItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.response.module_count {
+            return None;
+        }
+
+        // SAFETY: the response holds module_count valid pointers
+        let f = unsafe { &**self.response.modules.offset(self.index as isize) };
+        self.index += 1;
+
+        Some(Self::Item {
+            // SAFETY: limine loads the whole file into KernelAndModules memory, which is never
+            // reclaimed
+            data: unsafe { core::slice::from_raw_parts(f.address, f.size as usize) },
+            // SAFETY: limine provides null terminated strings
+            path: unsafe { CStr::from_ptr(f.path as *const i8).to_bytes() },
+            cmdline: unsafe { CStr::from_ptr(f.cmdline as *const i8).to_bytes() },
+        })
+    }
+}
+
+/// rust-friendly version of `File`
+pub struct ModuleItem {
+    /// the file content
+    pub data: &'static [u8],
+    /// e.g. `/splash.bmp`
+    pub path: &'static [u8],
+    /// the `MODULE_CMDLINE` of the module, empty if there is none
+    pub cmdline: &'static [u8],
+}
+
 // ======= Terminal feature
 // See: https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#bootloader-info-feature
 
+/// Called by the terminal for escape sequences it does not handle itself, they are ignored
+extern "C" fn terminal_callback(_terminal: Ptr<Terminal>, _typ: u64, _a: u64, _b: u64, _c: u64) {}
+
 /// Safe wrapper over the `TerminalWriteFunction` by the terminal feature
 struct TerminalWriter {
     term: usize, // pointer to terminal
@@ -355,7 +764,7 @@ struct TerminalWriter {
 // handles
 impl TerminalWriter {
     fn new(terminal_number: u64) -> Option<Self> {
-        let term_resp = unsafe { &*(LIMINE_REQUEST_TERMINAL.response) };
+        let term_resp = response(&LIMINE_REQUEST_TERMINAL).ok()?;
         if term_resp.terminal_count > terminal_number {
             return Some(Self {
                 term: term_resp.terminals as usize + terminal_number as usize,
@@ -380,8 +789,12 @@ limine_feature! {
 
     /// `https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#bootloader-info-feature`
 
-    struct RequestTerminal {
-        callback: TerminalCallbackFunction,
+    static LIMINE_REQUEST_TERMINAL = RequestTerminal {
+        name: "Terminal",
+        id: [0xc8ac59310c2b0844, 0xa68d0c7265d38878],
+        revision: 0,
+        response_revision: 0,
+        callback: TerminalCallbackFunction = terminal_callback,
     }
 
     struct ResponseTerminal {
@@ -420,4 +833,62 @@ struct Framebuffer {
     pub reserved: [u8; 7],
     pub edid_size: u64,
     pub edid: Ptr<u8>,
+    // only with response revision 1 or later
+    pub mode_count: u64,
+    pub modes: Ptr<Ptr<VideoModeEntry>>,
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+
+    limine_feature! {
+
+        /// feature of a newer protocol revision
+
+        static TEST_REQUEST = RequestTest {
+            name: "Test",
+            id: [1, 2],
+            revision: 0,
+            response_revision: 2,
+        }
+
+        struct ResponseTest {
+            value: u64,
+        }
+    }
+
+    fn request(response: Ptr<ResponseTest>) -> RequestTest {
+        RequestTest {
+            id: TEST_REQUEST.id,
+            revision: 0,
+            response: UnsafeCell::new(response),
+        }
+    }
+
+    #[test]
+    fn checked_responses() {
+        // not answered by the bootloader
+        assert_eq!(
+            response(&TEST_REQUEST).err(),
+            Some(LimineError::NoResponse("Test"))
+        );
+
+        let old = ResponseTest {
+            revision: 1,
+            value: 7,
+        };
+        assert_eq!(
+            feature(&request(&old)),
+            Err(LimineError::Revision("Test", 1))
+        );
+
+        let current = ResponseTest {
+            revision: 3,
+            value: 7,
+        };
+        assert_eq!(response(&request(&current)).unwrap().value, 7);
+        assert_eq!(feature(&request(&current)), Ok(("Test", 3)));
+    }
 }
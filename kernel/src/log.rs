@@ -4,34 +4,92 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-//! All logs are passed to a `GlobalLog` object that then stores/outputs them.
+//! All logs are passed to the registered sinks (limine terminal, serial port, `GlobalLog`, ...)
+//! that then store/output them. `GlobalLog` keeps the latest records for `dmesg()`.
+//!
+//! Records are created with the leveled macros `error!()`, `warn!()`, `info!()`, `debug!()` and
+//! `trace!()`, which prefix the level & module and append a line feed. They are filtered per module
+//! by the spec in `config::LOG_FILTER` (see `filter.rs`) and per sink by its own max level.
+//! `log!()` writes unleveled text to every sink, use it for multi-line output.
+//!
+//! All locks are `IrqMutex`es, so logging from interrupt handlers can not deadlock against the
+//! interrupted code. The panic & exception handlers use `emergency!()` instead, which never waits
+//! for a lock as the panicking code may hold it.
 
+use crate::config::{LOG_FILTER, LOG_SINK_CAPACITY, LOG_STATIC_CAPACITY};
 use crate::limine;
-use arrayvec::ArrayString;
+use crate::sync::IrqMutex;
 use core::fmt;
 use core::fmt::{Arguments, Write};
 use lazy_static::lazy_static;
-use spin::Mutex;
 
-/// The selected logger at compile time. It must implement a `new()` function that returns `Self` &
-/// the `core::fmt::Write` trait.
-type GlobalLog = StaticLog;
+mod filter;
+mod ring;
+pub use filter::{Filter, FilterError};
+pub use ring::{Record, RingLog, RingStats, RECORD_MAX_LENGTH};
+
+/// The selected logger at compile time. It must implement a const `new()` function that returns
+/// `Self` & the `core::fmt::Write` trait.
+type GlobalLog = RingLog<LOG_STATIC_CAPACITY>;
+
+/// Global object that stores the whole kernel log runtime
+static GLOBAL_LOG: IrqMutex<GlobalLog> = IrqMutex::new(GlobalLog::new());
 
 lazy_static! {
-    /// Global object that stores the whole kernel log runtime
-    static ref GLOBAL_LOG: Mutex<GlobalLog> = Mutex::new(GlobalLog::new());
+    /// Sinks that receive all records
+    static ref SINKS: IrqMutex<[Option<SinkEntry>; LOG_SINK_CAPACITY]> =
+        IrqMutex::new(default_sinks());
+    /// Per-module levels
+    static ref FILTER: IrqMutex<Filter> =
+        IrqMutex::new(Filter::parse(LOG_FILTER).expect("Invalid config::LOG_FILTER!"));
 }
 
-/// Panic message when `GlobalLog.write_str()` fails. See more in the current `GlobalLog`
-/// implementation.
-const PRINT_PANIC: &'static str = "Could not write to GLOBAL_LOG!";
+// ========== Levels
 
-/// Used in the `log!()` macro as utility function to reach `GLOBAL_LOG`
-pub fn print(msg: Arguments) {
-    GLOBAL_LOG.lock().write_fmt(msg).expect(PRINT_PANIC)
+/// Importance of a record, `Error` is the most important
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// Prefix written in front of every record
+    pub const fn prefix(self) -> &'static str {
+        match self {
+            Level::Error => "[ERROR]",
+            Level::Warn => "[WARNING]",
+            Level::Info => "[INFO]",
+            Level::Debug => "[DEBUG]",
+            Level::Trace => "[TRACE]",
+        }
+    }
 }
 
-/// Main macro used to log data, similar syntax to the standart `print!()`
+/// Max `Level` that passes a filter or sink, `Off` blocks every record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LevelFilter {
+    Off = 0,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LevelFilter {
+    /// true if records of `level` pass
+    pub const fn allows(self, level: Level) -> bool {
+        level as u8 <= self as u8
+    }
+}
+
+// ========== Macros
+
+/// Unleveled output to every sink, similar syntax to the standart `print!()`
 ///
 /// WARNING: In newer rust version using padding -> blocks the main thread for an uknown reason
 #[macro_export]
@@ -39,35 +97,457 @@ macro_rules! log {
     ($($arg:tt)*) => ($crate::log::print(format_args!($($arg)*)));
 }
 
-// Static Log implementation
+/// Unleveled output that never waits for a lock, see `emergency()`
+#[macro_export]
+macro_rules! emergency {
+    ($($arg:tt)*) => ($crate::log::emergency(format_args!($($arg)*)));
+}
 
-use crate::config::LOG_STATIC_CAPACITY;
+/// Logs a record of `Level::Error`, a line feed is appended
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log::record($crate::log::Level::Error, module_path!(), format_args!($($arg)*)));
+}
 
-/// Simple implementation of `GlobalLog` with a static size/limit.
+/// Logs a record of `Level::Warn`, a line feed is appended
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log::record($crate::log::Level::Warn, module_path!(), format_args!($($arg)*)));
+}
+
+/// Logs a record of `Level::Info`, a line feed is appended
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log::record($crate::log::Level::Info, module_path!(), format_args!($($arg)*)));
+}
+
+/// Logs a record of `Level::Debug`, a line feed is appended
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log::record($crate::log::Level::Debug, module_path!(), format_args!($($arg)*)));
+}
+
+/// Logs a record of `Level::Trace`, a line feed is appended
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log::record($crate::log::Level::Trace, module_path!(), format_args!($($arg)*)));
+}
+
+// ========== Records
+
+/// Used in the `log!()` macro as utility function to reach all sinks
+pub fn print(msg: Arguments) {
+    for entry in sinks() {
+        SinkWriter(entry.sink).write_fmt(msg).ok();
+    }
+}
+
+/// Used in the `emergency!()` macro, writes to every sink through `Sink::write_emergency()`.
+/// If the sink registry itself is locked only the serial port is written.
+pub fn emergency(msg: Arguments) {
+    let sinks = match SINKS.try_lock() {
+        Some(sinks) => *sinks,
+        None => {
+            EmergencyWriter(&SerialSink).write_fmt(msg).ok();
+            return;
+        }
+    };
+    for entry in sinks.into_iter().flatten() {
+        EmergencyWriter(entry.sink).write_fmt(msg).ok();
+    }
+}
+
+/// Used in the leveled macros, writes `[LEVEL] module: msg` to every sink that allows `level`
+pub fn record(level: Level, module_path: &'static str, msg: Arguments) {
+    let module = strip_crate(module_path);
+    if !FILTER.lock().enabled(level, module) {
+        return;
+    }
+    for entry in sinks().filter(|e| e.level.allows(level)) {
+        write!(
+            SinkWriter(entry.sink),
+            "{} {}: {}\n",
+            level.prefix(),
+            module,
+            msg
+        )
+        .ok();
+    }
+}
+
+/// true if a record of `level` from `module_path` (e.g. `module_path!()`) would be logged by any
+/// sink, use it to skip expensive debug output
+pub fn enabled(level: Level, module_path: &'static str) -> bool {
+    FILTER.lock().enabled(level, strip_crate(module_path)) && sinks().any(|e| e.level.allows(level))
+}
+
+/// Replaces the module filter, see `filter.rs` for the spec format
+pub fn set_filter(spec: &'static str) -> Result<(), FilterError> {
+    *FILTER.lock() = Filter::parse(spec)?;
+    Ok(())
+}
+
+/// Sets the max level of `module` (without the crate name) & its submodules
+pub fn set_module_level(module: &'static str, level: LevelFilter) -> Result<(), FilterError> {
+    FILTER.lock().set(module, level)
+}
+
+/// `kernel::memman::map` -> `memman::map`, filters & records do not contain the crate name
+fn strip_crate(module_path: &'static str) -> &'static str {
+    module_path
+        .split_once("::")
+        .map_or(module_path, |(_, module)| module)
+}
+
+// ========== Sinks
+
+/// Implement for log outputs, see `add_sink()`
+pub trait Sink: Sync {
+    /// Outputs formatted log text, must not log itself
+    fn write(&self, s: &str);
+
+    /// Like `write()` but called from the panic & exception handlers, so it must not wait for a
+    /// lock. Sinks that can not guarantee this drop the text.
+    fn write_emergency(&self, _s: &str) {}
+}
+
+/// Errors while changing the sink registry
 ///
-/// This writes all info to `limine::print_bytes` and stores it in a static buffer.
-/// One big issue with this is that if the buffer fills using `log!()` will cause a `PRINT_PANIC`.
-/// The only possible fix is to increase `LOG_STATIC_CAPACITY`,
-/// recompile and hope it does not fill again.
-struct StaticLog {
-    content: ArrayString<LOG_STATIC_CAPACITY>,
-}
-
-impl StaticLog {
-    fn new() -> Self {
-        Self {
-            content: ArrayString::<LOG_STATIC_CAPACITY>::new(),
+/// ## Variants:
+/// - `AlreadyAdded` a sink with the same name is registered
+/// - `Full` the registry holds `config::LOG_SINK_CAPACITY` sinks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SinkError {
+    AlreadyAdded,
+    Full,
+}
+
+#[derive(Clone, Copy)]
+struct SinkEntry {
+    name: &'static str,
+    sink: &'static dyn Sink,
+    level: LevelFilter,
+}
+
+/// Registers a sink under `name`, it receives records up to `level` & all `log!()` output
+pub fn add_sink(
+    name: &'static str,
+    sink: &'static dyn Sink,
+    level: LevelFilter,
+) -> Result<(), SinkError> {
+    let mut sinks = SINKS.lock();
+    if sinks.iter().flatten().any(|e| e.name == name) {
+        return Err(SinkError::AlreadyAdded);
+    }
+    let slot = sinks
+        .iter_mut()
+        .find(|e| e.is_none())
+        .ok_or(SinkError::Full)?;
+    *slot = Some(SinkEntry { name, sink, level });
+    Ok(())
+}
+
+/// Unregisters the sink `name` & returns it
+pub fn remove_sink(name: &str) -> Option<&'static dyn Sink> {
+    let mut sinks = SINKS.lock();
+    let slot = sinks
+        .iter_mut()
+        .find(|e| e.map_or(false, |e| e.name == name))?;
+    slot.take().map(|e| e.sink)
+}
+
+/// Changes the max level of the sink `name`, returns false if it is not registered
+pub fn set_sink_level(name: &str, level: LevelFilter) -> bool {
+    let mut sinks = SINKS.lock();
+    match sinks.iter_mut().flatten().find(|e| e.name == name) {
+        Some(entry) => {
+            entry.level = level;
+            true
         }
+        None => false,
     }
 }
 
-impl Write for StaticLog {
+/// copy of the registry, sinks are not called with `SINKS` locked so a panicking sink can not
+/// block the panic handler
+fn sinks() -> impl Iterator<Item = SinkEntry> {
+    let sinks = *SINKS.lock();
+    sinks.into_iter().flatten()
+}
+
+/// adapter to use `write!()` on a sink
+struct SinkWriter<'a>(&'a dyn Sink);
+
+impl Write for SinkWriter<'_> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        if s.len() > self.content.remaining_capacity() {
-            return Err(fmt::Error);
-        }
-        limine::print_bytes(s.as_bytes());
-        self.content.push_str(s);
+        self.0.write(s);
         Ok(())
     }
 }
+
+/// adapter to use `write!()` on `Sink::write_emergency()`
+struct EmergencyWriter<'a>(&'a dyn Sink);
+
+impl Write for EmergencyWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_emergency(s);
+        Ok(())
+    }
+}
+
+/// Writes to the limine terminal, which may be reclaimed with the bootloader memory
+pub struct TerminalSink;
+
+impl Sink for TerminalSink {
+    fn write(&self, s: &str) {
+        limine::print_bytes(s.as_bytes());
+    }
+
+    fn write_emergency(&self, s: &str) {
+        limine::try_print_bytes(s.as_bytes());
+    }
+}
+
+/// Writes to `arch::serial::SERIAL0`
+pub struct SerialSink;
+
+impl Sink for SerialSink {
+    fn write(&self, s: &str) {
+        crate::arch::serial::print_bytes(s.as_bytes());
+    }
+
+    fn write_emergency(&self, s: &str) {
+        crate::arch::serial::emergency_writer().write_bytes(s.as_bytes());
+    }
+}
+
+/// Stores everything in `GLOBAL_LOG`
+pub struct MemorySink;
+
+impl Sink for MemorySink {
+    fn write(&self, s: &str) {
+        // the ring overwrites old records, it never fails
+        GLOBAL_LOG.lock().write_str(s).ok();
+    }
+
+    fn write_emergency(&self, s: &str) {
+        if let Some(mut log) = GLOBAL_LOG.try_lock() {
+            log.write_str(s).ok();
+        }
+    }
+}
+
+#[cfg(target_os = "none")]
+fn default_sinks() -> [Option<SinkEntry>; LOG_SINK_CAPACITY] {
+    let mut sinks = [None; LOG_SINK_CAPACITY];
+    sinks[0] = Some(SinkEntry {
+        name: "memory",
+        sink: &MemorySink,
+        level: LevelFilter::Trace,
+    });
+    sinks[1] = Some(SinkEntry {
+        name: "terminal",
+        sink: &TerminalSink,
+        level: LevelFilter::Trace,
+    });
+    sinks[2] = Some(SinkEntry {
+        name: "serial",
+        sink: &SerialSink,
+        level: LevelFilter::Trace,
+    });
+    sinks
+}
+
+/// Host unit tests have no limine terminal, logs go to the test output instead
+#[cfg(not(target_os = "none"))]
+struct StdSink;
+
+#[cfg(not(target_os = "none"))]
+impl Sink for StdSink {
+    fn write(&self, s: &str) {
+        std::print!("{}", s)
+    }
+
+    fn write_emergency(&self, s: &str) {
+        std::print!("{}", s)
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+fn default_sinks() -> [Option<SinkEntry>; LOG_SINK_CAPACITY] {
+    let mut sinks = [None; LOG_SINK_CAPACITY];
+    sinks[0] = Some(SinkEntry {
+        name: "std",
+        sink: &StdSink,
+        level: LevelFilter::Trace,
+    });
+    sinks
+}
+
+// ========== Reader
+
+/// Reads the records of `GLOBAL_LOG` in order, like `dmesg`
+pub struct LogReader {
+    next: u64,
+}
+
+impl LogReader {
+    /// Starts at the record `sequence`, or the oldest stored one if it was overwritten
+    pub fn new(sequence: u64) -> Self {
+        Self { next: sequence }
+    }
+
+    /// Copies the text of the next record into `buf` (cut to its length) & returns its metadata
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<Record> {
+        let record = GLOBAL_LOG.lock().read(self.next, buf)?;
+        self.next = record.sequence + 1;
+        Some(record)
+    }
+}
+
+/// Counters of `GLOBAL_LOG`, e.g. the overwritten records & bytes
+pub fn stats() -> RingStats {
+    GLOBAL_LOG.lock().stats()
+}
+
+/// Counters of `GLOBAL_LOG` without waiting for the lock, for the panic handler
+pub fn try_stats() -> Option<RingStats> {
+    GLOBAL_LOG.try_lock().map(|log| log.stats())
+}
+
+/// Replays all records from `sequence` on as `sequence timestamp | text` to `sink`. Records added
+/// during the replay are not included.
+pub fn dmesg(sequence: u64, sink: &dyn Sink) {
+    let end = stats().next_sequence;
+    let mut reader = LogReader::new(sequence);
+    let mut buf = [0; RECORD_MAX_LENGTH];
+    while let Some(record) = reader.read(&mut buf) {
+        if record.sequence >= end {
+            break;
+        }
+        write_dmesg(&mut SinkWriter(sink), record, &buf);
+    }
+}
+
+/// Writes the newest records before `end` like `dmesg()`, as many as fit into `max` bytes. Does
+/// not wait for the log lock, returns false if it is held.
+pub fn emergency_tail(end: u64, max: usize, writer: &mut dyn Write) -> bool {
+    let log = match GLOBAL_LOG.try_lock() {
+        Some(log) => log,
+        None => return false,
+    };
+    let mut sequence = log.tail(end, max, DMESG_PREFIX_MAX);
+    let mut buf = [0; RECORD_MAX_LENGTH];
+    while let Some(record) = log.read(sequence, &mut buf) {
+        if record.sequence >= end {
+            break;
+        }
+        write_dmesg(writer, record, &buf);
+        sequence = record.sequence + 1;
+    }
+    true
+}
+
+/// Max ammount of bytes `write_dmesg()` adds to a record: 2 u64, the separators & a line feed
+const DMESG_PREFIX_MAX: usize = 2 * 20 + 4 + 1;
+
+/// writes a record read into `buf` as `sequence timestamp | text`
+fn write_dmesg(writer: &mut dyn Write, record: Record, buf: &[u8]) {
+    let bytes = &buf[..record.length.min(buf.len())];
+    // long records are split at any byte, drop a cut off character
+    let text = match core::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+    };
+    write!(
+        writer,
+        "{} {} | {}",
+        record.sequence, record.timestamp, text
+    )
+    .ok();
+    if !text.ends_with('\n') {
+        writer.write_str("\n").ok();
+    }
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+    use std::string::String;
+    use std::sync::Mutex as StdMutex;
+
+    /// collects everything written to it
+    struct TestSink(StdMutex<String>);
+
+    impl Sink for TestSink {
+        fn write(&self, s: &str) {
+            self.0.lock().unwrap().push_str(s);
+        }
+    }
+
+    #[test]
+    fn level_filters() {
+        assert!(LevelFilter::Trace.allows(Level::Error));
+        assert!(LevelFilter::Warn.allows(Level::Warn));
+        assert!(!LevelFilter::Warn.allows(Level::Info));
+        assert!(!LevelFilter::Off.allows(Level::Error));
+        assert_eq!(strip_crate("kernel::memman::map"), "memman::map");
+        assert_eq!(strip_crate("kernel"), "kernel");
+    }
+
+    #[test]
+    fn sink_registry() {
+        static SINK: TestSink = TestSink(StdMutex::new(String::new()));
+        add_sink("test_registry", &SINK, LevelFilter::Warn).unwrap();
+        assert_eq!(
+            add_sink("test_registry", &SINK, LevelFilter::Warn),
+            Err(SinkError::AlreadyAdded)
+        );
+
+        record(
+            Level::Error,
+            "kernel::log::tests",
+            format_args!("gone {}", 1),
+        );
+        record(
+            Level::Info,
+            "kernel::log::tests",
+            format_args!("too verbose"),
+        );
+        crate::log!("raw\n");
+        assert!(set_sink_level("test_registry", LevelFilter::Info));
+        crate::info!("now visible");
+
+        assert!(remove_sink("test_registry").is_some());
+        assert!(remove_sink("test_registry").is_none());
+        crate::error!("not received");
+
+        // other tests may log at the same time
+        let text = SINK.0.lock().unwrap().clone();
+        assert!(text.contains("[ERROR] log::tests: gone 1\n"));
+        assert!(text.contains("raw\n"));
+        assert!(text.contains("[INFO] log::tests: now visible\n"));
+        assert!(!text.contains("too verbose"));
+        assert!(!text.contains("not received"));
+    }
+
+    #[test]
+    fn dmesg_replay() {
+        static OUTPUT: TestSink = TestSink(StdMutex::new(String::new()));
+        add_sink("test_memory", &MemorySink, LevelFilter::Trace).unwrap();
+        let first = stats().next_sequence;
+        crate::warn!("stored {}", 42);
+        remove_sink("test_memory").unwrap();
+
+        dmesg(first, &OUTPUT);
+        let text = OUTPUT.0.lock().unwrap().clone();
+        assert!(text.starts_with(&std::format!("{} ", first)));
+        assert!(text.contains(" | [WARNING] log::tests: stored 42\n"));
+
+        let mut buf = [0; RECORD_MAX_LENGTH];
+        let record = LogReader::new(first).read(&mut buf).unwrap();
+        assert_eq!(record.sequence, first);
+        assert_eq!(&buf[..record.length], b"[WARNING] log::tests: stored 42\n");
+    }
+}
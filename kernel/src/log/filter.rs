@@ -0,0 +1,170 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// LOG FILTER
+// Decides which records are logged based on the module they come from. A filter is parsed from a
+// comma separated spec, e.g. `"info,memman=debug,arch::amd64::idt=off"`: entries without a module
+// set the default level, the longest module prefix wins.
+
+use super::{Level, LevelFilter};
+
+/// Max ammount of per-module entries in a single `Filter`
+const FILTER_CAPACITY: usize = 16;
+
+/// Errors while parsing a filter spec
+///
+/// ## Variants:
+/// - `UnknownLevel` the level name is not one of off/error/warn/info/debug/trace, holds the entry
+/// - `Full` the spec has more than `FILTER_CAPACITY` module entries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterError {
+    UnknownLevel(&'static str),
+    Full,
+}
+
+/// Per-module log levels, see the module docs for the spec format
+#[derive(Clone, Copy)]
+pub struct Filter {
+    default: LevelFilter,
+    /// (module path without the crate name, max level)
+    modules: [(&'static str, LevelFilter); FILTER_CAPACITY],
+    count: usize,
+}
+
+impl Filter {
+    /// Filter that logs everything up to `default` in every module
+    pub const fn new(default: LevelFilter) -> Self {
+        Self {
+            default,
+            modules: [("", LevelFilter::Off); FILTER_CAPACITY],
+            count: 0,
+        }
+    }
+
+    /// Parses a spec like `"info,memman=debug"`, whitespace around entries is ignored
+    pub fn parse(spec: &'static str) -> Result<Self, FilterError> {
+        let mut filter = Self::new(LevelFilter::Info);
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=') {
+                Some((module, level)) => {
+                    let level =
+                        parse_level(level.trim()).ok_or(FilterError::UnknownLevel(entry))?;
+                    filter.set(module.trim(), level)?;
+                }
+                None => {
+                    filter.default = parse_level(entry).ok_or(FilterError::UnknownLevel(entry))?;
+                }
+            }
+        }
+        Ok(filter)
+    }
+
+    /// Sets the max level of `module` & all its submodules, replaces an existing entry
+    pub fn set(&mut self, module: &'static str, level: LevelFilter) -> Result<(), FilterError> {
+        if let Some(entry) = self.modules[..self.count]
+            .iter_mut()
+            .find(|(m, _)| *m == module)
+        {
+            entry.1 = level;
+            return Ok(());
+        }
+        if self.count >= FILTER_CAPACITY {
+            return Err(FilterError::Full);
+        }
+        self.modules[self.count] = (module, level);
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Max level of `module`, the path must not contain the crate name
+    pub fn level(&self, module: &str) -> LevelFilter {
+        let mut best: Option<(&str, LevelFilter)> = None;
+        for &(prefix, level) in &self.modules[..self.count] {
+            let matches = module == prefix
+                || (module.starts_with(prefix) && module[prefix.len()..].starts_with("::"));
+            if matches && best.map_or(true, |(b, _)| prefix.len() > b.len()) {
+                best = Some((prefix, level));
+            }
+        }
+        best.map_or(self.default, |(_, level)| level)
+    }
+
+    /// true if a record of `level` from `module` passes the filter
+    pub fn enabled(&self, level: Level, module: &str) -> bool {
+        self.level(module).allows(level)
+    }
+}
+
+fn parse_level(name: &str) -> Option<LevelFilter> {
+    Some(match name {
+        "off" => LevelFilter::Off,
+        "error" => LevelFilter::Error,
+        "warn" => LevelFilter::Warn,
+        "info" => LevelFilter::Info,
+        "debug" => LevelFilter::Debug,
+        "trace" => LevelFilter::Trace,
+        _ => return None,
+    })
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_level() {
+        let filter = Filter::parse("warn").unwrap();
+        assert!(filter.enabled(Level::Error, "memman::map"));
+        assert!(filter.enabled(Level::Warn, "memman::map"));
+        assert!(!filter.enabled(Level::Info, "memman::map"));
+        // empty spec keeps info
+        let filter = Filter::parse("").unwrap();
+        assert!(filter.enabled(Level::Info, "log"));
+        assert!(!filter.enabled(Level::Debug, "log"));
+    }
+
+    #[test]
+    fn module_levels() {
+        let filter = Filter::parse(" error , memman=debug,memman::map=off ").unwrap();
+        assert!(!filter.enabled(Level::Warn, "arch::amd64"));
+        assert!(filter.enabled(Level::Debug, "memman"));
+        assert!(filter.enabled(Level::Debug, "memman::frame"));
+        assert!(!filter.enabled(Level::Trace, "memman::frame"));
+        assert!(!filter.enabled(Level::Error, "memman::map"));
+        assert!(!filter.enabled(Level::Error, "memman::map::buddy"));
+        // only whole path segments match
+        assert!(!filter.enabled(Level::Debug, "memmanager"));
+    }
+
+    #[test]
+    fn set_replaces_entries() {
+        let mut filter = Filter::parse("info,log=trace").unwrap();
+        filter.set("log", LevelFilter::Error).unwrap();
+        assert!(!filter.enabled(Level::Warn, "log"));
+        assert_eq!(filter.count, 1);
+    }
+
+    #[test]
+    fn invalid_specs() {
+        assert_eq!(
+            Filter::parse("info,memman=loud").err(),
+            Some(FilterError::UnknownLevel("memman=loud"))
+        );
+        assert_eq!(
+            Filter::parse("verbose").err(),
+            Some(FilterError::UnknownLevel("verbose"))
+        );
+        let mut filter = Filter::new(LevelFilter::Info);
+        for module in ["a", "b", "c", "d", "e", "f", "g", "h"] {
+            filter.set(module, LevelFilter::Off).unwrap();
+        }
+        for module in ["i", "j", "k", "l", "m", "n", "o", "p"] {
+            filter.set(module, LevelFilter::Off).unwrap();
+        }
+        assert_eq!(filter.set("q", LevelFilter::Off), Err(FilterError::Full));
+    }
+}
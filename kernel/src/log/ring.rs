@@ -0,0 +1,334 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// RING LOG
+// `GlobalLog` implementation that stores every line as a record in a fixed size ring buffer. When
+// the buffer is full the oldest records are overwritten & counted as dropped, so logging never
+// fails. Every record starts with a header holding its sequence number, timestamp & length.
+
+use crate::arch::cpu;
+use core::fmt;
+use core::fmt::Write;
+
+/// Bytes of a record header: sequence (u64), timestamp (u64), length (u16)
+const HEADER_SIZE: usize = 18;
+
+/// Max ammount of text bytes in a single record, longer lines are split into several records
+pub const RECORD_MAX_LENGTH: usize = 1024;
+
+/// Metadata of a record in a `RingLog`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    /// increases by one for every record, starting at 0
+    pub sequence: u64,
+    /// `arch::cpu::read_timestamp()` when the record was started
+    pub timestamp: u64,
+    /// length of the text in bytes
+    pub length: usize,
+}
+
+/// Counters of a `RingLog`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RingStats {
+    /// records currently stored
+    pub records: usize,
+    /// bytes used by stored records, headers included. Needed as `head == tail` is both empty and
+    /// full
+    pub used: usize,
+    /// records overwritten since boot
+    pub dropped_records: u64,
+    /// text bytes of the overwritten records
+    pub dropped_bytes: u64,
+    /// sequence number of the next record
+    pub next_sequence: u64,
+}
+
+/// Ring buffer of log records with `N` bytes of storage, see the module docs
+pub struct RingLog<const N: usize> {
+    buffer: [u8; N],
+    /// offset of the oldest record
+    head: usize,
+    /// offset where the next byte is written
+    tail: usize,
+    /// offset of the header of the unfinished last record
+    open: Option<usize>,
+    stats: RingStats,
+}
+
+impl<const N: usize> RingLog<N> {
+    /// const, so a large `RingLog` can be placed in a static without passing through the stack
+    pub const fn new() -> Self {
+        assert!(N >= 2 * (HEADER_SIZE + RECORD_MAX_LENGTH));
+        Self {
+            buffer: [0; N],
+            head: 0,
+            tail: 0,
+            open: None,
+            stats: RingStats {
+                records: 0,
+                used: 0,
+                dropped_records: 0,
+                dropped_bytes: 0,
+                next_sequence: 0,
+            },
+        }
+    }
+
+    pub fn stats(&self) -> RingStats {
+        self.stats
+    }
+
+    /// Copies the text of the first stored record with a sequence number >= `sequence` into
+    /// `buf`, cut to its length. Older records may be overwritten already, compare the returned
+    /// sequence number to detect the gap.
+    pub fn read(&self, sequence: u64, buf: &mut [u8]) -> Option<Record> {
+        let mut offset = self.head;
+        for _ in 0..self.stats.records {
+            let record = self.header(offset);
+            if record.sequence >= sequence {
+                let length = record.length.min(buf.len());
+                self.copy_out(self.wrap(offset + HEADER_SIZE), &mut buf[..length]);
+                return Some(record);
+            }
+            offset = self.wrap(offset + HEADER_SIZE + record.length);
+        }
+        None
+    }
+
+    /// Returns the sequence number of the oldest stored record from which on the records before
+    /// `end` fit into `max` bytes, counting `overhead` extra bytes per record for formatting
+    pub fn tail(&self, end: u64, max: usize, overhead: usize) -> u64 {
+        let mut total = 0;
+        let mut offset = self.head;
+        for _ in 0..self.stats.records {
+            let record = self.header(offset);
+            if record.sequence < end {
+                total += record.length + overhead;
+            }
+            offset = self.wrap(offset + HEADER_SIZE + record.length);
+        }
+
+        // drop the oldest records until the rest fits
+        offset = self.head;
+        for _ in 0..self.stats.records {
+            let record = self.header(offset);
+            if total <= max || record.sequence >= end {
+                return record.sequence;
+            }
+            total -= record.length + overhead;
+            offset = self.wrap(offset + HEADER_SIZE + record.length);
+        }
+        end
+    }
+
+    /// appends text to the open record, lines are split into records at '\n'
+    fn append(&mut self, s: &[u8]) {
+        let mut rest = s;
+        while !rest.is_empty() {
+            let header = match self.open {
+                Some(header) => header,
+                None => self.start_record(),
+            };
+            let mut record = self.header(header);
+            let space = RECORD_MAX_LENGTH - record.length;
+            let line = match rest.iter().position(|&b| b == b'\n') {
+                Some(i) => i + 1,
+                None => rest.len(),
+            };
+            let count = line.min(space);
+
+            self.reserve(count);
+            self.copy_in(self.tail, &rest[..count]);
+            self.tail = self.wrap(self.tail + count);
+            self.stats.used += count;
+            record.length += count;
+            self.write_header(header, record);
+
+            if rest[count - 1] == b'\n' || record.length == RECORD_MAX_LENGTH {
+                self.open = None;
+            }
+            rest = &rest[count..];
+        }
+    }
+
+    /// writes the header of a new record & returns its offset
+    fn start_record(&mut self) -> usize {
+        self.reserve(HEADER_SIZE);
+        let header = self.tail;
+        let record = Record {
+            sequence: self.stats.next_sequence,
+            timestamp: cpu::read_timestamp(),
+            length: 0,
+        };
+        self.write_header(header, record);
+        self.tail = self.wrap(self.tail + HEADER_SIZE);
+        self.stats.used += HEADER_SIZE;
+        self.open = Some(header);
+        self.stats.next_sequence += 1;
+        self.stats.records += 1;
+        header
+    }
+
+    /// overwrites the oldest records until `size` bytes are free
+    fn reserve(&mut self, size: usize) {
+        while N - self.stats.used < size {
+            // the open record is at most half of the buffer, it is never the oldest one here
+            debug_assert!(Some(self.head) != self.open);
+            let record = self.header(self.head);
+            self.head = self.wrap(self.head + HEADER_SIZE + record.length);
+            self.stats.used -= HEADER_SIZE + record.length;
+            self.stats.records -= 1;
+            self.stats.dropped_records += 1;
+            self.stats.dropped_bytes += record.length as u64;
+        }
+    }
+
+    fn header(&self, offset: usize) -> Record {
+        let mut raw = [0; HEADER_SIZE];
+        self.copy_out(offset, &mut raw);
+        Record {
+            sequence: u64::from_le_bytes(raw[0..8].try_into().unwrap()),
+            timestamp: u64::from_le_bytes(raw[8..16].try_into().unwrap()),
+            length: u16::from_le_bytes(raw[16..18].try_into().unwrap()) as usize,
+        }
+    }
+
+    fn write_header(&mut self, offset: usize, record: Record) {
+        let mut raw = [0; HEADER_SIZE];
+        raw[0..8].copy_from_slice(&record.sequence.to_le_bytes());
+        raw[8..16].copy_from_slice(&record.timestamp.to_le_bytes());
+        raw[16..18].copy_from_slice(&(record.length as u16).to_le_bytes());
+        self.copy_in(offset, &raw);
+    }
+
+    /// copies into the buffer at `offset`, wrapping around the end
+    fn copy_in(&mut self, offset: usize, bytes: &[u8]) {
+        let first = bytes.len().min(N - offset);
+        self.buffer[offset..offset + first].copy_from_slice(&bytes[..first]);
+        self.buffer[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+    }
+
+    /// copies out of the buffer at `offset`, wrapping around the end
+    fn copy_out(&self, offset: usize, bytes: &mut [u8]) {
+        let first = bytes.len().min(N - offset);
+        let len = bytes.len();
+        bytes[..first].copy_from_slice(&self.buffer[offset..offset + first]);
+        bytes[first..].copy_from_slice(&self.buffer[..len - first]);
+    }
+
+    fn wrap(&self, offset: usize) -> usize {
+        offset % N
+    }
+}
+
+impl<const N: usize> Write for RingLog<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.append(s.as_bytes());
+        Ok(())
+    }
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+
+    /// room for a few full records
+    const SIZE: usize = 4 * (HEADER_SIZE + RECORD_MAX_LENGTH);
+
+    fn text<const N: usize>(
+        log: &RingLog<N>,
+        sequence: u64,
+    ) -> Option<(Record, std::string::String)> {
+        let mut buf = [0; RECORD_MAX_LENGTH];
+        let record = log.read(sequence, &mut buf)?;
+        let text = std::str::from_utf8(&buf[..record.length]).unwrap().into();
+        Some((record, text))
+    }
+
+    #[test]
+    fn records_are_lines() {
+        let mut log = RingLog::<SIZE>::new();
+        write!(log, "first {}", 1).unwrap();
+        write!(log, " line\nsecond\nthird").unwrap();
+        assert_eq!(text(&log, 0).unwrap().1, "first 1 line\n");
+        assert_eq!(text(&log, 1).unwrap().1, "second\n");
+        assert_eq!(text(&log, 2).unwrap().1, "third");
+        // the open record grows
+        log.write_str(" continued\n").unwrap();
+        let (record, third) = text(&log, 2).unwrap();
+        assert_eq!(third, "third continued\n");
+        assert_eq!(record.sequence, 2);
+        assert!(text(&log, 3).is_none());
+
+        let stats = log.stats();
+        assert_eq!(stats.records, 3);
+        assert_eq!(stats.next_sequence, 3);
+        assert_eq!(stats.used, 3 * HEADER_SIZE + 13 + 7 + 16);
+        assert_eq!(stats.dropped_records, 0);
+        // timestamps do not go backwards
+        assert!(text(&log, 0).unwrap().0.timestamp <= record.timestamp);
+    }
+
+    #[test]
+    fn long_lines_are_split() {
+        let mut log = RingLog::<SIZE>::new();
+        let line = "x".repeat(RECORD_MAX_LENGTH + 10) + "\n";
+        log.write_str(&line).unwrap();
+        assert_eq!(text(&log, 0).unwrap().0.length, RECORD_MAX_LENGTH);
+        assert_eq!(text(&log, 1).unwrap().0.length, 11);
+    }
+
+    #[test]
+    fn oldest_records_are_overwritten() {
+        let mut log = RingLog::<SIZE>::new();
+        for i in 0..1000 {
+            writeln!(log, "record number {}", i).unwrap();
+        }
+        let stats = log.stats();
+        assert_eq!(stats.next_sequence, 1000);
+        assert_eq!(stats.records as u64 + stats.dropped_records, 1000);
+        assert!(stats.used <= SIZE);
+        assert!(stats.dropped_bytes > 0);
+
+        // reading a dropped sequence returns the oldest stored record
+        let oldest = 1000 - stats.records as u64;
+        let (record, first) = text(&log, 0).unwrap();
+        assert_eq!(record.sequence, oldest);
+        assert_eq!(first, std::format!("record number {}\n", oldest));
+        // every stored record is intact across the wrap around
+        for i in oldest..1000 {
+            assert_eq!(
+                text(&log, i).unwrap().1,
+                std::format!("record number {}\n", i)
+            );
+        }
+    }
+
+    #[test]
+    fn tail_fits() {
+        let mut log = RingLog::<SIZE>::new();
+        for i in 0..10 {
+            writeln!(log, "line {}", i).unwrap();
+        }
+        // every record is 7 bytes long
+        assert_eq!(log.tail(10, 1000, 0), 0);
+        assert_eq!(log.tail(10, 21, 0), 7);
+        assert_eq!(log.tail(10, 20, 0), 8);
+        assert_eq!(log.tail(5, 14, 3), 4);
+        assert_eq!(log.tail(10, 0, 0), 10);
+    }
+
+    #[test]
+    fn short_buffers_truncate() {
+        let mut log = RingLog::<SIZE>::new();
+        log.write_str("abcdef\n").unwrap();
+        let mut buf = [0; 3];
+        let record = log.read(0, &mut buf).unwrap();
+        assert_eq!(record.length, 7);
+        assert_eq!(&buf, b"abc");
+    }
+}
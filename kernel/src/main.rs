@@ -6,16 +6,24 @@
 
 //! The core part of the kernel written in rust. It compiles to a static library that then gets linked to `kentry` to produce the binary.
 
-#![no_std]
-#![no_main]
+// only the host unit tests are built with std, see `make test-host`
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
 #![crate_type = "staticlib"]
-#![feature(layout_for_ptr)]
 // required by tools.rs
 #![feature(const_convert)]
 #![feature(const_trait_impl)]
 #![feature(const_mut_refs)]
 // required by panic handler
 #![feature(panic_info_message)]
+// required by memman/heap.rs
+#![feature(alloc_error_handler)]
+// required by arch/amd64/syscall.rs
+#![feature(asm_const)]
+// kernel tests booted in QEMU, see testing.rs
+#![feature(custom_test_frameworks)]
+#![cfg_attr(target_os = "none", test_runner(crate::testing::runner))]
+#![cfg_attr(target_os = "none", reexport_test_harness_main = "test_main")]
 
 /*
 // required by const-bitfields
@@ -24,61 +32,136 @@
 #![feature(const_trait_impl)] // always required
 */
 
+extern crate alloc;
+
+#[cfg(target_os = "none")]
+use core::fmt::Write;
 use core::panic::{self, PanicInfo};
+#[cfg(target_os = "none")]
+use core::sync::atomic::{AtomicUsize, Ordering};
 // Do not remove these imports, they may useless but they prevent link errors
+#[cfg(target_os = "none")]
 #[allow(unused_imports)]
 use rlibc;
+#[cfg(target_os = "none")]
 use rlibcex;
 
-/// kernel panic handler, uses the `log` module internally
+/// Panics in progress, a panic inside `kpanic()` must not take the same path again
+#[cfg(target_os = "none")]
+static PANIC_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// kernel panic handler, uses `emergency!()` as the panicking code may hold any log lock
+#[cfg(target_os = "none")]
 #[panic_handler]
+#[cfg_attr(test, allow(unreachable_code))]
 fn kpanic(info: &core::panic::PanicInfo<'_>) -> ! {
-    log!("\nKERNEL PANIC!!!\n");
+    // SAFETY: the kernel runs in ring 0 / EL1
+    unsafe { arch::cpu::disable_interrupts() };
+    match PANIC_DEPTH.fetch_add(1, Ordering::SeqCst) {
+        0 => {}
+        // the first panic handler panicked, only touch the serial port
+        1 => {
+            let mut serial = arch::serial::emergency_writer();
+            serial.write_bytes(b"\nKERNEL PANIC WHILE PANICKING!!!\n");
+            if let Some(loc) = info.location() {
+                write!(serial, "Location: {}\n", loc).ok();
+            }
+            halt();
+        }
+        // even the serial port panicked
+        _ => halt(),
+    }
+
+    // a failed kernel test, report it over serial & close QEMU
+    #[cfg(test)]
+    testing::panic(info);
+
+    emergency!("\nKERNEL PANIC!!!\n");
     // payload
     match info.payload().downcast_ref::<&str>() {
-        Some(p) => log!("Payload: {:?}\n", p),
-        None => log!("Payload: unknown\n"),
+        Some(p) => emergency!("Payload: {:?}\n", p),
+        None => emergency!("Payload: unknown\n"),
     }
     // message
     match info.message() {
-        Some(msg) => log!("Message: {:?}\n", msg),
-        None => log!("Message: unknown\n"),
+        Some(msg) => emergency!("Message: {:?}\n", msg),
+        None => emergency!("Message: unknown\n"),
     }
     // location
     match info.location() {
-        Some(loc) => log!("Location: {}\n", loc),
-        None => log!("Location: unknown"),
+        Some(loc) => emergency!("Location: {}\n", loc),
+        None => emergency!("Location: unknown\n"),
     }
+    arch::cpu::dump_registers();
+    backtrace::print();
 
-    loop {}
+    crashdump::finish();
+    if config::GDB_STUB {
+        gdb::breakpoint();
+    }
+    if config::PANIC_REBOOT {
+        // SAFETY: the kernel runs in ring 0 / EL1
+        unsafe { arch::cpu::reset() };
+    }
+    halt();
+}
+
+/// stops the cpu forever, interrupts must be disabled
+#[cfg(target_os = "none")]
+fn halt() -> ! {
+    loop {
+        unsafe { arch::cpu::halt() };
+    }
 }
 
 /// contains architecture specific code.
 pub mod arch;
+/// stack walking & kernel symbol names for panics.
+pub mod backtrace;
 /// Generated by `config.sh`
 pub mod config;
+/// crash reports that survive a reboot.
+pub mod crashdump;
+/// remote debugging with gdb over the serial port.
+pub mod gdb;
 /// This module handles all things limine.
 pub mod limine;
 /// Handles logging info in the kernel runtime.
 pub mod log;
 /// handles memory managment.
 pub mod memman;
+/// interactive debug monitor on the serial console & keyboard.
+pub mod monitor;
+/// system call table used by user programs.
+pub mod syscall;
+/// locks that are safe to use in interrupt handlers.
+pub mod sync;
 /// contains various utilities used everywhere.
 pub mod tools;
+/// drawing on the framebuffers of the bootloader.
+pub mod video;
+/// runs the `#[test_case]` functions in QEMU.
+#[cfg(all(test, target_os = "none"))]
+mod testing;
 
+#[cfg(target_os = "none")]
 use memman::map::{MapArea, MemoryMapper};
+#[cfg(target_os = "none")]
 use tinyvec::ArrayVec;
 
 /// kernel main function called & linked by `kentry`
 
+#[cfg(target_os = "none")]
 #[no_mangle]
 pub extern "C" fn kmain() {
     log!("{}", config::MESSAGE_FIRST);
 
     // hardware
-    let boot_time = limine::boot_time_stamp();
     log!("[ Hardware Info ]\n");
-    log!("UNIX Boot time: {}\n", boot_time);
+    match limine::boot_time_stamp() {
+        Ok(boot_time) => log!("UNIX Boot time: {}\n", boot_time),
+        Err(e) => warn!("Boot time unknown: {:?}", e),
+    }
 
     // arch
     log!("CPU Architecture: ");
@@ -87,15 +170,50 @@ pub extern "C" fn kmain() {
         arch::ArchType::AArch64 => log!("arm64/AArch64\n"),
     };
     arch::init();
+    if config::GDB_STUB {
+        gdb::init();
+        info!("GDB stub enabled on the serial port");
+    }
 
     // boot loader
-    let (bootloader_name, bootloader_version) = limine::bootloader_info();
     log!("[ Bootloader info ]\n");
-    log!("name: {}\n", core::str::from_utf8(bootloader_name).unwrap());
-    log!(
-        "version: {}\n",
-        core::str::from_utf8(bootloader_version).unwrap()
-    );
+    match limine::bootloader_info() {
+        Ok((bootloader_name, bootloader_version)) => {
+            log!("name: {}\n", core::str::from_utf8(bootloader_name).unwrap());
+            log!(
+                "version: {}\n",
+                core::str::from_utf8(bootloader_version).unwrap()
+            );
+        }
+        Err(e) => warn!("Bootloader unknown: {:?}", e),
+    }
+    // the features limine answered, the required ones panic on first use if missing
+    for feature in limine::features() {
+        match feature {
+            Ok((name, revision)) => log!("feature: {} (revision {})\n", name, revision),
+            Err(e) => warn!("Limine feature unavailable: {:?}", e),
+        }
+    }
+
+    // displays
+    log!("[ Framebuffers ]\n");
+    if let Ok(framebuffers) = limine::framebuffers() {
+        for (i, fb) in framebuffers.enumerate() {
+            let mode = fb.mode;
+            log!(
+                "{}: {}x{} {}bpp, pitch {}, EDID {} bytes\n",
+                i,
+                mode.width,
+                mode.height,
+                mode.bpp,
+                mode.pitch,
+                fb.edid.len()
+            );
+            for mode in fb.modes() {
+                debug!("{}: mode {}x{} {}bpp", i, mode.width, mode.height, mode.bpp);
+            }
+        }
+    }
 
     // memory map
     log!("[ Memory Map ]\n");
@@ -112,7 +230,7 @@ pub extern "C" fn kmain() {
                 let ma = memman::map::claim_global(region.range)
                     .expect("Limine map entry could not be claimed!");
                 if let Some(_) = map_area_pool.try_push(ma) {
-                    log!("[ERROR] map_area_pool is full, limine entry will be dropped!\n");
+                    error!("map_area_pool is full, limine entry will be dropped!");
                 }
             }
         }
@@ -121,14 +239,38 @@ pub extern "C" fn kmain() {
         log!("{} 0x{:X} - 0x{:X}\n", print_typ, start, end);
     }
 
-    /*
-    // log GLOBAL_MEMORY_MAPPER entries
-    use memman::map::MemoryMapper;
-    for a in memman::map::GLOBAL_MEMORY_MAPPER.get().unwrap().iter() {
-        limine::print_hex(a.0);
-        log!(b"\n");
+    // crash report of the previous boot, before anything else claims usable memory
+    if let Err(e) = crashdump::init() {
+        warn!("Crash dumps are disabled: {:?}", e);
+    }
+
+    // page tables
+    #[cfg(target_arch = "x86_64")]
+    {
+        let mut frames = memman::paging::BootstrapFrames::claim(config::PAGING_BOOTSTRAP_SIZE)
+            .expect("Could not claim memory for the kernel page tables!");
+        memman::paging::init(&mut frames);
+        frames.finish();
+    }
+
+    // physical frames
+    memman::frame::init();
+    let frame_stats = memman::frame::stats_global();
+    info!(
+        "Frames: {} total, {} free, {} used",
+        frame_stats.total,
+        frame_stats.free,
+        frame_stats.used
+    );
+
+    // kernel tests, see `make test-x86_64`
+    #[cfg(test)]
+    test_main();
+
+    // console on framebuffer 0, once the kernel tests are done with it
+    if let Err(e) = video::console::init() {
+        warn!("Framebuffer console unavailable, staying on the limine terminal: {:?}", e);
     }
-    */
 
     // kernel address
     let kernel_physical_address = limine::kernel_address_physical();
@@ -143,6 +285,14 @@ pub extern "C" fn kmain() {
     let hhdm = limine::hhdm();
     log!("HHDM: 0x{:016X}\n", hhdm);
 
+    // booted, scrolling over the splash would redraw the whole screen
+    video::console::remove_backdrop();
+
+    // inspect the memory maps, page tables & log, see `monitor.rs`
+    if config::DEBUG_MONITOR {
+        monitor::run();
+    }
+
     log!("Nothing to do!\n");
     panic!("Nothing to do!");
 }
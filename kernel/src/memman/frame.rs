@@ -0,0 +1,365 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! This module hands out physical page frames from the usable memory claimed in the global map
+
+use super::map::{self, MapArea, MemoryMapper};
+use crate::limine;
+use crate::sync::IrqMutex;
+use crate::{error, warn};
+use core::slice;
+use spin::once::Once;
+use tinyvec::ArrayVec;
+
+/// Size of a single physical frame
+pub const FRAME_SIZE: usize = 4096;
+
+/// Max ammount of separate usable regions managed by `FrameAllocator`
+const MAX_ZONES: usize = 32;
+
+/// Bits in a single bitmap word
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// Global frame allocator for the whole kernel runtime
+pub static GLOBAL_FRAME_ALLOCATOR: Once<FrameAllocator> = Once::new();
+
+/// Seeds the global frame allocator with all usable memory that has not been claimed yet.
+///
+/// WARNING: must be called after all bootloader & kernel regions have been claimed in the global map
+pub fn init() {
+    GLOBAL_FRAME_ALLOCATOR.call_once(|| {
+        let allocator = FrameAllocator::new();
+        let gaps = map::GLOBAL_MEMORY_MAPPER
+            .get()
+            .expect("GLOBAL_MEMORY_MAPPER not setup!")
+            .gaps();
+        for gap in gaps {
+            // gaps also cover holes that are not backed by RAM
+            for region in limine::memory_map() {
+                if let limine::MemmapEntryType::Usable = region.typ {
+                    let start = align_up(gap.0.max(region.range.0), FRAME_SIZE);
+                    let end = gap.1.min(region.range.1) & !(FRAME_SIZE - 1);
+                    if start >= end {
+                        continue;
+                    }
+                    match map::claim_global((start, end)) {
+                        Ok(area) => allocator.add_region(area),
+                        Err(e) => warn!("Usable region could not be claimed: {:?}", e),
+                    }
+                }
+            }
+        }
+        allocator
+    });
+}
+
+/// Allocates a single frame from the global frame allocator
+pub fn allocate_global() -> Option<FrameArea> {
+    get_global().allocate()
+}
+
+/// Allocates `count` contiguous frames, starting at an address aligned to `align` bytes
+pub fn allocate_contiguous_global(count: usize, align: usize) -> Option<FrameArea> {
+    get_global().allocate_contiguous(count, align)
+}
+
+/// Returns frames to the global frame allocator
+pub fn free_global(frames: FrameArea) {
+    get_global().free(frames)
+}
+
+/// Get the frame statistics of the global frame allocator
+pub fn stats_global() -> FrameStats {
+    get_global().stats()
+}
+
+fn get_global() -> &'static FrameAllocator {
+    GLOBAL_FRAME_ALLOCATOR
+        .get()
+        .expect("GLOBAL_FRAME_ALLOCATOR not setup!")
+}
+
+const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// Capability representing ownage of contiguous physical frames, similar to `MapArea`
+pub struct FrameArea {
+    start: usize,
+    count: usize,
+}
+
+impl FrameArea {
+    /// Recreates a token that has been leaked with `into_raw()`
+    ///
+    /// ## SAFETY: the frames must have been allocated and not owned by another `FrameArea`
+    pub unsafe fn from_raw(start: usize, count: usize) -> Self {
+        Self { start, count }
+    }
+
+    /// Leaks the token, returns `(start, count)`. The frames stay allocated.
+    pub fn into_raw(self) -> (usize, usize) {
+        let raw = (self.start, self.count);
+        core::mem::forget(self);
+        raw
+    }
+
+    /// physical address of the first frame
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// first physical address after the last frame
+    pub fn end(&self) -> usize {
+        self.start + self.size()
+    }
+
+    /// number of frames
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// size in bytes
+    pub fn size(&self) -> usize {
+        self.count * FRAME_SIZE
+    }
+}
+
+impl Drop for FrameArea {
+    // same as MapArea, dropped frames can never be allocated again
+    fn drop(&mut self) {
+        warn!(
+            "Dropping FrameArea handle for frames {:016X} - {:016X}!",
+            self.start,
+            self.end()
+        )
+    }
+}
+
+/// Frame counters returned by `FrameAllocator::stats()`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    pub used: usize,
+}
+
+/// Contiguous usable region, the allocation bitmap is stored in its first frames
+#[derive(Default)]
+struct Zone {
+    _area: MapArea,
+    /// physical address of the first allocatable frame
+    start: usize,
+    /// number of allocatable frames
+    frames: usize,
+    free: usize,
+    /// physical address of the bitmap, 1 bit per frame, set -> used
+    bitmap: usize,
+}
+
+impl Zone {
+    /// Takes over a claimed region, returns None if it is too small to hold a bitmap and a frame
+    fn new(area: MapArea) -> Option<Self> {
+        let region = area.region();
+        let available = (region.1 - region.0) / FRAME_SIZE;
+        let words = (available + WORD_BITS - 1) / WORD_BITS;
+        let bitmap_frames = (words * 8 + FRAME_SIZE - 1) / FRAME_SIZE;
+        if available <= bitmap_frames {
+            // the region stays claimed but unused
+            core::mem::forget(area);
+            return None;
+        }
+        let zone = Self {
+            _area: area,
+            start: region.0 + bitmap_frames * FRAME_SIZE,
+            frames: available - bitmap_frames,
+            free: available - bitmap_frames,
+            bitmap: region.0,
+        };
+        zone.bitmap().fill(0);
+        Some(zone)
+    }
+
+    fn bitmap(&self) -> &'static mut [u64] {
+        let words = (self.frames + WORD_BITS - 1) / WORD_BITS;
+        // SAFETY: the bitmap lies in the claimed area & is only accessed under the allocator lock
+        unsafe { slice::from_raw_parts_mut((self.bitmap + limine::hhdm()) as *mut u64, words) }
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap()[frame / WORD_BITS] & (1 << (frame % WORD_BITS)) != 0
+    }
+
+    fn set_used(&mut self, frame: usize, used: bool) {
+        let word = &mut self.bitmap()[frame / WORD_BITS];
+        if used {
+            *word |= 1 << (frame % WORD_BITS);
+        } else {
+            *word &= !(1 << (frame % WORD_BITS));
+        }
+    }
+
+    fn contains(&self, start: usize, count: usize) -> bool {
+        start >= self.start && start + count * FRAME_SIZE <= self.start + self.frames * FRAME_SIZE
+    }
+
+    /// Finds & marks `count` free frames with an aligned start address
+    fn allocate(&mut self, count: usize, align: usize) -> Option<usize> {
+        if count > self.free {
+            return None;
+        }
+        let step = align / FRAME_SIZE;
+        // first frame index with an aligned address
+        let mut first = (align_up(self.start, align) - self.start) / FRAME_SIZE;
+        while first + count <= self.frames {
+            match (first..first + count).rev().find(|&f| self.is_used(f)) {
+                // skip past the used frame to the next aligned index
+                Some(used) => first += ((used - first) / step + 1) * step,
+                None => {
+                    for f in first..first + count {
+                        self.set_used(f, true);
+                    }
+                    self.free -= count;
+                    return Some(self.start + first * FRAME_SIZE);
+                }
+            }
+        }
+        None
+    }
+
+    fn free(&mut self, start: usize, count: usize) {
+        let first = (start - self.start) / FRAME_SIZE;
+        for f in first..first + count {
+            if !self.is_used(f) {
+                panic!("Poisoned FrameArea could not be freed!");
+            }
+            self.set_used(f, false);
+        }
+        self.free += count;
+    }
+}
+
+/// Bitmap based allocator of physical frames
+pub struct FrameAllocator {
+    zones: IrqMutex<ArrayVec<[Zone; MAX_ZONES]>>,
+}
+
+impl FrameAllocator {
+    pub fn new() -> Self {
+        Self {
+            zones: IrqMutex::new(ArrayVec::new()),
+        }
+    }
+
+    /// Hands a claimed region over to the allocator, it must be aligned to `FRAME_SIZE`
+    pub fn add_region(&self, area: MapArea) {
+        let mut zones = self.zones.lock();
+        if zones.len() >= MAX_ZONES {
+            error!("FrameAllocator zones are full, region will be leaked!");
+            core::mem::forget(area);
+            return;
+        }
+        if let Some(zone) = Zone::new(area) {
+            zones.push(zone);
+        }
+    }
+
+    /// Allocates a single frame
+    pub fn allocate(&self) -> Option<FrameArea> {
+        self.allocate_contiguous(1, FRAME_SIZE)
+    }
+
+    /// Allocates `count` contiguous frames, starting at an address aligned to `align` bytes.
+    /// `align` must be a power of two, values below `FRAME_SIZE` are rounded up.
+    pub fn allocate_contiguous(&self, count: usize, align: usize) -> Option<FrameArea> {
+        assert!(
+            align.is_power_of_two(),
+            "Frame alignment must be a power of two!"
+        );
+        if count == 0 {
+            return None;
+        }
+        let align = align.max(FRAME_SIZE);
+        self.zones
+            .lock()
+            .iter_mut()
+            .find_map(|zone| zone.allocate(count, align))
+            .map(|start| FrameArea { start, count })
+    }
+
+    /// Returns frames to the allocator
+    ///
+    /// ## WARNING: panics if the frames were not allocated, see `MemoryMapper::free()`
+    pub fn free(&self, frames: FrameArea) {
+        let (start, count) = frames.into_raw();
+        match self
+            .zones
+            .lock()
+            .iter_mut()
+            .find(|zone| zone.contains(start, count))
+        {
+            Some(zone) => zone.free(start, count),
+            None => panic!("Poisoned FrameArea could not be freed!"),
+        }
+    }
+
+    /// Get the current frame counters
+    pub fn stats(&self) -> FrameStats {
+        let zones = self.zones.lock();
+        let total = zones.iter().map(|zone| zone.frames).sum();
+        let free = zones.iter().map(|zone| zone.free).sum();
+        FrameStats {
+            total,
+            free,
+            used: total - free,
+        }
+    }
+}
+
+// kernel tests, see `make test-x86_64`
+#[cfg(all(test, target_os = "none"))]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn allocate_and_free() {
+        let before = stats_global();
+        let frame = allocate_global().expect("no free frame");
+        assert_eq!(frame.start() % FRAME_SIZE, 0);
+        assert_eq!(frame.count(), 1);
+        assert_eq!(stats_global().used, before.used + 1);
+        free_global(frame);
+        assert_eq!(stats_global().free, before.free);
+    }
+
+    #[test_case]
+    fn frames_are_not_handed_out_twice() {
+        let a = allocate_global().unwrap();
+        let b = allocate_global().unwrap();
+        assert!(a.end() <= b.start() || b.end() <= a.start());
+        free_global(a);
+        free_global(b);
+    }
+
+    #[test_case]
+    fn contiguous_frames_are_aligned() {
+        let frames = allocate_contiguous_global(4, 0x20_0000).expect("no aligned frames");
+        assert_eq!(frames.start() % 0x20_0000, 0);
+        assert_eq!(frames.size(), 4 * FRAME_SIZE);
+        free_global(frames);
+    }
+
+    #[test_case]
+    fn frames_are_reachable_through_the_hhdm() {
+        let frame = allocate_global().unwrap();
+        let memory = unsafe {
+            slice::from_raw_parts_mut((frame.start() + limine::hhdm()) as *mut u64, FRAME_SIZE / 8)
+        };
+        memory.fill(0x5A5A_5A5A_5A5A_5A5A);
+        assert!(memory.iter().all(|&word| word == 0x5A5A_5A5A_5A5A_5A5A));
+        free_global(frame);
+    }
+}
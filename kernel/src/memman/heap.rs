@@ -0,0 +1,252 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Kernel heap used by the `alloc` crate. Small objects are served from per size-class slabs,
+//! bigger ones get their own contiguous frames. All memory is reached through the HHDM.
+
+use super::frame::{self, FrameArea, FRAME_SIZE};
+#[cfg(target_os = "none")]
+use crate::error;
+use crate::limine;
+use crate::sync::IrqMutex;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Block sizes served by the slabs, anything bigger is a large allocation
+const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+// host unit tests use the std allocator
+#[cfg_attr(target_os = "none", global_allocator)]
+static KERNEL_HEAP: KernelHeap = KernelHeap::new();
+
+/// Called by `alloc` when an allocation fails
+#[cfg(target_os = "none")]
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    error!(
+        "Heap allocation of {} bytes (align {}) failed!",
+        layout.size(),
+        layout.align()
+    );
+    panic!("Out of heap memory!");
+}
+
+/// Get the heap counters
+pub fn stats() -> HeapStats {
+    KERNEL_HEAP.stats()
+}
+
+/// Heap counters returned by `stats()`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeapStats {
+    /// bytes requested by live allocations
+    pub allocated: usize,
+    /// frames taken from the frame allocator for slabs
+    pub slab_frames: usize,
+    /// frames currently used by large allocations
+    pub large_frames: usize,
+}
+
+/// Free block inside a slab, the list is stored in the free blocks themselves
+struct FreeBlock {
+    next: Option<NonNull<FreeBlock>>,
+}
+
+/// Free list of a single size class
+struct Slab {
+    block_size: usize,
+    head: Option<NonNull<FreeBlock>>,
+}
+
+// SAFETY: the blocks are only reached through the IrqMutex holding the Slab
+unsafe impl Send for Slab {}
+
+impl Slab {
+    const fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            head: None,
+        }
+    }
+
+    fn push(&mut self, block: NonNull<u8>) {
+        let block = block.cast::<FreeBlock>();
+        unsafe { block.as_ptr().write(FreeBlock { next: self.head }) };
+        self.head = Some(block);
+    }
+
+    fn pop(&mut self) -> Option<NonNull<u8>> {
+        let block = self.head?;
+        self.head = unsafe { block.as_ref().next };
+        Some(block.cast())
+    }
+
+    /// Carves a fresh frame into blocks, returns false if no frame is available
+    fn grow(&mut self) -> bool {
+        let frame = match frame::GLOBAL_FRAME_ALLOCATOR
+            .get()
+            .and_then(|allocator| allocator.allocate())
+        {
+            Some(frame) => frame,
+            None => return false,
+        };
+        // slab frames are never returned
+        let (start, _) = frame.into_raw();
+        let base = start + limine::hhdm();
+        for offset in (0..FRAME_SIZE).step_by(self.block_size).rev() {
+            // SAFETY: base is a valid HHDM address of a whole frame
+            self.push(unsafe { NonNull::new_unchecked((base + offset) as *mut u8) });
+        }
+        true
+    }
+}
+
+/// `GlobalAlloc` implementation backed by `memman::frame`
+struct KernelHeap {
+    slabs: [IrqMutex<Slab>; SIZE_CLASSES.len()],
+    allocated: AtomicUsize,
+    slab_frames: AtomicUsize,
+    large_frames: AtomicUsize,
+}
+
+impl KernelHeap {
+    const fn new() -> Self {
+        Self {
+            slabs: [
+                IrqMutex::new(Slab::new(SIZE_CLASSES[0])),
+                IrqMutex::new(Slab::new(SIZE_CLASSES[1])),
+                IrqMutex::new(Slab::new(SIZE_CLASSES[2])),
+                IrqMutex::new(Slab::new(SIZE_CLASSES[3])),
+                IrqMutex::new(Slab::new(SIZE_CLASSES[4])),
+                IrqMutex::new(Slab::new(SIZE_CLASSES[5])),
+                IrqMutex::new(Slab::new(SIZE_CLASSES[6])),
+                IrqMutex::new(Slab::new(SIZE_CLASSES[7])),
+                IrqMutex::new(Slab::new(SIZE_CLASSES[8])),
+            ],
+            allocated: AtomicUsize::new(0),
+            slab_frames: AtomicUsize::new(0),
+            large_frames: AtomicUsize::new(0),
+        }
+    }
+
+    /// index of the smallest size class that fits `layout`, blocks are aligned to their size
+    fn size_class(layout: &Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        SIZE_CLASSES.iter().position(|&class| class >= size)
+    }
+
+    /// number of frames backing a large allocation
+    fn frame_count(layout: &Layout) -> usize {
+        (layout.size() + FRAME_SIZE - 1) / FRAME_SIZE
+    }
+
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            allocated: self.allocated.load(Ordering::Relaxed),
+            slab_frames: self.slab_frames.load(Ordering::Relaxed),
+            large_frames: self.large_frames.load(Ordering::Relaxed),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let block = match Self::size_class(&layout) {
+            Some(class) => {
+                let mut slab = self.slabs[class].lock();
+                if slab.head.is_none() {
+                    if !slab.grow() {
+                        return ptr::null_mut();
+                    }
+                    self.slab_frames.fetch_add(1, Ordering::Relaxed);
+                }
+                slab.pop().map_or(ptr::null_mut(), NonNull::as_ptr)
+            }
+            None => {
+                let count = Self::frame_count(&layout);
+                match frame::GLOBAL_FRAME_ALLOCATOR
+                    .get()
+                    .and_then(|allocator| allocator.allocate_contiguous(count, layout.align()))
+                {
+                    Some(frames) => {
+                        self.large_frames.fetch_add(count, Ordering::Relaxed);
+                        (frames.into_raw().0 + limine::hhdm()) as *mut u8
+                    }
+                    None => ptr::null_mut(),
+                }
+            }
+        };
+        if !block.is_null() {
+            self.allocated.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        block
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.allocated.fetch_sub(layout.size(), Ordering::Relaxed);
+        match Self::size_class(&layout) {
+            Some(class) => self.slabs[class].lock().push(NonNull::new_unchecked(ptr)),
+            None => {
+                let count = Self::frame_count(&layout);
+                self.large_frames.fetch_sub(count, Ordering::Relaxed);
+                frame::free_global(FrameArea::from_raw(ptr as usize - limine::hhdm(), count));
+            }
+        }
+    }
+}
+
+// kernel tests, see `make test-x86_64`
+#[cfg(all(test, target_os = "none"))]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn small_allocations() {
+        let before = stats().allocated;
+        let value = Box::new(0xDEAD_BEEF_u64);
+        assert_eq!(*value, 0xDEAD_BEEF);
+        assert_eq!(stats().allocated, before + 8);
+        drop(value);
+        assert_eq!(stats().allocated, before);
+    }
+
+    #[test_case]
+    fn every_size_class() {
+        for &size in SIZE_CLASSES.iter() {
+            let layout = Layout::from_size_align(size, size).unwrap();
+            let block = unsafe { KERNEL_HEAP.alloc(layout) };
+            assert!(!block.is_null());
+            assert_eq!(block as usize % size, 0);
+            unsafe {
+                block.write_bytes(0xAB, size);
+                KERNEL_HEAP.dealloc(block, layout);
+            }
+        }
+    }
+
+    #[test_case]
+    fn large_allocations_use_frames() {
+        let before = stats().large_frames;
+        let mut buffer: Vec<u8> = Vec::with_capacity(3 * FRAME_SIZE);
+        buffer.resize(3 * FRAME_SIZE, 7);
+        assert_eq!(stats().large_frames, before + 3);
+        assert!(buffer.iter().all(|&byte| byte == 7));
+        drop(buffer);
+        assert_eq!(stats().large_frames, before);
+    }
+
+    #[test_case]
+    fn freed_blocks_are_reused() {
+        let first = Box::new([0_u8; 64]);
+        let address = &*first as *const _ as usize;
+        drop(first);
+        let second = Box::new([1_u8; 64]);
+        assert_eq!(&*second as *const _ as usize, address);
+    }
+}
@@ -6,13 +6,18 @@
 
 //! This module handles the memory map and claiming physical regions
 
-use crate::log;
-use core::mem;
+use crate::warn;
+use core::{mem, ptr, slice};
 use spin::once::Once;
 use spin::Mutex;
 
-/// Alias for the selected global `MemoryMapper` implementation (at compile time)
-pub type GlobalMemoryMapper = TableMemoryMapper;
+mod bitmap;
+mod buddy;
+pub use bitmap::{BitmapMap, BitmapMemoryMapper};
+pub use buddy::{BuddyMap, BuddyMemoryMapper, BuddyStats};
+
+/// Alias for the selected global `MemoryMapper` implementation (at compile time), see `config.rs`
+pub type GlobalMemoryMapper = crate::config::GlobalMemoryMapper;
 /// Global memory map for the whole kernel runtime
 pub static GLOBAL_MEMORY_MAPPER: Once<GlobalMemoryMapper> = Once::new();
 
@@ -36,9 +41,12 @@ type MapItem = (usize, usize);
 
 /// Implement for object that manage a memory map of physical regions. They must use interior
 /// mutability as they should work in a concurrent context.
-///
-/// I -> public iterator returned when reading the memory map is requested
-pub trait MemoryMapper<I: Iterator<Item = MapItem>> {
+pub trait MemoryMapper {
+    /// public iterator returned when reading the memory map is requested
+    type Map<'a>: Iterator<Item = MapItem>
+    where
+        Self: 'a;
+
     /// Creates and mounts a `MemoryMapper` in specified region
     /// ## SAFETY: region must adhere to the following:
     /// - must have read & write priviliges for ring0
@@ -64,14 +72,32 @@ pub trait MemoryMapper<I: Iterator<Item = MapItem>> {
         self.free(MapArea::new(region));
     }
 
+    /// Splits `area` at address `at`, `area` keeps the lower part & the upper part is returned
+    ///
+    /// ## WARNING: panics if `area` was not claimed from this map, see `free()`
+    fn split(&self, area: &mut MapArea, at: usize) -> Result<MapArea, MapAreaError>;
+
+    /// Joins `other` into `area`, returns `other` back if the two areas do not touch
+    ///
+    /// ## WARNING: panics if one of the areas was not claimed from this map, see `free()`
+    fn merge(&self, area: &mut MapArea, other: MapArea) -> Result<(), MapArea>;
+
+    /// Shrinks `area` to `region`, which must lie inside it. The cut off parts are freed.
+    ///
+    /// ## WARNING: panics if `area` was not claimed from this map, see `free()`
+    fn shrink(&self, area: &mut MapArea, region: (usize, usize)) -> Result<(), MapAreaError>;
+
     /// Iterate through claimed regions
-    fn iter(&self) -> I;
+    fn iter(&self) -> Self::Map<'_>;
 
-    /// Iterate through unclaimed regions between the claimed regions in the map
-    fn gaps(&self) -> MapGaps<I> {
+    /// Iterate through unclaimed regions between the claimed regions in the map, including the
+    /// space before the first and after the last claimed region
+    fn gaps(&self) -> MapGaps<Self::Map<'_>> {
+        let (start, end) = self.dimensions();
         MapGaps {
             iter: self.iter(),
-            last: self.dimensions().0,
+            last: start,
+            end,
         }
     }
 
@@ -79,10 +105,11 @@ pub trait MemoryMapper<I: Iterator<Item = MapItem>> {
     fn dimensions(&self) -> MapItem;
 }
 
-/// Iterate through the free space between map entries `I`
+/// Iterate through the free space between map entries `I`, which must be sorted
 pub struct MapGaps<I: Iterator<Item = MapItem>> {
     iter: I,
     last: usize,
+    end: usize,
 }
 
 impl<I> Iterator for MapGaps<I>
@@ -100,6 +127,12 @@ where
             }
             self.last = claimed.1;
         }
+        // space after the last claimed region
+        if self.last < self.end {
+            let cache = self.last;
+            self.last = self.end;
+            return Some((cache, self.end));
+        }
         None
     }
 }
@@ -116,39 +149,167 @@ pub enum MemoryMapperError {
     OutOfBound((usize, usize)),        // contains the valid Mapper region
 }
 
+/// Error returned by the `MapArea` accessors, `split()` & `shrink()`
+///
+/// ## Variants:
+/// - `OutOfBound` : The access or address does not fit into the area, contains the area region
+/// - `Misaligned` : The address is not on a boundary of the `MemoryMapper` granularity, contains
+/// the granularity
+#[derive(Debug)]
+pub enum MapAreaError {
+    OutOfBound((usize, usize)), // contains the area region
+    Misaligned(usize),          // contains the required alignment
+}
+
 /// Capability representing ownage of a claimed region
-#[derive(Default)]
+///
+/// The owned memory is reached through the HHDM with the typed accessors, the area can only be
+/// resized through the `MemoryMapper` that created it so the map stays consistent.
+#[derive(Debug, Default)]
 pub struct MapArea {
     region: (usize, usize),
 }
 
-impl<'a> MapArea {
-    // this function must be called only from inside MemoryMapper::claim() or MemoryMapper::force_free()
+impl MapArea {
+    // this function must be called only from inside a MemoryMapper or MemoryMapper::force_free()
     fn new(region: (usize, usize)) -> Self {
         Self { region }
     }
 
-    #[inline]
-    fn validate<T>(&self, ptr: *const T) -> bool {
-        ptr as usize >= self.region.0
-            && ptr as usize + unsafe { mem::size_of_val_raw(ptr) } <= self.region.1
+    /// Recreates a capability that has been leaked with `into_raw()`, used to transfer the
+    /// ownage through code that can not hold a `MapArea`
+    ///
+    /// ## SAFETY: the region must be claimed & not owned by another `MapArea`
+    pub unsafe fn from_raw(region: (usize, usize)) -> Self {
+        Self { region }
+    }
+
+    /// Leaks the capability, returns the region. It stays claimed.
+    pub fn into_raw(self) -> (usize, usize) {
+        let region = self.region;
+        mem::forget(self);
+        region
+    }
+
+    /// the owned region
+    pub fn region(&self) -> MapItem {
+        self.region
+    }
+
+    /// first address of the owned region
+    pub fn start(&self) -> usize {
+        self.region.0
+    }
+
+    /// first address after the owned region
+    pub fn end(&self) -> usize {
+        self.region.1
     }
 
-    fn create_ptr<T>(&self, addr: usize) -> Option<*const T> {
-        if self.validate(addr as *const T) {
-            return Some(addr as *const T);
+    /// size of the owned region in bytes
+    pub fn size(&self) -> usize {
+        self.region.1 - self.region.0
+    }
+
+    /// Checks if `at` lies strictly inside the area
+    fn check_split(&self, at: usize) -> Result<(), MapAreaError> {
+        if at <= self.region.0 || at >= self.region.1 {
+            return Err(MapAreaError::OutOfBound(self.region));
         }
-        // invalid address
-        None
+        Ok(())
     }
 
-    unsafe fn get<T>(&self, ptr: *const T) -> Option<&'a T> {
-        if self.validate(ptr) {
-            return Some(&*ptr);
+    /// Checks if `region` is a non-empty part of the area
+    fn check_shrink(&self, region: (usize, usize)) -> Result<(), MapAreaError> {
+        if region.0 >= region.1 || region.0 < self.region.0 || region.1 > self.region.1 {
+            return Err(MapAreaError::OutOfBound(self.region));
         }
-        // invalid address
-        None
+        Ok(())
+    }
+
+    /// Returns the region covering both areas if they touch
+    fn joined(&self, other: &MapArea) -> Option<MapItem> {
+        if self.region.1 == other.region.0 {
+            Some((self.region.0, other.region.1))
+        } else if other.region.1 == self.region.0 {
+            Some((other.region.0, self.region.1))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the virtual address of `offset` if `size` bytes starting there fit into the area
+    fn address(&self, offset: usize, size: usize) -> Result<usize, MapAreaError> {
+        match offset.checked_add(size) {
+            Some(end) if end <= self.size() => Ok(self.region.0 + offset + physical_offset()),
+            _ => Err(MapAreaError::OutOfBound(self.region)),
+        }
+    }
+
+    /// Reads a `T` at `offset` bytes into the area, it does not need to be aligned
+    ///
+    /// ## SAFETY: the bytes at `offset` must be a valid `T`
+    pub unsafe fn read<T: Copy>(&self, offset: usize) -> Result<T, MapAreaError> {
+        let address = self.address(offset, mem::size_of::<T>())?;
+        Ok(ptr::read_unaligned(address as *const T))
+    }
+
+    /// Writes `value` at `offset` bytes into the area, it does not need to be aligned
+    pub fn write<T: Copy>(&mut self, offset: usize, value: T) -> Result<(), MapAreaError> {
+        let address = self.address(offset, mem::size_of::<T>())?;
+        // SAFETY: the area is owned & the write is bound checked
+        unsafe { ptr::write_unaligned(address as *mut T, value) };
+        Ok(())
+    }
+
+    /// Fills `buffer` with the values stored at `offset` bytes into the area
+    ///
+    /// ## SAFETY: the bytes at `offset` must be valid `T`s
+    pub unsafe fn read_slice<T: Copy>(
+        &self,
+        offset: usize,
+        buffer: &mut [T],
+    ) -> Result<(), MapAreaError> {
+        let size = mem::size_of_val(buffer);
+        let address = self.address(offset, size)?;
+        ptr::copy_nonoverlapping(address as *const u8, buffer.as_mut_ptr() as *mut u8, size);
+        Ok(())
+    }
+
+    /// Copies `data` to `offset` bytes into the area
+    pub fn write_slice<T: Copy>(&mut self, offset: usize, data: &[T]) -> Result<(), MapAreaError> {
+        let size = mem::size_of_val(data);
+        let address = self.address(offset, size)?;
+        // SAFETY: the area is owned & the write is bound checked
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr() as *const u8, address as *mut u8, size) };
+        Ok(())
+    }
+
+    /// The whole area as bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        let address = self.region.0 + physical_offset();
+        // SAFETY: the area is owned for the lifetime of the borrow
+        unsafe { slice::from_raw_parts(address as *const u8, self.size()) }
     }
+
+    /// The whole area as mutable bytes
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        let address = self.region.0 + physical_offset();
+        // SAFETY: the area is owned for the lifetime of the borrow
+        unsafe { slice::from_raw_parts_mut(address as *mut u8, self.size()) }
+    }
+}
+
+/// Offset between a claimed physical address and the virtual address it is reached at
+#[cfg(target_os = "none")]
+fn physical_offset() -> usize {
+    crate::limine::hhdm()
+}
+
+/// Host unit tests manage plain heap buffers
+#[cfg(not(target_os = "none"))]
+fn physical_offset() -> usize {
+    0
 }
 
 impl Drop for MapArea {
@@ -159,10 +320,9 @@ impl Drop for MapArea {
         if self.region == (0, 0) {
             return;
         }
-        log!(
-            "[WARNING] Dropping MapArea handle for region {:016X} - {:016X}!\n",
-            self.region.0,
-            self.region.1
+        warn!(
+            "Dropping MapArea handle for region {:016X} - {:016X}!",
+            self.region.0, self.region.1
         )
     }
 }
@@ -208,7 +368,17 @@ impl Iterator for TableMap {
     }
 }
 
-impl MemoryMapper<TableMap> for TableMemoryMapper {
+/// Returns the index of the entry of `region`, panics if it is not in the table
+fn table_position(table: &[Option<(usize, usize)>], region: (usize, usize)) -> usize {
+    match table.iter().position(|taken| taken == &Some(region)) {
+        Some(i) => i,
+        None => panic!("Poisoned MapArea could not be freed!"),
+    }
+}
+
+impl MemoryMapper for TableMemoryMapper {
+    type Map<'a> = TableMap;
+
     unsafe fn manage(region: (usize, usize)) -> Self {
         Self {
             start: region.0,
@@ -230,7 +400,7 @@ impl MemoryMapper<TableMap> for TableMemoryMapper {
             // slot may be None and not hold an entry
             if let Some((first, last)) = *slot {
                 // cover all possible intersections of regions
-                if (first < start && last > start) || (first > start && first < end) {
+                if first < end && start < last {
                     return Err(MemoryMapperError::AlreadyOccupiedBy((first, last)));
                 }
             }
@@ -247,24 +417,55 @@ impl MemoryMapper<TableMap> for TableMemoryMapper {
         }
 
         table[i] = Some(region);
-        Ok(MapArea::new((start, end - start)))
+        Ok(MapArea::new(region))
     }
 
     // WARNING: calling free() on a region that is still used may lead to undefind behaviour
-    // returns true -> on success, false -> the target region has not been found
     fn free(&self, area: MapArea) {
         let mut table = self.table.lock();
-        let mut ai = None;
-        for (i, taken) in table.iter().enumerate() {
-            if taken == &Some(area.region) {
-                ai = Some(i);
-                break;
-            }
-        }
-        match ai {
-            Some(i) => table[i] = None,
-            None => panic!("Poisoned MapArea could not be freed!"),
-        }
+        let i = table_position(&*table, area.region);
+        table[i] = None;
+        // the region is no longer claimed
+        area.into_raw();
+    }
+
+    fn split(&self, area: &mut MapArea, at: usize) -> Result<MapArea, MapAreaError> {
+        area.check_split(at)?;
+        let mut table = self.table.lock();
+        let i = table_position(&*table, area.region);
+        let j = match table.iter().position(|slot| slot.is_none()) {
+            Some(j) => j,
+            None => panic!("Maximum number of MemoryMapper entries reached!"),
+        };
+        let upper = (at, area.region.1);
+        area.region.1 = at;
+        table[i] = Some(area.region);
+        table[j] = Some(upper);
+        Ok(MapArea::new(upper))
+    }
+
+    fn merge(&self, area: &mut MapArea, other: MapArea) -> Result<(), MapArea> {
+        let joined = match area.joined(&other) {
+            Some(joined) => joined,
+            None => return Err(other),
+        };
+        let mut table = self.table.lock();
+        let i = table_position(&*table, area.region);
+        let j = table_position(&*table, other.region);
+        table[i] = Some(joined);
+        table[j] = None;
+        area.region = joined;
+        other.into_raw();
+        Ok(())
+    }
+
+    fn shrink(&self, area: &mut MapArea, region: (usize, usize)) -> Result<(), MapAreaError> {
+        area.check_shrink(region)?;
+        let mut table = self.table.lock();
+        let i = table_position(&*table, area.region);
+        table[i] = Some(region);
+        area.region = region;
+        Ok(())
     }
 
     fn iter(&self) -> TableMap {
@@ -280,6 +481,8 @@ impl MemoryMapper<TableMap> for TableMemoryMapper {
             i += 1;
         }
         tm.limit = i;
+        // sorted by start address, as required by gaps()
+        tm.entries[..i].sort_unstable();
         tm
     }
 
@@ -287,3 +490,346 @@ impl MemoryMapper<TableMap> for TableMemoryMapper {
         (self.start, self.end)
     }
 }
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    /// size of the region managed in every test
+    const REGION_SIZE: usize = 0x40_0000;
+
+    /// page aligned heap buffer that is never freed, as the mappers may keep pointers into it
+    fn region() -> MapItem {
+        let buffer = std::vec![0_u8; REGION_SIZE + 0x1000].leak();
+        let start = (buffer.as_ptr() as usize + 0xFFF) & !0xFFF;
+        (start, start + REGION_SIZE)
+    }
+
+    /// runs `test` against every `MemoryMapper` implementation
+    macro_rules! for_each_mapper {
+        ($test:ident) => {
+            $test(unsafe { TableMemoryMapper::manage(region()) });
+            $test(unsafe { BuddyMemoryMapper::manage(region()) });
+            $test(unsafe { BitmapMemoryMapper::manage(region()) });
+        };
+    }
+
+    /// first address that is free right after `manage()`, some mappers claim their own storage
+    fn first_free(mapper: &impl MemoryMapper) -> usize {
+        let (start, _) = mapper.dimensions();
+        mapper.iter().last().map_or(start, |claimed| claimed.1)
+    }
+
+    /// start of a page aligned test region that is far away from the mapper storage
+    fn base(mapper: &impl MemoryMapper) -> usize {
+        mapper.dimensions().0 + 0x10_0000
+    }
+
+    fn assert_occupied(result: Result<MapArea, MemoryMapperError>) {
+        match result {
+            Err(MemoryMapperError::AlreadyOccupiedBy(_)) => {}
+            Err(e) => panic!("expected AlreadyOccupiedBy, got {:?}", e),
+            Ok(area) => panic!("overlapping claim succeeded: {:?}", area.into_raw()),
+        }
+    }
+
+    fn assert_out_of_bound(result: Result<MapArea, MemoryMapperError>) {
+        match result {
+            Err(MemoryMapperError::OutOfBound(_)) => {}
+            Err(e) => panic!("expected OutOfBound, got {:?}", e),
+            Ok(area) => panic!("out of bound claim succeeded: {:?}", area.into_raw()),
+        }
+    }
+
+    // ========== Behaviour suite
+
+    fn claim_and_free<M: MemoryMapper>(mapper: M) {
+        let before: Vec<MapItem> = mapper.iter().collect();
+        let base = base(&mapper);
+        let area = mapper.claim((base, base + 0x3000)).unwrap();
+        assert_eq!(area.region(), (base, base + 0x3000));
+        assert!(mapper
+            .iter()
+            .any(|claimed| claimed == (base, base + 0x3000)));
+        mapper.free(area);
+        assert_eq!(mapper.iter().collect::<Vec<_>>(), before);
+        // the region can be claimed again
+        mapper.free(mapper.claim((base, base + 0x3000)).unwrap());
+    }
+
+    fn overlapping_claims<M: MemoryMapper>(mapper: M) {
+        let base = base(&mapper);
+        let area = mapper.claim((base + 0x4000, base + 0x8000)).unwrap();
+        // identical
+        assert_occupied(mapper.claim((base + 0x4000, base + 0x8000)));
+        // same start, shorter & longer
+        assert_occupied(mapper.claim((base + 0x4000, base + 0x5000)));
+        assert_occupied(mapper.claim((base + 0x4000, base + 0x9000)));
+        // same end
+        assert_occupied(mapper.claim((base + 0x7000, base + 0x8000)));
+        assert_occupied(mapper.claim((base + 0x3000, base + 0x8000)));
+        // overlapping the start & the end
+        assert_occupied(mapper.claim((base + 0x2000, base + 0x5000)));
+        assert_occupied(mapper.claim((base + 0x7000, base + 0xA000)));
+        // contained & containing
+        assert_occupied(mapper.claim((base + 0x5000, base + 0x6000)));
+        assert_occupied(mapper.claim((base, base + 0x10000)));
+        mapper.free(area);
+    }
+
+    fn touching_claims<M: MemoryMapper>(mapper: M) {
+        let base = base(&mapper);
+        let middle = mapper.claim((base + 0x4000, base + 0x8000)).unwrap();
+        let before = mapper.claim((base, base + 0x4000)).unwrap();
+        let after = mapper.claim((base + 0x8000, base + 0xC000)).unwrap();
+        mapper.free(middle);
+        mapper.free(before);
+        mapper.free(after);
+    }
+
+    fn boundary_regions<M: MemoryMapper>(mapper: M) {
+        let (start, end) = mapper.dimensions();
+        let free = first_free(&mapper);
+        assert_out_of_bound(mapper.claim((start - 0x1000, free + 0x1000)));
+        assert_out_of_bound(mapper.claim((end - 0x1000, end + 1)));
+        assert_out_of_bound(mapper.claim((end, end + 0x1000)));
+        // the very first & last page are valid
+        let first = mapper.claim((free, free + 0x1000)).unwrap();
+        let last = mapper.claim((end - 0x1000, end)).unwrap();
+        assert_eq!(mapper.iter().last(), Some((end - 0x1000, end)));
+        mapper.free(first);
+        mapper.free(last);
+        // the whole free space
+        mapper.free(mapper.claim((free, end)).unwrap());
+    }
+
+    fn gaps_iteration<M: MemoryMapper>(mapper: M) {
+        let (_, end) = mapper.dimensions();
+        let free = first_free(&mapper);
+        assert_eq!(mapper.gaps().collect::<Vec<_>>(), [(free, end)]);
+
+        let base = base(&mapper);
+        // claimed out of order, gaps() must still be sorted
+        let b = mapper.claim((base + 0x8000, base + 0x9000)).unwrap();
+        let a = mapper.claim((base + 0x2000, base + 0x4000)).unwrap();
+        let c = mapper.claim((base + 0x9000, base + 0xA000)).unwrap();
+        assert_eq!(
+            mapper.gaps().collect::<Vec<_>>(),
+            [
+                (free, base + 0x2000),
+                (base + 0x4000, base + 0x8000),
+                (base + 0xA000, end),
+            ]
+        );
+
+        // no trailing gap if the end is claimed
+        let last = mapper.claim((base + 0xA000, end)).unwrap();
+        assert_eq!(mapper.gaps().last(), Some((base + 0x4000, base + 0x8000)));
+        for area in [a, b, c, last] {
+            mapper.free(area);
+        }
+        assert_eq!(mapper.gaps().collect::<Vec<_>>(), [(free, end)]);
+    }
+
+    fn split_merge_shrink<M: MemoryMapper>(mapper: M) {
+        let base = base(&mapper);
+        let mut area = mapper.claim((base, base + 0x8000)).unwrap();
+        assert!(matches!(
+            mapper.split(&mut area, base),
+            Err(MapAreaError::OutOfBound(_))
+        ));
+        assert!(matches!(
+            mapper.split(&mut area, base + 0x8000),
+            Err(MapAreaError::OutOfBound(_))
+        ));
+        let upper = mapper.split(&mut area, base + 0x2000).unwrap();
+        assert_eq!(area.region(), (base, base + 0x2000));
+        assert_eq!(upper.region(), (base + 0x2000, base + 0x8000));
+
+        // areas that do not touch are given back
+        let other = mapper.claim((base + 0x9000, base + 0xA000)).unwrap();
+        let other = mapper.merge(&mut area, other).unwrap_err();
+        mapper.merge(&mut area, upper).unwrap();
+        assert_eq!(area.region(), (base, base + 0x8000));
+
+        assert!(mapper.shrink(&mut area, (base, base + 0x9000)).is_err());
+        mapper
+            .shrink(&mut area, (base + 0x1000, base + 0x3000))
+            .unwrap();
+        assert_occupied(mapper.claim((base + 0x2000, base + 0x3000)));
+        mapper.free(mapper.claim((base, base + 0x1000)).unwrap());
+        mapper.free(mapper.claim((base + 0x3000, base + 0x8000)).unwrap());
+        mapper.free(area);
+        mapper.free(other);
+    }
+
+    fn typed_access<M: MemoryMapper>(mapper: M) {
+        let base = base(&mapper);
+        let mut area = mapper.claim((base, base + 0x1000)).unwrap();
+        area.write(0xFF8, u64::MAX).unwrap();
+        assert!(area.write(0xFF9, 0_u64).is_err());
+        assert!(area.write(usize::MAX, 0_u8).is_err());
+        assert_eq!(unsafe { area.read::<u64>(0xFF8) }.unwrap(), u64::MAX);
+        // unaligned
+        area.write(0x3, 0x1234_5678_u32).unwrap();
+        assert_eq!(unsafe { area.read::<u32>(0x3) }.unwrap(), 0x1234_5678);
+
+        area.write_slice(0x100, &[1_u16, 2, 3]).unwrap();
+        let mut buffer = [0_u16; 3];
+        unsafe { area.read_slice(0x100, &mut buffer) }.unwrap();
+        assert_eq!(buffer, [1, 2, 3]);
+        assert!(area.write_slice(0xFFE, &[0_u16; 2]).is_err());
+        assert_eq!(area.as_bytes()[0x100], 1);
+        area.as_bytes_mut().fill(0);
+        assert_eq!(unsafe { area.read::<u64>(0xFF8) }.unwrap(), 0);
+        mapper.free(area);
+    }
+
+    fn raw_transfer<M: MemoryMapper>(mapper: M) {
+        let base = base(&mapper);
+        let raw = mapper.claim((base, base + 0x1000)).unwrap().into_raw();
+        // still claimed after leaking
+        assert_occupied(mapper.claim(raw));
+        mapper.free(unsafe { MapArea::from_raw(raw) });
+    }
+
+    /// claims every other page, more regions than fit into a fixed size iterator
+    fn many_claims<M: MemoryMapper>(mapper: M) {
+        let (_, end) = mapper.dimensions();
+        // keep the claims apart from the mapper storage
+        let first = first_free(&mapper) + 0x1000;
+        let areas: Vec<MapArea> = (0..450)
+            .map(|i| first + i * 0x2000)
+            .map(|page| mapper.claim((page, page + 0x1000)).unwrap())
+            .collect();
+        let claimed: Vec<MapItem> = mapper.iter().filter(|c| c.0 >= first).collect();
+        assert_eq!(claimed.len(), 450);
+        assert_eq!(
+            claimed[449],
+            (first + 449 * 0x2000, first + 449 * 0x2000 + 0x1000)
+        );
+        // one in front of every claim & one behind the last
+        assert_eq!(mapper.gaps().count(), 451);
+        assert_eq!(mapper.gaps().last(), Some((claimed[449].1, end)));
+        for area in areas {
+            mapper.free(area);
+        }
+    }
+
+    #[test]
+    fn mappers_claim_and_free() {
+        for_each_mapper!(claim_and_free);
+    }
+
+    #[test]
+    fn mappers_reject_overlapping_claims() {
+        for_each_mapper!(overlapping_claims);
+    }
+
+    #[test]
+    fn mappers_accept_touching_claims() {
+        for_each_mapper!(touching_claims);
+    }
+
+    #[test]
+    fn mappers_check_boundaries() {
+        for_each_mapper!(boundary_regions);
+    }
+
+    #[test]
+    fn mappers_iterate_gaps() {
+        for_each_mapper!(gaps_iteration);
+    }
+
+    #[test]
+    fn mappers_split_merge_shrink() {
+        for_each_mapper!(split_merge_shrink);
+    }
+
+    #[test]
+    fn mappers_typed_access() {
+        for_each_mapper!(typed_access);
+    }
+
+    #[test]
+    fn mappers_raw_transfer() {
+        for_each_mapper!(raw_transfer);
+    }
+
+    #[test]
+    #[should_panic(expected = "Poisoned MapArea")]
+    fn table_free_unclaimed_panics() {
+        let mapper = unsafe { TableMemoryMapper::manage(region()) };
+        let base = base(&mapper);
+        mapper.free(MapArea::new((base, base + 0x1000)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Poisoned MapArea")]
+    fn buddy_free_unclaimed_panics() {
+        let mapper = unsafe { BuddyMemoryMapper::manage(region()) };
+        let base = base(&mapper);
+        mapper.free(MapArea::new((base, base + 0x1000)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Poisoned MapArea")]
+    fn bitmap_free_unclaimed_panics() {
+        let mapper = unsafe { BitmapMemoryMapper::manage(region()) };
+        let base = base(&mapper);
+        mapper.free(MapArea::new((base, base + 0x1000)));
+    }
+
+    #[test]
+    fn page_mappers_iterate_many_claims() {
+        many_claims(unsafe { BuddyMemoryMapper::manage(region()) });
+        many_claims(unsafe { BitmapMemoryMapper::manage(region()) });
+    }
+
+    #[test]
+    fn table_claims_are_byte_exact() {
+        let mapper = unsafe { TableMemoryMapper::manage(region()) };
+        let base = base(&mapper);
+        let a = mapper.claim((base + 1, base + 3)).unwrap();
+        let b = mapper.claim((base + 3, base + 4)).unwrap();
+        assert_occupied(mapper.claim((base + 2, base + 3)));
+        mapper.free(a);
+        mapper.free(b);
+    }
+
+    #[test]
+    fn page_mappers_round_to_pages() {
+        // bitmap words hold 64 pages, cover the bit 63 -> 64 step
+        let mapper = unsafe { BitmapMemoryMapper::manage(region()) };
+        let start = mapper.dimensions().0;
+        let mut a = mapper
+            .claim((start + 63 * 0x1000 + 1, start + 64 * 0x1000))
+            .unwrap();
+        let b = mapper
+            .claim((start + 64 * 0x1000, start + 64 * 0x1000 + 1))
+            .unwrap();
+        assert_eq!(
+            mapper.iter().last(),
+            Some((start + 63 * 0x1000, start + 65 * 0x1000))
+        );
+        assert_occupied(mapper.claim((start + 63 * 0x1000, start + 63 * 0x1000 + 1)));
+        mapper.merge(&mut a, b).unwrap();
+        // both halves would own page 63
+        assert!(matches!(
+            mapper.split(&mut a, start + 63 * 0x1000 + 0x800),
+            Err(MapAreaError::Misaligned(0x1000))
+        ));
+        let b = mapper.split(&mut a, start + 64 * 0x1000).unwrap();
+        mapper.free(a);
+        mapper.free(b);
+
+        let mapper = unsafe { BuddyMemoryMapper::manage(region()) };
+        let base = base(&mapper);
+        let a = mapper.claim((base + 0x10, base + 0x20)).unwrap();
+        assert_occupied(mapper.claim((base + 0x800, base + 0x1000)));
+        mapper.free(a);
+        assert_eq!(mapper.stats().free_blocks, 1);
+    }
+}
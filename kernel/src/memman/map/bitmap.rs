@@ -0,0 +1,247 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// BITMAP MEMORY MAPPER
+// Implementation of MemoryMapper that stores one bit per page in a bitmap placed at the start of
+// the managed region itself. The number of claimed regions is unlimited, claims are rounded
+// outwards to whole pages. The pages holding the bitmap are claimed forever.
+// WARNING: the start of the managed region must be RAM, the bitmap is written through the HHDM
+
+use super::{physical_offset, MapArea, MapAreaError, MapItem, MemoryMapper, MemoryMapperError};
+use core::slice;
+use spin::Mutex;
+
+/// Granularity of `BitmapMemoryMapper`, every bit of the bitmap stands for one page
+const BITMAP_PAGE_SIZE: usize = 4096;
+
+/// Bits in a single bitmap word
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// `MemoryMapper` implementation that uses a bitmap with one bit per page to store claimed pages
+pub struct BitmapMemoryMapper {
+    start: usize,
+    end: usize,
+    /// number of pages in the managed region
+    pages: usize,
+    /// set -> claimed
+    bitmap: Mutex<&'static mut [u64]>,
+}
+
+/// `Iterator` object for `BitmapMemoryMapper` claimed runs of pages. Every step searches the
+/// bitmap again, starting after the previous run.
+pub struct BitmapMap<'a> {
+    mapper: &'a BitmapMemoryMapper,
+    /// the next run starts at or after this page
+    page: usize,
+}
+
+impl Iterator for BitmapMap<'_> {
+    type Item = MapItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bitmap = self.mapper.bitmap.lock();
+        let pages = self.mapper.pages;
+        let first = match find(&bitmap, self.page, pages, true) {
+            Some(first) => first,
+            None => {
+                // pages claimed while iterating must not show up after the end
+                self.page = pages;
+                return None;
+            }
+        };
+        let last = find(&bitmap, first, pages, false).unwrap_or(pages);
+        self.page = last;
+        Some(self.mapper.region((first, last)))
+    }
+}
+
+/// bits of word `word` that lie inside the pages `(s, e)`
+fn word_mask(word: usize, s: usize, e: usize) -> u64 {
+    let low = s.saturating_sub(word * WORD_BITS).min(WORD_BITS);
+    let high = (e - word * WORD_BITS).min(WORD_BITS);
+    let below_high = match high {
+        WORD_BITS => u64::MAX,
+        _ => (1_u64 << high) - 1,
+    };
+    let below_low = match low {
+        WORD_BITS => u64::MAX,
+        _ => (1_u64 << low) - 1,
+    };
+    below_high & !below_low
+}
+
+/// Returns the first page inside `(s, e)` that is claimed (`claimed = true`) or unclaimed
+fn find(bitmap: &[u64], s: usize, e: usize, claimed: bool) -> Option<usize> {
+    if s >= e {
+        return None;
+    }
+    for word in s / WORD_BITS..(e + WORD_BITS - 1) / WORD_BITS {
+        let bits = match claimed {
+            true => bitmap[word],
+            false => !bitmap[word],
+        } & word_mask(word, s, e);
+        if bits != 0 {
+            return Some(word * WORD_BITS + bits.trailing_zeros() as usize);
+        }
+    }
+    None
+}
+
+/// Returns the last page inside `(s, e)` that is claimed (`claimed = true`) or unclaimed
+fn rfind(bitmap: &[u64], s: usize, e: usize, claimed: bool) -> Option<usize> {
+    if s >= e {
+        return None;
+    }
+    for word in (s / WORD_BITS..(e + WORD_BITS - 1) / WORD_BITS).rev() {
+        let bits = match claimed {
+            true => bitmap[word],
+            false => !bitmap[word],
+        } & word_mask(word, s, e);
+        if bits != 0 {
+            return Some(word * WORD_BITS + WORD_BITS - 1 - bits.leading_zeros() as usize);
+        }
+    }
+    None
+}
+
+/// Sets all pages inside `(s, e)` to claimed or unclaimed
+fn set(bitmap: &mut [u64], s: usize, e: usize, claimed: bool) {
+    for word in s / WORD_BITS..(e + WORD_BITS - 1) / WORD_BITS {
+        let mask = word_mask(word, s, e);
+        if claimed {
+            bitmap[word] |= mask;
+        } else {
+            bitmap[word] &= !mask;
+        }
+    }
+}
+
+impl BitmapMemoryMapper {
+    /// converts a region into page indices, rounded outwards
+    fn pages(&self, region: MapItem) -> MapItem {
+        let s = (region.0 - self.start) / BITMAP_PAGE_SIZE;
+        let e = (region.1 - self.start + BITMAP_PAGE_SIZE - 1) / BITMAP_PAGE_SIZE;
+        (s, e)
+    }
+
+    /// panics if a page of `region` is not claimed
+    fn check_claimed(&self, region: MapItem) {
+        let (s, e) = self.pages(region);
+        if find(&self.bitmap.lock(), s, e, false).is_some() {
+            panic!("Poisoned MapArea could not be freed!");
+        }
+    }
+
+    /// converts page indices back into a region, clamped to the managed region
+    fn region(&self, pages: MapItem) -> MapItem {
+        (
+            self.start + pages.0 * BITMAP_PAGE_SIZE,
+            (self.start + pages.1 * BITMAP_PAGE_SIZE).min(self.end),
+        )
+    }
+}
+
+impl MemoryMapper for BitmapMemoryMapper {
+    type Map<'a> = BitmapMap<'a>;
+
+    unsafe fn manage(region: (usize, usize)) -> Self {
+        let pages = (region.1 - region.0 + BITMAP_PAGE_SIZE - 1) / BITMAP_PAGE_SIZE;
+        let words = (pages + WORD_BITS - 1) / WORD_BITS;
+        // the bitmap is placed at the first word aligned address
+        let address = (region.0 + 7) & !7;
+        let reserved = (address - region.0 + words * 8 + BITMAP_PAGE_SIZE - 1) / BITMAP_PAGE_SIZE;
+        if reserved > pages {
+            panic!("Region is too small to hold the BitmapMemoryMapper bitmap!");
+        }
+
+        let bitmap = slice::from_raw_parts_mut((address + physical_offset()) as *mut u64, words);
+        bitmap.fill(0);
+        set(bitmap, 0, reserved, true);
+        Self {
+            start: region.0,
+            end: region.1,
+            pages,
+            bitmap: Mutex::new(bitmap),
+        }
+    }
+
+    fn claim(&self, region: (usize, usize)) -> Result<MapArea, MemoryMapperError> {
+        let (start, end) = region;
+        // bound check the request
+        if start < self.start || end > self.end {
+            return Err(MemoryMapperError::OutOfBound((self.start, self.end)));
+        }
+
+        let (s, e) = self.pages(region);
+        let mut bitmap = self.bitmap.lock();
+        if let Some(page) = find(&bitmap, s, e, true) {
+            // report the whole claimed run around the page
+            let first = rfind(&bitmap, 0, page, false).map_or(0, |p| p + 1);
+            let last = find(&bitmap, page, self.pages, false).unwrap_or(self.pages);
+            return Err(MemoryMapperError::AlreadyOccupiedBy(
+                self.region((first, last)),
+            ));
+        }
+        set(&mut bitmap, s, e, true);
+        Ok(MapArea::new(region))
+    }
+
+    fn free(&self, area: MapArea) {
+        self.check_claimed(area.region);
+        let (s, e) = self.pages(area.region);
+        let mut bitmap = self.bitmap.lock();
+        set(&mut bitmap, s, e, false);
+        // the region is no longer claimed
+        area.into_raw();
+    }
+
+    fn split(&self, area: &mut MapArea, at: usize) -> Result<MapArea, MapAreaError> {
+        area.check_split(at)?;
+        // both halves would own the page holding `at`
+        if (at - self.start) % BITMAP_PAGE_SIZE != 0 {
+            return Err(MapAreaError::Misaligned(BITMAP_PAGE_SIZE));
+        }
+        self.check_claimed(area.region);
+        let upper = (at, area.region.1);
+        area.region.1 = at;
+        Ok(MapArea::new(upper))
+    }
+
+    fn merge(&self, area: &mut MapArea, other: MapArea) -> Result<(), MapArea> {
+        let joined = match area.joined(&other) {
+            Some(joined) => joined,
+            None => return Err(other),
+        };
+        // the pages of both areas stay claimed
+        self.check_claimed(joined);
+        area.region = joined;
+        other.into_raw();
+        Ok(())
+    }
+
+    fn shrink(&self, area: &mut MapArea, region: (usize, usize)) -> Result<(), MapAreaError> {
+        area.check_shrink(region)?;
+        self.check_claimed(area.region);
+        let (old_s, old_e) = self.pages(area.region);
+        let (s, e) = self.pages(region);
+        let mut bitmap = self.bitmap.lock();
+        set(&mut bitmap, old_s, s, false);
+        set(&mut bitmap, e, old_e, false);
+        area.region = region;
+        Ok(())
+    }
+
+    fn iter(&self) -> BitmapMap<'_> {
+        BitmapMap {
+            mapper: self,
+            page: 0,
+        }
+    }
+
+    fn dimensions(&self) -> MapItem {
+        (self.start, self.end)
+    }
+}
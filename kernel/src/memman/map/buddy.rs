@@ -0,0 +1,407 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// BUDDY MEMORY MAPPER
+// Implementation of MemoryMapper that splits the managed region into a binary tree of power of two
+// sized blocks. Only the nodes on the borders of claimed regions are ever split, so the tree stays
+// small no matter how big the managed region is and claim/free only walk O(log n) nodes.
+// The nodes of all trees come from one static pool, so no tree is ever built on the stack.
+// WARNING: will panic if the node pool gets full
+
+use super::{MapArea, MapAreaError, MapItem, MemoryMapper, MemoryMapperError};
+use spin::Mutex;
+
+/// Smallest block the tree splits into, claimed regions are rounded outwards to it
+const BUDDY_BLOCK_SIZE: usize = 4096;
+
+/// Max ammount of nodes in the trees of all `BuddyMemoryMapper`s together
+const BUDDY_POOL_SIZE: usize = 16384;
+
+/// Depth limit of the tree, enough for a 64 bit address space
+const BUDDY_MAX_DEPTH: usize = 64;
+
+/// Nodes of every `BuddyMemoryMapper`
+static BUDDY_POOL: Mutex<BuddyTree> = Mutex::new(BuddyTree::new());
+
+/// State of a block in the tree
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Node {
+    /// whole block is unclaimed
+    Free,
+    /// whole block is claimed
+    Claimed,
+    /// block lies after the end of the managed region
+    Padding,
+    /// block is split, holds the index of the left child, the right one follows it
+    Split(u32),
+    /// node pair is not part of the tree, holds the index of the next unused pair
+    Unused(Option<u32>),
+}
+
+/// `MemoryMapper` implementation that uses a sparse buddy tree to store claimed entries
+pub struct BuddyMemoryMapper {
+    start: usize,
+    end: usize,
+    /// index of the root node in `BUDDY_POOL`
+    root: usize,
+    /// size of the root block
+    size: usize,
+}
+
+/// Fragmentation info returned by `BuddyMemoryMapper::stats()`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BuddyStats {
+    /// unclaimed bytes inside the managed region
+    pub free: usize,
+    /// size of the biggest unclaimed block
+    pub largest_free_block: usize,
+    /// number of unclaimed blocks
+    pub free_blocks: usize,
+    /// 0 -> all free memory is in one block, 100 -> free memory is scattered into tiny blocks
+    pub fragmentation_percent: usize,
+}
+
+/// Node pairs of the trees, the children of a node are always allocated together
+struct BuddyTree {
+    nodes: [Node; BUDDY_POOL_SIZE],
+    /// first released node pair that is not part of a tree
+    unused: Option<u32>,
+    /// pairs from here on have never been used & are not linked into `unused`
+    fresh: usize,
+}
+
+impl BuddyTree {
+    const fn new() -> Self {
+        Self {
+            nodes: [Node::Free; BUDDY_POOL_SIZE],
+            unused: None,
+            fresh: 0,
+        }
+    }
+
+    /// takes a node pair out of the pool, returns the index of the left node
+    fn allocate(&mut self) -> u32 {
+        if let Some(left) = self.unused {
+            self.unused = match self.nodes[left as usize] {
+                Node::Unused(next) => next,
+                _ => unreachable!(),
+            };
+            return left;
+        }
+        if self.fresh + 2 > BUDDY_POOL_SIZE {
+            panic!("Maximum number of MemoryMapper entries reached!");
+        }
+        self.fresh += 2;
+        (self.fresh - 2) as u32
+    }
+
+    /// returns the node pair starting at `left` to the pool
+    fn release(&mut self, left: u32) {
+        self.nodes[left as usize] = Node::Unused(self.unused);
+        self.unused = Some(left);
+    }
+
+    /// turns a Free or Padding node into a Split one with 2 children of the same state
+    fn split(&mut self, node: usize) -> u32 {
+        let state = self.nodes[node];
+        let left = self.allocate();
+        self.nodes[left as usize] = state;
+        self.nodes[left as usize + 1] = state;
+        self.nodes[node] = Node::Split(left);
+        left
+    }
+
+    /// turns a Split node back into `state` & returns all its descendants to the pool
+    fn merge(&mut self, node: usize, state: Node) {
+        if let Node::Split(left) = self.nodes[node] {
+            self.merge(left as usize, state);
+            self.merge(left as usize + 1, state);
+            self.release(left);
+        }
+        self.nodes[node] = state;
+    }
+
+    /// Returns the first non-free block intersecting `(s, e)`, offsets relative to the root
+    fn occupant(
+        &self,
+        node: usize,
+        start: usize,
+        size: usize,
+        s: usize,
+        e: usize,
+    ) -> Option<MapItem> {
+        if e <= start || s >= start + size {
+            return None;
+        }
+        match self.nodes[node] {
+            Node::Free => None,
+            Node::Split(left) => {
+                let half = size / 2;
+                self.occupant(left as usize, start, half, s, e)
+                    .or_else(|| self.occupant(left as usize + 1, start + half, half, s, e))
+            }
+            _ => Some((start, start + size)),
+        }
+    }
+
+    /// Sets all blocks inside `(s, e)` to `state`, splitting the ones on the border
+    fn mark(&mut self, node: usize, start: usize, size: usize, s: usize, e: usize, state: Node) {
+        if e <= start || s >= start + size {
+            return;
+        }
+        if s <= start && start + size <= e {
+            self.merge(node, state);
+            return;
+        }
+        let left = match self.nodes[node] {
+            Node::Split(left) => left,
+            _ => self.split(node),
+        } as usize;
+        let half = size / 2;
+        self.mark(left, start, half, s, e, state);
+        self.mark(left + 1, start + half, half, s, e, state);
+        // merge buddies back together
+        if self.nodes[left] == self.nodes[left + 1] && !matches!(self.nodes[left], Node::Split(_)) {
+            let merged = self.nodes[left];
+            self.merge(node, merged);
+        }
+    }
+
+    /// Checks if every block inside `(s, e)` is claimed
+    fn is_claimed(&self, node: usize, start: usize, size: usize, s: usize, e: usize) -> bool {
+        if e <= start || s >= start + size {
+            return true;
+        }
+        match self.nodes[node] {
+            Node::Claimed => true,
+            Node::Split(left) => {
+                let half = size / 2;
+                self.is_claimed(left as usize, start, half, s, e)
+                    && self.is_claimed(left as usize + 1, start + half, half, s, e)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the first claimed block below `node` that ends after `from`, offsets relative to the
+    /// root
+    fn first_claimed(
+        &self,
+        node: usize,
+        start: usize,
+        size: usize,
+        from: usize,
+    ) -> Option<MapItem> {
+        if start + size <= from {
+            return None;
+        }
+        match self.nodes[node] {
+            Node::Claimed => Some((start, start + size)),
+            Node::Split(left) => {
+                let half = size / 2;
+                self.first_claimed(left as usize, start, half, from)
+                    .or_else(|| self.first_claimed(left as usize + 1, start + half, half, from))
+            }
+            _ => None,
+        }
+    }
+
+    /// Calls `f` with every leaf block of the tree at `root` of `size` in address order
+    fn for_each_block<F: FnMut(Node, usize, usize)>(&self, root: usize, size: usize, mut f: F) {
+        // (node, start, size)
+        let mut stack = [(0_usize, 0_usize, 0_usize); BUDDY_MAX_DEPTH + 1];
+        stack[0] = (root, 0, size);
+        let mut depth = 1;
+        while depth > 0 {
+            depth -= 1;
+            let (node, start, size) = stack[depth];
+            match self.nodes[node] {
+                Node::Split(left) => {
+                    let half = size / 2;
+                    // right first, so the left child is visited first
+                    stack[depth] = (left as usize + 1, start + half, half);
+                    stack[depth + 1] = (left as usize, start, half);
+                    depth += 2;
+                }
+                state => f(state, start, size),
+            }
+        }
+    }
+}
+
+/// `Iterator` object for `BuddyMemoryMapper` claimed entries, adjacent blocks are joined.
+/// Every step searches the tree again, starting after the previous entry.
+pub struct BuddyMap<'a> {
+    mapper: &'a BuddyMemoryMapper,
+    /// offset relative to the root, the next entry starts at or after it
+    next: usize,
+}
+
+impl Iterator for BuddyMap<'_> {
+    type Item = MapItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tree = BUDDY_POOL.lock();
+        let (root, size) = (self.mapper.root, self.mapper.size);
+        let (start, mut end) = match tree.first_claimed(root, 0, size, self.next) {
+            Some(block) => block,
+            None => {
+                // regions claimed while iterating must not show up after the end
+                self.next = size;
+                return None;
+            }
+        };
+        // join the blocks that follow without a gap
+        while let Some((next_start, next_end)) = tree.first_claimed(root, 0, size, end) {
+            if next_start != end {
+                break;
+            }
+            end = next_end;
+        }
+        self.next = end;
+        Some((self.mapper.start + start, self.mapper.start + end))
+    }
+}
+
+impl BuddyMemoryMapper {
+    /// converts a region into block aligned offsets relative to the root
+    fn offsets(&self, region: MapItem) -> MapItem {
+        let s = (region.0 - self.start) & !(BUDDY_BLOCK_SIZE - 1);
+        let e = (region.1 - self.start + BUDDY_BLOCK_SIZE - 1) & !(BUDDY_BLOCK_SIZE - 1);
+        (s, e)
+    }
+
+    /// panics if a block of `region` is not claimed
+    fn check_claimed(&self, region: MapItem) {
+        let (s, e) = self.offsets(region);
+        if !BUDDY_POOL.lock().is_claimed(self.root, 0, self.size, s, e) {
+            panic!("Poisoned MapArea could not be freed!");
+        }
+    }
+
+    /// Get the fragmentation of the unclaimed memory
+    pub fn stats(&self) -> BuddyStats {
+        let mut stats = BuddyStats::default();
+        BUDDY_POOL
+            .lock()
+            .for_each_block(self.root, self.size, |state, _, size| {
+                if state == Node::Free {
+                    stats.free += size;
+                    stats.free_blocks += 1;
+                    stats.largest_free_block = stats.largest_free_block.max(size);
+                }
+            });
+        if stats.free > 0 {
+            stats.fragmentation_percent = 100 - stats.largest_free_block * 100 / stats.free;
+        }
+        stats
+    }
+}
+
+impl Drop for BuddyMemoryMapper {
+    fn drop(&mut self) {
+        let mut tree = BUDDY_POOL.lock();
+        tree.merge(self.root, Node::Free);
+        tree.release(self.root as u32);
+    }
+}
+
+impl MemoryMapper for BuddyMemoryMapper {
+    type Map<'a> = BuddyMap<'a>;
+
+    unsafe fn manage(region: (usize, usize)) -> Self {
+        let blocks = (region.1 - region.0 + BUDDY_BLOCK_SIZE - 1) / BUDDY_BLOCK_SIZE;
+        let size = blocks.next_power_of_two() * BUDDY_BLOCK_SIZE;
+        let mut tree = BUDDY_POOL.lock();
+        // the right node of the pair stays unused
+        let root = tree.allocate() as usize;
+        tree.nodes[root] = Node::Free;
+        let used = blocks * BUDDY_BLOCK_SIZE;
+        tree.mark(root, 0, size, used, size, Node::Padding);
+        Self {
+            start: region.0,
+            end: region.1,
+            root,
+            size,
+        }
+    }
+
+    fn claim(&self, region: (usize, usize)) -> Result<MapArea, MemoryMapperError> {
+        let (start, end) = region;
+        // bound check the request
+        if start < self.start || end > self.end {
+            return Err(MemoryMapperError::OutOfBound((self.start, self.end)));
+        }
+
+        let (s, e) = self.offsets(region);
+        let mut tree = BUDDY_POOL.lock();
+        if let Some((first, last)) = tree.occupant(self.root, 0, self.size, s, e) {
+            return Err(MemoryMapperError::AlreadyOccupiedBy((
+                first + self.start,
+                last + self.start,
+            )));
+        }
+        tree.mark(self.root, 0, self.size, s, e, Node::Claimed);
+        Ok(MapArea::new(region))
+    }
+
+    fn free(&self, area: MapArea) {
+        self.check_claimed(area.region);
+        let (s, e) = self.offsets(area.region);
+        BUDDY_POOL
+            .lock()
+            .mark(self.root, 0, self.size, s, e, Node::Free);
+        // the region is no longer claimed
+        area.into_raw();
+    }
+
+    fn split(&self, area: &mut MapArea, at: usize) -> Result<MapArea, MapAreaError> {
+        area.check_split(at)?;
+        // both halves would own the block holding `at`
+        if (at - self.start) % BUDDY_BLOCK_SIZE != 0 {
+            return Err(MapAreaError::Misaligned(BUDDY_BLOCK_SIZE));
+        }
+        self.check_claimed(area.region);
+        let upper = (at, area.region.1);
+        area.region.1 = at;
+        Ok(MapArea::new(upper))
+    }
+
+    fn merge(&self, area: &mut MapArea, other: MapArea) -> Result<(), MapArea> {
+        let joined = match area.joined(&other) {
+            Some(joined) => joined,
+            None => return Err(other),
+        };
+        // the blocks of both areas stay claimed
+        self.check_claimed(joined);
+        area.region = joined;
+        other.into_raw();
+        Ok(())
+    }
+
+    fn shrink(&self, area: &mut MapArea, region: (usize, usize)) -> Result<(), MapAreaError> {
+        area.check_shrink(region)?;
+        self.check_claimed(area.region);
+        let (old_s, old_e) = self.offsets(area.region);
+        let (s, e) = self.offsets(region);
+        let mut tree = BUDDY_POOL.lock();
+        tree.mark(self.root, 0, self.size, old_s, s, Node::Free);
+        tree.mark(self.root, 0, self.size, e, old_e, Node::Free);
+        area.region = region;
+        Ok(())
+    }
+
+    fn iter(&self) -> BuddyMap<'_> {
+        BuddyMap {
+            mapper: self,
+            next: 0,
+        }
+    }
+
+    fn dimensions(&self) -> MapItem {
+        (self.start, self.end)
+    }
+}
@@ -4,4 +4,8 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub mod frame;
+pub mod heap;
 pub mod map;
+#[cfg(target_arch = "x86_64")]
+pub mod paging;
@@ -0,0 +1,917 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! This module manages the amd64 page tables (4 or 5 levels) that translate virtual addresses
+//! <br> main source: https://wiki.osdev.org/Paging
+
+use super::frame::{FrameAllocator, FrameArea};
+use super::map::{self, MapArea, MemoryMapper};
+use crate::arch::gdt;
+use crate::config::CPU_MAX_COUNT;
+use crate::info;
+use crate::limine::{self, MemmapEntryType};
+use spin::{Mutex, Once};
+use x86::controlregs::{cr3, cr3_write, cr4, cr4_write, Cr4};
+use x86::cpuid::CpuId;
+use x86::msr::{rdmsr, wrmsr, IA32_EFER, IA32_PAT};
+use x86::tlb;
+
+/// Size of the smallest page & of every page table
+pub const PAGE_SIZE: usize = 4096;
+
+/// Number of entries in every page table
+const TABLE_ENTRIES: usize = 512;
+
+/// Mask of the physical address stored in an entry
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+// entry bits
+const ENTRY_PRESENT: u64 = 1 << 0;
+const ENTRY_WRITABLE: u64 = 1 << 1;
+const ENTRY_USER: u64 = 1 << 2;
+const ENTRY_WRITE_THROUGH: u64 = 1 << 3;
+const ENTRY_CACHE_DISABLE: u64 = 1 << 4;
+const ENTRY_HUGE: u64 = 1 << 7;
+const ENTRY_GLOBAL: u64 = 1 << 8;
+const ENTRY_PAT_SMALL: u64 = 1 << 7; // only in level 1 entries, where bit 7 is not HUGE
+const ENTRY_PAT_HUGE: u64 = 1 << 12;
+const ENTRY_NO_EXECUTE: u64 = 1 << 63;
+
+/// EFER.NXE, enables the no-execute bit in page table entries
+const EFER_NO_EXECUTE_ENABLE: u64 = 1 << 11;
+
+/// PAT layout used by `CacheType`, entries 0-3 keep their power-on values
+/// (WB, WT, UC-, UC), entry 4 is write-combining and 5 write-protected.
+const PAT_VALUE: u64 = 0x0007_0501_0007_0406;
+
+/// Limine identity maps the first 4GiB, its terminal still depends on the parts it uses
+const IDENTITY_MAP_SIZE: usize = 0x1_0000_0000;
+
+/// Address space of the kernel, shared by the upper half of every other address space
+pub static KERNEL_SPACE: Once<Mutex<AddressSpace>> = Once::new();
+
+// Linked from link/x86_64.ld
+extern "C" {
+    static __kernel_text_start: u8;
+    static __kernel_text_end: u8;
+    static __kernel_rodata_start: u8;
+    static __kernel_rodata_end: u8;
+    static __kernel_data_start: u8;
+    static __kernel_data_end: u8;
+}
+
+/// Hands out zeroed-or-not physical frames of `PAGE_SIZE` bytes used for page tables
+pub trait FrameSource {
+    /// Returns the physical address of an unused frame
+    fn allocate_frame(&mut self) -> Option<usize>;
+
+    /// Returns a frame received from `allocate_frame()`
+    fn free_frame(&mut self, frame: usize);
+}
+
+// page tables own their frames, so the FrameArea tokens are leaked until the table is freed
+impl FrameSource for FrameAllocator {
+    fn allocate_frame(&mut self) -> Option<usize> {
+        self.allocate().map(|frame| frame.into_raw().0)
+    }
+
+    fn free_frame(&mut self, frame: usize) {
+        // SAFETY: frame was leaked by allocate_frame()
+        self.free(unsafe { FrameArea::from_raw(frame, 1) })
+    }
+}
+
+/// Error returned by `AddressSpace` operations
+///
+/// ## Variants:
+/// - `OutOfFrames` : The `FrameSource` could not provide a frame for a new table
+/// - `AlreadyMapped` : The virtual address is already mapped
+/// - `NotMapped` : The virtual address is not mapped
+/// - `HugePageConflict` : A huge page covers the requested address, it must be split first
+/// - `Misaligned` : An address is not aligned to the page size
+/// - `NotMergeable` : The pages can not be merged into a huge page
+#[derive(Debug)]
+pub enum PagingError {
+    OutOfFrames,
+    AlreadyMapped,
+    NotMapped,
+    HugePageConflict,
+    Misaligned,
+    NotMergeable,
+}
+
+/// Page sizes supported by the amd64 MMU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSize {
+    Size4KiB,
+    Size2MiB,
+    Size1GiB,
+}
+
+impl PageSize {
+    pub const fn bytes(&self) -> usize {
+        match self {
+            PageSize::Size4KiB => PAGE_SIZE,
+            PageSize::Size2MiB => PAGE_SIZE * TABLE_ENTRIES,
+            PageSize::Size1GiB => PAGE_SIZE * TABLE_ENTRIES * TABLE_ENTRIES,
+        }
+    }
+
+    /// table level that holds entries of this size, 1 is the lowest level
+    const fn level(&self) -> usize {
+        match self {
+            PageSize::Size4KiB => 1,
+            PageSize::Size2MiB => 2,
+            PageSize::Size1GiB => 3,
+        }
+    }
+
+    const fn from_level(level: usize) -> Option<Self> {
+        match level {
+            1 => Some(PageSize::Size4KiB),
+            2 => Some(PageSize::Size2MiB),
+            3 => Some(PageSize::Size1GiB),
+            _ => None,
+        }
+    }
+
+    /// next smaller size, used when splitting
+    const fn smaller(&self) -> Option<Self> {
+        Self::from_level(self.level() - 1)
+    }
+}
+
+/// Memory types selectable through the PAT, see `PAT_VALUE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheType {
+    WriteBack,
+    WriteThrough,
+    UncachedMinus,
+    Uncached,
+    WriteCombining,
+    WriteProtected,
+}
+
+impl CacheType {
+    const fn pat_index(&self) -> u64 {
+        match self {
+            CacheType::WriteBack => 0,
+            CacheType::WriteThrough => 1,
+            CacheType::UncachedMinus => 2,
+            CacheType::Uncached => 3,
+            CacheType::WriteCombining => 4,
+            CacheType::WriteProtected => 5,
+        }
+    }
+
+    const fn from_pat_index(index: u64) -> Self {
+        match index {
+            1 => CacheType::WriteThrough,
+            2 => CacheType::UncachedMinus,
+            3 => CacheType::Uncached,
+            4 => CacheType::WriteCombining,
+            5 => CacheType::WriteProtected,
+            _ => CacheType::WriteBack,
+        }
+    }
+}
+
+/// Access rights & memory type of a mapped page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags {
+    pub writable: bool,
+    pub user: bool,
+    pub executable: bool,
+    /// kept in the TLB across address space switches
+    pub global: bool,
+    pub cache: CacheType,
+}
+
+impl PageFlags {
+    pub const KERNEL_CODE: Self = Self {
+        writable: false,
+        user: false,
+        executable: true,
+        global: true,
+        cache: CacheType::WriteBack,
+    };
+
+    pub const KERNEL_RODATA: Self = Self {
+        writable: false,
+        executable: false,
+        ..Self::KERNEL_CODE
+    };
+
+    pub const KERNEL_DATA: Self = Self {
+        writable: true,
+        executable: false,
+        ..Self::KERNEL_CODE
+    };
+
+    /// memory mapped devices
+    pub const KERNEL_MMIO: Self = Self {
+        cache: CacheType::Uncached,
+        ..Self::KERNEL_DATA
+    };
+
+    pub const USER_DATA: Self = Self {
+        writable: true,
+        user: true,
+        executable: false,
+        global: false,
+        cache: CacheType::WriteBack,
+    };
+
+    /// encode into the bits of a leaf entry of `size`
+    const fn encode(&self, size: PageSize) -> u64 {
+        let mut bits = ENTRY_PRESENT;
+        if self.writable {
+            bits |= ENTRY_WRITABLE;
+        }
+        if self.user {
+            bits |= ENTRY_USER;
+        }
+        if !self.executable {
+            bits |= ENTRY_NO_EXECUTE;
+        }
+        if self.global {
+            bits |= ENTRY_GLOBAL;
+        }
+        let pat = self.cache.pat_index();
+        if pat & 0b001 != 0 {
+            bits |= ENTRY_WRITE_THROUGH;
+        }
+        if pat & 0b010 != 0 {
+            bits |= ENTRY_CACHE_DISABLE;
+        }
+        match size {
+            PageSize::Size4KiB => {
+                if pat & 0b100 != 0 {
+                    bits |= ENTRY_PAT_SMALL;
+                }
+            }
+            _ => {
+                bits |= ENTRY_HUGE;
+                if pat & 0b100 != 0 {
+                    bits |= ENTRY_PAT_HUGE;
+                }
+            }
+        }
+        bits
+    }
+
+    /// decode from a leaf entry of `size`
+    const fn decode(entry: u64, size: PageSize) -> Self {
+        let pat_bit = match size {
+            PageSize::Size4KiB => ENTRY_PAT_SMALL,
+            _ => ENTRY_PAT_HUGE,
+        };
+        let pat = (entry & ENTRY_WRITE_THROUGH != 0) as u64
+            | ((entry & ENTRY_CACHE_DISABLE != 0) as u64) << 1
+            | ((entry & pat_bit != 0) as u64) << 2;
+        Self {
+            writable: entry & ENTRY_WRITABLE != 0,
+            user: entry & ENTRY_USER != 0,
+            executable: entry & ENTRY_NO_EXECUTE == 0,
+            global: entry & ENTRY_GLOBAL != 0,
+            cache: CacheType::from_pat_index(pat),
+        }
+    }
+}
+
+type PageTable = [u64; TABLE_ENTRIES];
+
+/// access a page table through the higher half direct map
+fn table(phys: usize) -> &'static mut PageTable {
+    // SAFETY: the HHDM covers all physical memory & phys is a page table owned by an AddressSpace
+    unsafe { &mut *((phys + limine::hhdm()) as *mut PageTable) }
+}
+
+/// index of `virt` in a table of `level`
+const fn table_index(virt: usize, level: usize) -> usize {
+    (virt >> (12 + 9 * (level - 1))) & (TABLE_ENTRIES - 1)
+}
+
+/// physical address stored in a leaf entry of `size`, without the huge page PAT bit
+const fn leaf_address(entry: u64, size: PageSize) -> usize {
+    (entry & ADDRESS_MASK) as usize & !(size.bytes() - 1)
+}
+
+const fn is_aligned(addr: usize, align: usize) -> bool {
+    addr & (align - 1) == 0
+}
+
+const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// Virtual address space defined by a tree of page tables
+pub struct AddressSpace {
+    /// physical address of the PML4 or PML5
+    root: usize,
+    /// 4 or 5 depending on LA57
+    levels: usize,
+}
+
+impl AddressSpace {
+    /// Creates an empty address space with the same paging depth as the current one
+    pub fn new(frames: &mut dyn FrameSource) -> Result<Self, PagingError> {
+        Ok(Self {
+            root: Self::new_table(frames)?,
+            levels: current_levels(),
+        })
+    }
+
+    /// Wraps the address space currently loaded in CR3
+    ///
+    /// ## SAFETY: Another `AddressSpace` may already manage the same tables
+    pub unsafe fn current() -> Self {
+        Self {
+            root: (cr3() & ADDRESS_MASK) as usize,
+            levels: current_levels(),
+        }
+    }
+
+    /// physical address of the root table
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    /// number of table levels, 5 when LA57 is active
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    /// Loads this address space into CR3
+    ///
+    /// ## SAFETY: the running code, its stack and all used data must be mapped
+    pub unsafe fn activate(&self) {
+        cr3_write(self.root as u64);
+    }
+
+    fn is_active(&self) -> bool {
+        unsafe { (cr3() & ADDRESS_MASK) as usize == self.root }
+    }
+
+    fn flush(&self, virt: usize) {
+        if self.is_active() {
+            unsafe { tlb::flush(virt) };
+        }
+    }
+
+    fn flush_all(&self) {
+        if self.is_active() {
+            unsafe { tlb::flush_all() };
+        }
+    }
+
+    fn new_table(frames: &mut dyn FrameSource) -> Result<usize, PagingError> {
+        let frame = frames.allocate_frame().ok_or(PagingError::OutOfFrames)?;
+        table(frame).fill(0);
+        Ok(frame)
+    }
+
+    /// Walks down to the table of `level` that covers `virt`, returns its physical address.
+    /// Missing tables are created if `frames` is provided.
+    fn walk(
+        &mut self,
+        virt: usize,
+        level: usize,
+        mut frames: Option<&mut dyn FrameSource>,
+    ) -> Result<usize, PagingError> {
+        let mut current = self.root;
+        for l in ((level + 1)..=self.levels).rev() {
+            let entry = &mut table(current)[table_index(virt, l)];
+            if *entry & ENTRY_PRESENT == 0 {
+                let frames = frames.as_deref_mut().ok_or(PagingError::NotMapped)?;
+                // access rights are decided by the leaf entries
+                *entry =
+                    Self::new_table(frames)? as u64 | ENTRY_PRESENT | ENTRY_WRITABLE | ENTRY_USER;
+            } else if *entry & ENTRY_HUGE != 0 {
+                return Err(PagingError::HugePageConflict);
+            }
+            current = (*entry & ADDRESS_MASK) as usize;
+        }
+        Ok(current)
+    }
+
+    /// Finds the leaf entry that maps `virt`, returns it with its size
+    fn leaf(&self, virt: usize) -> Result<(&'static mut u64, PageSize), PagingError> {
+        let mut current = self.root;
+        for l in (1..=self.levels).rev() {
+            let entry = &mut table(current)[table_index(virt, l)];
+            if *entry & ENTRY_PRESENT == 0 {
+                return Err(PagingError::NotMapped);
+            }
+            if l == 1 || *entry & ENTRY_HUGE != 0 {
+                return PageSize::from_level(l)
+                    .map(|size| (entry, size))
+                    .ok_or(PagingError::NotMapped);
+            }
+            current = (*entry & ADDRESS_MASK) as usize;
+        }
+        Err(PagingError::NotMapped)
+    }
+
+    /// Translates `virt` into its physical address if ring 3 may access it, which requires the
+    /// user bit on every level
+    pub fn translate_user(&self, virt: usize) -> Option<usize> {
+        let mut current = self.root;
+        for l in (1..=self.levels).rev() {
+            let entry = table(current)[table_index(virt, l)];
+            if entry & ENTRY_PRESENT == 0 || entry & ENTRY_USER == 0 {
+                return None;
+            }
+            if l == 1 || entry & ENTRY_HUGE != 0 {
+                let size = PageSize::from_level(l)?;
+                return Some(leaf_address(entry, size) + (virt & (size.bytes() - 1)));
+            }
+            current = (entry & ADDRESS_MASK) as usize;
+        }
+        None
+    }
+
+    /// Maps a single page of `size` at `virt` to the frame at `phys`
+    pub fn map(
+        &mut self,
+        virt: usize,
+        phys: usize,
+        size: PageSize,
+        flags: PageFlags,
+        frames: &mut dyn FrameSource,
+    ) -> Result<(), PagingError> {
+        if !is_aligned(virt, size.bytes()) || !is_aligned(phys, size.bytes()) {
+            return Err(PagingError::Misaligned);
+        }
+        let table_phys = self.walk(virt, size.level(), Some(frames))?;
+        let entry = &mut table(table_phys)[table_index(virt, size.level())];
+        if *entry & ENTRY_PRESENT != 0 {
+            return Err(PagingError::AlreadyMapped);
+        }
+        *entry = phys as u64 | flags.encode(size);
+        Ok(())
+    }
+
+    /// Maps `(phys, phys + length)` at `virt` using the biggest pages the alignment allows
+    pub fn map_range(
+        &mut self,
+        virt: usize,
+        phys: usize,
+        length: usize,
+        flags: PageFlags,
+        frames: &mut dyn FrameSource,
+    ) -> Result<(), PagingError> {
+        if !is_aligned(virt, PAGE_SIZE) || !is_aligned(phys, PAGE_SIZE) {
+            return Err(PagingError::Misaligned);
+        }
+        let huge_1gib = has_1gib_pages();
+        let mut offset = 0;
+        while offset < align_up(length, PAGE_SIZE) {
+            let remaining = length - offset;
+            let fits = |size: PageSize| {
+                remaining >= size.bytes()
+                    && is_aligned(virt + offset, size.bytes())
+                    && is_aligned(phys + offset, size.bytes())
+            };
+            let size = if huge_1gib && fits(PageSize::Size1GiB) {
+                PageSize::Size1GiB
+            } else if fits(PageSize::Size2MiB) {
+                PageSize::Size2MiB
+            } else {
+                PageSize::Size4KiB
+            };
+            self.map(virt + offset, phys + offset, size, flags, frames)?;
+            offset += size.bytes();
+        }
+        Ok(())
+    }
+
+    /// Removes the page that maps `virt`, returns the physical frame and size it covered
+    pub fn unmap(&mut self, virt: usize) -> Result<(usize, PageSize), PagingError> {
+        let (entry, size) = self.leaf(virt)?;
+        let phys = leaf_address(*entry, size);
+        *entry = 0;
+        self.flush(virt);
+        Ok((phys, size))
+    }
+
+    /// Changes the flags of the page that maps `virt`
+    pub fn protect(&mut self, virt: usize, flags: PageFlags) -> Result<(), PagingError> {
+        let (entry, size) = self.leaf(virt)?;
+        *entry = leaf_address(*entry, size) as u64 | flags.encode(size);
+        self.flush(virt);
+        Ok(())
+    }
+
+    /// Translates `virt` into its physical address, returns the page size and flags as well
+    pub fn translate(&self, virt: usize) -> Option<(usize, PageSize, PageFlags)> {
+        let (entry, size) = self.leaf(virt).ok()?;
+        Some((
+            leaf_address(*entry, size) + (virt & (size.bytes() - 1)),
+            size,
+            PageFlags::decode(*entry, size),
+        ))
+    }
+
+    /// Replaces the huge page covering `virt` with a table of the next smaller pages
+    pub fn split(&mut self, virt: usize, frames: &mut dyn FrameSource) -> Result<(), PagingError> {
+        let (entry, size) = self.leaf(virt)?;
+        let smaller = size.smaller().ok_or(PagingError::NotMergeable)?;
+        let base = leaf_address(*entry, size);
+        let flags = PageFlags::decode(*entry, size);
+
+        let child = Self::new_table(frames)?;
+        for (i, e) in table(child).iter_mut().enumerate() {
+            *e = (base + i * smaller.bytes()) as u64 | flags.encode(smaller);
+        }
+        *entry = child as u64 | ENTRY_PRESENT | ENTRY_WRITABLE | ENTRY_USER;
+        self.flush_all();
+        Ok(())
+    }
+
+    /// Replaces the table of pages covering `virt` with a single page of `size`, if all of them
+    /// are mapped contiguously with the same flags
+    pub fn merge(
+        &mut self,
+        virt: usize,
+        size: PageSize,
+        frames: &mut dyn FrameSource,
+    ) -> Result<(), PagingError> {
+        let smaller = size.smaller().ok_or(PagingError::NotMergeable)?;
+        let virt = virt & !(size.bytes() - 1);
+        let table_phys = self.walk(virt, size.level(), None)?;
+        let entry = &mut table(table_phys)[table_index(virt, size.level())];
+        if *entry & ENTRY_PRESENT == 0 {
+            return Err(PagingError::NotMapped);
+        }
+        if *entry & ENTRY_HUGE != 0 {
+            // already merged
+            return Ok(());
+        }
+
+        let child = (*entry & ADDRESS_MASK) as usize;
+        let first = table(child)[0];
+        let base = leaf_address(first, smaller);
+        let flags = PageFlags::decode(first, smaller);
+        if !is_aligned(base, size.bytes()) {
+            return Err(PagingError::NotMergeable);
+        }
+        for (i, e) in table(child).iter().enumerate() {
+            let present = *e & ENTRY_PRESENT != 0;
+            let leaf = smaller == PageSize::Size4KiB || *e & ENTRY_HUGE != 0;
+            if !present
+                || !leaf
+                || leaf_address(*e, smaller) != base + i * smaller.bytes()
+                || PageFlags::decode(*e, smaller) != flags
+            {
+                return Err(PagingError::NotMergeable);
+            }
+        }
+        *entry = base as u64 | flags.encode(size);
+        self.flush_all();
+        frames.free_frame(child);
+        Ok(())
+    }
+}
+
+/// number of page table levels in use
+fn current_levels() -> usize {
+    if unsafe { cr4() }.contains(Cr4::CR4_ENABLE_LA57) {
+        5
+    } else {
+        4
+    }
+}
+
+fn has_1gib_pages() -> bool {
+    CpuId::new()
+        .get_extended_processor_and_feature_identifiers()
+        .map_or(false, |f| f.has_1gib_pages())
+}
+
+// ========== Bootstrap
+
+/// `FrameSource` that hands out frames from a single claimed region, freed frames are leaked.
+/// Used to build the kernel page tables before any other allocator exists, `finish()` it after.
+pub struct BootstrapFrames {
+    area: MapArea,
+    next: usize,
+    end: usize,
+}
+
+impl BootstrapFrames {
+    /// Claims `size` bytes of usable memory from `GLOBAL_MEMORY_MAPPER`
+    pub fn claim(size: usize) -> Option<Self> {
+        let size = align_up(size, PAGE_SIZE);
+        for region in limine::memory_map() {
+            if let limine::MemmapEntryType::Usable = region.typ {
+                let start = align_up(region.range.0, PAGE_SIZE);
+                if start + size > region.range.1 {
+                    continue;
+                }
+                // the region may already be partially claimed, try the next one
+                if let Ok(area) = map::claim_global((start, start + size)) {
+                    return Some(Self {
+                        area,
+                        next: start,
+                        end: start + size,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// Keeps the handed out frames claimed for good, they hold page tables for the whole kernel
+    /// runtime. The unused rest of the claim is freed.
+    pub fn finish(self) {
+        let Self { mut area, next, .. } = self;
+        let mapper = map::GLOBAL_MEMORY_MAPPER
+            .get()
+            .expect("GLOBAL_MEMORY_MAPPER not setup!");
+        let used = (area.start(), next);
+        if used.0 == used.1 {
+            mapper.free(area);
+            return;
+        }
+        mapper
+            .shrink(&mut area, used)
+            .expect("Bootstrap frames lie outside of their claim!");
+        area.into_raw();
+    }
+}
+
+impl FrameSource for BootstrapFrames {
+    fn allocate_frame(&mut self) -> Option<usize> {
+        if self.next >= self.end {
+            return None;
+        }
+        self.next += PAGE_SIZE;
+        Some(self.next - PAGE_SIZE)
+    }
+
+    fn free_frame(&mut self, _frame: usize) {}
+}
+
+/// Enables NX, global pages and programs the PAT used by `CacheType`
+fn enable_features() {
+    let has_nx = CpuId::new()
+        .get_extended_processor_and_feature_identifiers()
+        .map_or(false, |f| f.has_execute_disable());
+    // PageFlags always encode the NX bit, it is reserved without EFER.NXE
+    assert!(has_nx, "CPU does not support no-execute pages!");
+    unsafe {
+        wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_NO_EXECUTE_ENABLE);
+        cr4_write(cr4() | Cr4::CR4_ENABLE_GLOBAL_PAGES);
+        wrmsr(IA32_PAT, PAT_VALUE);
+    }
+}
+
+/// Memory type of a memory map entry in the HHDM, None for entries left unmapped as they may
+/// hold MMIO (`Reserved`) or must not be touched (`BadMemory`)
+fn hhdm_cache(typ: MemmapEntryType) -> Option<CacheType> {
+    match typ {
+        MemmapEntryType::Reserved | MemmapEntryType::BadMemory => None,
+        MemmapEntryType::MemmapFramebuffer => Some(CacheType::WriteCombining),
+        _ => Some(CacheType::WriteBack),
+    }
+}
+
+/// Flags of a memory map entry in the identity map, None for entries the limine terminal does
+/// not use. Not global as the identity map lives in the lower half.
+fn identity_flags(typ: MemmapEntryType) -> Option<PageFlags> {
+    let data = PageFlags {
+        global: false,
+        ..PageFlags::KERNEL_DATA
+    };
+    match typ {
+        // the terminal code & its data
+        MemmapEntryType::BootloaderReclaimable => Some(PageFlags {
+            executable: true,
+            ..data
+        }),
+        MemmapEntryType::MemmapFramebuffer => Some(PageFlags {
+            cache: CacheType::WriteCombining,
+            ..data
+        }),
+        _ => None,
+    }
+}
+
+/// Page aligned `(start, end, flags)` of the identity map inside the first 4GiB, page 0 stays
+/// unmapped to catch null pointers
+fn identity_ranges() -> impl Iterator<Item = (usize, usize, PageFlags)> {
+    let mut mapped_end = PAGE_SIZE;
+    limine::memory_map().filter_map(move |region| {
+        let flags = identity_flags(region.typ)?;
+        let start = (region.range.0 & !(PAGE_SIZE - 1)).max(mapped_end);
+        let end = align_up(region.range.1, PAGE_SIZE).min(IDENTITY_MAP_SIZE);
+        if start >= end {
+            return None;
+        }
+        mapped_end = end;
+        Some((start, end, flags))
+    })
+}
+
+/// Builds the kernel page tables and switches CR3 away from the bootloader tables.
+///
+/// Mapped regions:
+/// - the HHDM over the memory map entries, RAM write-back & the framebuffer write-combining
+/// - an identity map of the bootloader reclaimable memory & the framebuffer, required by the
+///   limine terminal until `remove_identity_map()`
+/// - the kernel image, with permissions per section
+pub fn init(frames: &mut dyn FrameSource) {
+    enable_features();
+    let mut space = AddressSpace::new(frames).expect("Could not allocate the kernel page table!");
+
+    // HHDM, MMIO holes between the entries stay unmapped
+    let mut mapped_end = 0;
+    for region in limine::memory_map() {
+        let cache = match hhdm_cache(region.typ) {
+            Some(cache) => cache,
+            None => continue,
+        };
+        // the framebuffer entry is not required to be page aligned
+        let start = (region.range.0 & !(PAGE_SIZE - 1)).max(mapped_end);
+        let end = align_up(region.range.1, PAGE_SIZE);
+        if start >= end {
+            continue;
+        }
+        let flags = PageFlags {
+            cache,
+            ..PageFlags::KERNEL_DATA
+        };
+        space
+            .map_range(limine::hhdm() + start, start, end - start, flags, frames)
+            .expect("Could not map the HHDM!");
+        mapped_end = end;
+    }
+
+    // identity map for the limine terminal
+    for (start, end, flags) in identity_ranges() {
+        space
+            .map_range(start, start, end - start, flags, frames)
+            .expect("Could not identity map the bootloader facilities!");
+    }
+
+    // kernel image
+    let virt_base = limine::kernel_address_virtual();
+    let phys_base = limine::kernel_address_physical();
+    let sections = unsafe {
+        [
+            (
+                &__kernel_text_start as *const u8,
+                &__kernel_text_end as *const u8,
+                PageFlags::KERNEL_CODE,
+            ),
+            (
+                &__kernel_rodata_start as *const u8,
+                &__kernel_rodata_end as *const u8,
+                PageFlags::KERNEL_RODATA,
+            ),
+            (
+                &__kernel_data_start as *const u8,
+                &__kernel_data_end as *const u8,
+                PageFlags::KERNEL_DATA,
+            ),
+        ]
+    };
+    for (start, end, flags) in sections {
+        let start = start as usize & !(PAGE_SIZE - 1);
+        let end = align_up(end as usize, PAGE_SIZE);
+        let mut virt = start;
+        while virt < end {
+            space
+                .map(
+                    virt,
+                    virt - virt_base + phys_base,
+                    PageSize::Size4KiB,
+                    flags,
+                    frames,
+                )
+                .expect("Could not map the kernel image!");
+            virt += PAGE_SIZE;
+        }
+    }
+
+    // interrupt stack guards, a stack overflow now causes a page fault
+    for cpu in 0..CPU_MAX_COUNT {
+        for (start, end) in gdt::stack_guards(cpu) {
+            let mut virt = start;
+            while virt < end {
+                space
+                    .unmap(virt)
+                    .expect("Stack guard is not part of the kernel image!");
+                virt += PAGE_SIZE;
+            }
+        }
+    }
+
+    unsafe { space.activate() };
+    info!(
+        "Switched to kernel page tables at 0x{:X} ({} levels)",
+        space.root(),
+        space.levels()
+    );
+    KERNEL_SPACE.call_once(|| Mutex::new(space));
+}
+
+/// Unmaps the identity map of `init()` once the limine terminal is no longer used, the page
+/// tables it used stay allocated
+pub fn remove_identity_map() {
+    let mut space = KERNEL_SPACE.get().expect("KERNEL_SPACE not setup!").lock();
+    for (start, end, _) in identity_ranges() {
+        let mut virt = start;
+        while virt < end {
+            // pages may already be unmapped by an earlier call
+            virt += space
+                .unmap(virt)
+                .map_or(PAGE_SIZE, |(_, size)| size.bytes());
+        }
+    }
+}
+
+// kernel tests, see `make test-x86_64`
+#[cfg(all(test, target_os = "none"))]
+mod tests {
+    use super::*;
+
+    static RODATA: [u8; 4] = [1, 2, 3, 4];
+    static mut DATA: [u8; 4] = [0; 4];
+
+    fn kernel_space() -> spin::MutexGuard<'static, AddressSpace> {
+        KERNEL_SPACE.get().expect("KERNEL_SPACE not setup!").lock()
+    }
+
+    #[test_case]
+    fn kernel_sections_have_their_flags() {
+        let space = kernel_space();
+        let (_, _, code) = space.translate(kernel_space as usize).unwrap();
+        assert_eq!(code, PageFlags::KERNEL_CODE);
+        let (_, _, rodata) = space.translate(&RODATA as *const _ as usize).unwrap();
+        assert_eq!(rodata, PageFlags::KERNEL_RODATA);
+        let (_, _, data) = space
+            .translate(unsafe { &DATA } as *const _ as usize)
+            .unwrap();
+        assert_eq!(data, PageFlags::KERNEL_DATA);
+    }
+
+    #[test_case]
+    fn symbol_table_is_mapped() {
+        extern "C" {
+            static __kernel_symtab_start: u8;
+        }
+        let start = unsafe { &__kernel_symtab_start as *const u8 as usize };
+        let (_, _, flags) = kernel_space().translate(start).unwrap();
+        assert_eq!(flags, PageFlags::KERNEL_RODATA);
+        // the test binary is not filled by ksymtab.sh, but reading the table must not fault
+        if let Some(symbol) = crate::backtrace::resolve(crate::kmain as usize) {
+            assert_eq!(symbol.name, "kmain");
+        }
+    }
+
+    #[test_case]
+    fn hhdm_maps_physical_memory() {
+        let space = kernel_space();
+        let phys = limine::kernel_address_physical();
+        let (translated, _, flags) = space.translate(phys + limine::hhdm()).unwrap();
+        assert_eq!(translated, phys);
+        assert!(!flags.executable);
+    }
+
+    #[test_case]
+    fn identity_map_leaves_page_0_unmapped() {
+        let space = kernel_space();
+        assert!(space.translate(0).is_none());
+        for (start, _, flags) in identity_ranges() {
+            let (phys, _, mapped) = space.translate(start).unwrap();
+            assert_eq!((phys, mapped), (start, flags));
+        }
+    }
+
+    #[test_case]
+    fn hhdm_maps_entries_by_type() {
+        let space = kernel_space();
+        for region in limine::memory_map() {
+            let virt = (region.range.0 & !(PAGE_SIZE - 1)) + limine::hhdm();
+            match hhdm_cache(region.typ) {
+                Some(cache) => assert_eq!(space.translate(virt).unwrap().2.cache, cache),
+                // a neighbouring entry may share the page
+                None if !is_aligned(region.range.0, PAGE_SIZE) => {}
+                None => assert!(space.translate(virt).is_none()),
+            }
+        }
+    }
+
+    #[test_case]
+    fn stack_guards_are_unmapped() {
+        let space = kernel_space();
+        for (start, _) in gdt::stack_guards(0) {
+            assert!(space.translate(start).is_none());
+        }
+    }
+}
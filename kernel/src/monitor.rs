@@ -0,0 +1,522 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Interactive debug monitor on the serial console & the keyboard.
+//!
+//! With `DEBUG_MONITOR` set in `config/` `kmain()` ends in `run()` instead of the final panic. It
+//! reads command lines from `arch::serial::SERIAL0` & `arch::keyboard` and writes the output to the
+//! console & the serial port, `help` lists the commands. Numbers are decimal or hexadecimal with
+//! a `0x` prefix. With `GDB_STUB` set the serial port belongs to gdb, only the keyboard is read.
+
+use crate::arch;
+use crate::config;
+use crate::crashdump;
+use crate::limine;
+use crate::log::{self, SerialSink, Sink, TerminalSink};
+use crate::memman::map::{MemoryMapper, GLOBAL_MEMORY_MAPPER};
+use crate::video::{self, ConsoleSink};
+use core::fmt::{self, Write};
+use tinyvec::ArrayVec;
+
+/// Max ammount of bytes in a command line
+const LINE_MAX: usize = 128;
+
+/// Max ammount of bytes dumped by one `peek`
+const PEEK_MAX: usize = 4096;
+
+/// Bytes dumped by `peek` without a count
+const PEEK_DEFAULT: usize = 64;
+
+/// Max ammount of bytes written by one `poke`
+const POKE_MAX: usize = 16;
+
+/// Lines scrolled back by `scroll` without a count
+const SCROLL_DEFAULT: usize = 20;
+
+/// Bytes per line of `write_hex_dump()`
+const HEX_DUMP_WIDTH: usize = 16;
+
+const HELP: &str = "\
+help                  this list
+map                   claimed regions of GLOBAL_MEMORY_MAPPER
+gaps                  unclaimed regions of GLOBAL_MEMORY_MAPPER
+memmap                memory map reported by limine
+pt ADDRESS            page table translation of a virtual address
+peek ADDRESS [COUNT]  hex dump of physical memory
+poke ADDRESS BYTE...  writes bytes to physical memory
+threads               threads of execution
+dmesg [SEQUENCE]      log records, from SEQUENCE on
+crash                 crash report of the previous boot
+scroll [LINES]        shows the console LINES lines back, typing returns
+exit                  leaves the monitor
+";
+
+/// Error returned by `Command::parse()`
+///
+/// ## Variants:
+/// - `UnknownCommand` : The first word is not a command
+/// - `MissingArgument` : The command needs more arguments
+/// - `TooManyArguments` : The command takes less arguments
+/// - `InvalidNumber` : An argument is not a number or does not fit
+#[derive(Debug, PartialEq, Eq)]
+pub enum MonitorError {
+    UnknownCommand,
+    MissingArgument,
+    TooManyArguments,
+    InvalidNumber,
+}
+
+/// A parsed command line, see `HELP`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Map,
+    Gaps,
+    MemoryMap,
+    PageTable(usize),
+    Peek(usize, usize),
+    Poke(usize, ArrayVec<[u8; POKE_MAX]>),
+    Threads,
+    Dmesg(u64),
+    Crash,
+    Scroll(usize),
+    Exit,
+}
+
+impl Command {
+    /// Parses a command line, returns None if it is empty
+    pub fn parse(line: &str) -> Result<Option<Self>, MonitorError> {
+        let mut words = line.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let command = match name {
+            "help" => Command::Help,
+            "map" => Command::Map,
+            "gaps" => Command::Gaps,
+            "memmap" => Command::MemoryMap,
+            "pt" => Command::PageTable(parse_number(words.next())?),
+            "peek" => {
+                let address = parse_number(words.next())?;
+                let count = match words.next() {
+                    Some(word) => parse_number(Some(word))?,
+                    None => PEEK_DEFAULT,
+                };
+                if count > PEEK_MAX {
+                    return Err(MonitorError::InvalidNumber);
+                }
+                Command::Peek(address, count)
+            }
+            "poke" => {
+                let address = parse_number(words.next())?;
+                let mut bytes = ArrayVec::new();
+                for word in words.by_ref() {
+                    let byte = parse_number(Some(word))?;
+                    let byte = u8::try_from(byte).map_err(|_| MonitorError::InvalidNumber)?;
+                    if bytes.try_push(byte).is_some() {
+                        return Err(MonitorError::TooManyArguments);
+                    }
+                }
+                if bytes.is_empty() {
+                    return Err(MonitorError::MissingArgument);
+                }
+                Command::Poke(address, bytes)
+            }
+            "threads" => Command::Threads,
+            "dmesg" => match words.next() {
+                Some(word) => Command::Dmesg(parse_number(Some(word))? as u64),
+                None => Command::Dmesg(0),
+            },
+            "crash" => Command::Crash,
+            "scroll" => match words.next() {
+                Some(word) => Command::Scroll(parse_number(Some(word))?),
+                None => Command::Scroll(SCROLL_DEFAULT),
+            },
+            "exit" => Command::Exit,
+            _ => return Err(MonitorError::UnknownCommand),
+        };
+        match words.next() {
+            Some(_) => Err(MonitorError::TooManyArguments),
+            None => Ok(Some(command)),
+        }
+    }
+}
+
+/// decimal or `0x` hexadecimal number
+fn parse_number(word: Option<&str>) -> Result<usize, MonitorError> {
+    let word = word.ok_or(MonitorError::MissingArgument)?;
+    let result = match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    result.map_err(|_| MonitorError::InvalidNumber)
+}
+
+/// What the caller of `LineEditor::push()` shows on the console
+#[derive(Debug, PartialEq, Eq)]
+enum Edit {
+    Ignore,
+    Echo(u8),
+    Erase,
+    /// the line is complete, see `LineEditor::line()`
+    Enter,
+}
+
+/// Collects typed bytes into a command line
+struct LineEditor {
+    buf: [u8; LINE_MAX],
+    length: usize,
+}
+
+impl LineEditor {
+    const fn new() -> Self {
+        Self {
+            buf: [0; LINE_MAX],
+            length: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Edit {
+        match byte {
+            b'\r' | b'\n' => Edit::Enter,
+            // backspace & delete, terminals send either one
+            0x08 | 0x7F if self.length > 0 => {
+                self.length -= 1;
+                Edit::Erase
+            }
+            0x20..=0x7E if self.length < LINE_MAX => {
+                self.buf[self.length] = byte;
+                self.length += 1;
+                Edit::Echo(byte)
+            }
+            _ => Edit::Ignore,
+        }
+    }
+
+    fn line(&self) -> &str {
+        // only printable ascii is pushed
+        core::str::from_utf8(&self.buf[..self.length]).unwrap()
+    }
+
+    fn clear(&mut self) {
+        self.length = 0;
+    }
+}
+
+/// Writes the monitor output to the framebuffer console or the limine terminal & the serial port,
+/// but not to the log
+struct Console;
+
+impl Sink for Console {
+    fn write(&self, s: &str) {
+        match video::console::is_active() {
+            true => ConsoleSink.write(s),
+            false => TerminalSink.write(s),
+        }
+        if !config::GDB_STUB {
+            SerialSink.write(s);
+        }
+    }
+}
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        Sink::write(self, s);
+        Ok(())
+    }
+}
+
+/// next typed byte of the serial port or the keyboard, if any
+fn read_byte() -> Option<u8> {
+    if !config::GDB_STUB {
+        if let Some(byte) = arch::serial::SERIAL0.lock().read_byte() {
+            return Some(byte);
+        }
+    }
+    arch::keyboard::read_byte()
+}
+
+/// Runs the monitor until `exit` is entered
+pub fn run() {
+    let mut console = Console;
+    let mut editor = LineEditor::new();
+    console
+        .write_str("[ Debug Monitor ]\ntype `help` for a list of commands\n> ")
+        .unwrap();
+    loop {
+        let byte = match read_byte() {
+            Some(byte) => byte,
+            None => {
+                core::hint::spin_loop();
+                continue;
+            }
+        };
+        // back to the bottom of the console after `scroll`
+        video::console::scroll_view(0);
+        match editor.push(byte) {
+            Edit::Ignore => {}
+            Edit::Echo(byte) => console.write_char(byte as char).unwrap(),
+            Edit::Erase => console.write_str("\x08 \x08").unwrap(),
+            Edit::Enter => {
+                console.write_str("\n").unwrap();
+                match Command::parse(editor.line()) {
+                    Ok(Some(Command::Exit)) => return,
+                    Ok(Some(command)) => execute(command, &mut console),
+                    Ok(None) => {}
+                    Err(e) => writeln!(console, "error: {:?}", e).unwrap(),
+                }
+                editor.clear();
+                console.write_str("> ").unwrap();
+            }
+        }
+    }
+}
+
+fn execute(command: Command, console: &mut Console) {
+    match command {
+        Command::Help => console.write_str(HELP).unwrap(),
+        Command::Map => {
+            for (start, end) in GLOBAL_MEMORY_MAPPER.get().unwrap().iter() {
+                writeln!(console, "0x{:016X} - 0x{:016X}", start, end).unwrap();
+            }
+        }
+        Command::Gaps => {
+            for (start, end) in GLOBAL_MEMORY_MAPPER.get().unwrap().gaps() {
+                writeln!(console, "0x{:016X} - 0x{:016X}", start, end).unwrap();
+            }
+        }
+        Command::MemoryMap => {
+            for region in limine::memory_map() {
+                let (start, end) = region.range;
+                let name: &str = region.typ.into();
+                writeln!(console, "0x{:016X} - 0x{:016X} {}", start, end, name).unwrap();
+            }
+        }
+        Command::PageTable(virt) => page_table(virt, console),
+        Command::Peek(address, count) => {
+            if !in_memory_map(address, count) {
+                console
+                    .write_str("error: not inside the memory map\n")
+                    .unwrap();
+                return;
+            }
+            let mut buf = [0; HEX_DUMP_WIDTH];
+            for offset in (0..count).step_by(HEX_DUMP_WIDTH) {
+                let line = &mut buf[..HEX_DUMP_WIDTH.min(count - offset)];
+                for (i, byte) in line.iter_mut().enumerate() {
+                    let virt = limine::hhdm() + address + offset + i;
+                    // SAFETY: the memory map is mapped in the HHDM
+                    *byte = unsafe { core::ptr::read_volatile(virt as *const u8) };
+                }
+                write_hex_dump(console, address + offset, line).unwrap();
+            }
+        }
+        Command::Poke(address, bytes) => {
+            if !in_memory_map(address, bytes.len()) {
+                console
+                    .write_str("error: not inside the memory map\n")
+                    .unwrap();
+                return;
+            }
+            for (i, byte) in bytes.iter().enumerate() {
+                let virt = limine::hhdm() + address + i;
+                // SAFETY: none, the user is trusted to know what the bytes are used for
+                unsafe { core::ptr::write_volatile(virt as *mut u8, *byte) };
+            }
+        }
+        Command::Threads => {
+            console
+                .write_str("no scheduler, only the boot cpu runs\n")
+                .unwrap();
+        }
+        Command::Dmesg(sequence) => {
+            let stats = log::stats();
+            writeln!(
+                console,
+                "{} records, {} dropped ({} bytes)",
+                stats.records, stats.dropped_records, stats.dropped_bytes
+            )
+            .unwrap();
+            log::dmesg(sequence, console);
+        }
+        Command::Crash => {
+            if !crashdump::print_previous(console) {
+                console
+                    .write_str("no crash report from the previous boot\n")
+                    .unwrap();
+            }
+        }
+        Command::Scroll(lines) => {
+            if !video::console::is_active() {
+                console
+                    .write_str("error: no framebuffer console\n")
+                    .unwrap();
+                return;
+            }
+            let lines = video::console::scroll_view(lines);
+            // the view stays on the shown lines while this is written
+            writeln!(console, "scrolled back {} lines", lines).unwrap();
+        }
+        // handled by `run()`
+        Command::Exit => {}
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn page_table(virt: usize, console: &mut Console) {
+    use crate::memman::paging::AddressSpace;
+    // SAFETY: the tables are only read
+    let space = unsafe { AddressSpace::current() };
+    writeln!(
+        console,
+        "root 0x{:016X}, {} levels",
+        space.root(),
+        space.levels()
+    )
+    .unwrap();
+    match space.translate(virt) {
+        Some((phys, size, flags)) => {
+            writeln!(console, "0x{:016X} -> 0x{:016X} {:?}", virt, phys, size).unwrap();
+            writeln!(console, "{:?}", flags).unwrap();
+        }
+        None => writeln!(console, "0x{:016X} is not mapped", virt).unwrap(),
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn page_table(virt: usize, console: &mut Console) {
+    match arch::cpu::translate(virt) {
+        Some(phys) => writeln!(console, "0x{:016X} -> 0x{:016X}", virt, phys).unwrap(),
+        None => writeln!(console, "0x{:016X} is not mapped", virt).unwrap(),
+    }
+}
+
+/// true if `address..address + length` lies inside a single limine memory map entry of the HHDM
+fn in_memory_map(address: usize, length: usize) -> bool {
+    let end = match address.checked_add(length) {
+        Some(end) => end,
+        None => return false,
+    };
+    limine::memory_map().any(|region| {
+        // not mapped in the HHDM, may hold MMIO
+        let mapped = !matches!(
+            region.typ,
+            limine::MemmapEntryType::Reserved | limine::MemmapEntryType::BadMemory
+        );
+        mapped && region.range.0 <= address && end <= region.range.1
+    })
+}
+
+/// Writes `bytes` as one `address: hex |ascii|` line
+fn write_hex_dump(writer: &mut dyn Write, address: usize, bytes: &[u8]) -> fmt::Result {
+    write!(writer, "0x{:016X}:", address)?;
+    for byte in bytes {
+        write!(writer, " {:02X}", byte)?;
+    }
+    for _ in bytes.len()..HEX_DUMP_WIDTH {
+        writer.write_str("   ")?;
+    }
+    writer.write_str(" |")?;
+    for byte in bytes {
+        let c = match byte {
+            0x20..=0x7E => *byte as char,
+            _ => '.',
+        };
+        writer.write_char(c)?;
+    }
+    writer.write_str("|\n")
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+    use std::string::String;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse("  "), Ok(None));
+        assert_eq!(Command::parse("map"), Ok(Some(Command::Map)));
+        assert_eq!(
+            Command::parse("pt 0xFFFF8000"),
+            Ok(Some(Command::PageTable(0xFFFF_8000)))
+        );
+        assert_eq!(
+            Command::parse("peek 4096"),
+            Ok(Some(Command::Peek(4096, PEEK_DEFAULT)))
+        );
+        assert_eq!(
+            Command::parse(" peek  0x1000 32 "),
+            Ok(Some(Command::Peek(0x1000, 32)))
+        );
+        let mut bytes = ArrayVec::new();
+        bytes.extend([0xAB, 1]);
+        assert_eq!(
+            Command::parse("poke 0x10 0xAB 1"),
+            Ok(Some(Command::Poke(0x10, bytes)))
+        );
+        assert_eq!(Command::parse("dmesg"), Ok(Some(Command::Dmesg(0))));
+        assert_eq!(Command::parse("dmesg 7"), Ok(Some(Command::Dmesg(7))));
+        assert_eq!(
+            Command::parse("scroll"),
+            Ok(Some(Command::Scroll(SCROLL_DEFAULT)))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Command::parse("mpa"), Err(MonitorError::UnknownCommand));
+        assert_eq!(Command::parse("pt"), Err(MonitorError::MissingArgument));
+        assert_eq!(Command::parse("pt 0xZ"), Err(MonitorError::InvalidNumber));
+        assert_eq!(Command::parse("map 1"), Err(MonitorError::TooManyArguments));
+        assert_eq!(
+            Command::parse("peek 0 5000"),
+            Err(MonitorError::InvalidNumber)
+        );
+        assert_eq!(Command::parse("poke 0"), Err(MonitorError::MissingArgument));
+        assert_eq!(
+            Command::parse("poke 0 256"),
+            Err(MonitorError::InvalidNumber)
+        );
+        let long = std::format!("poke 0{}", " 1".repeat(POKE_MAX + 1));
+        assert_eq!(Command::parse(&long), Err(MonitorError::TooManyArguments));
+    }
+
+    #[test]
+    fn line_editing() {
+        let mut editor = LineEditor::new();
+        for byte in b"mpa" {
+            assert_eq!(editor.push(*byte), Edit::Echo(*byte));
+        }
+        assert_eq!(editor.push(0x7F), Edit::Erase);
+        assert_eq!(editor.push(0x08), Edit::Erase);
+        assert_eq!(editor.push(b'a'), Edit::Echo(b'a'));
+        assert_eq!(editor.push(b'p'), Edit::Echo(b'p'));
+        assert_eq!(editor.push(0x1B), Edit::Ignore);
+        assert_eq!(editor.push(b'\r'), Edit::Enter);
+        assert_eq!(editor.line(), "map");
+
+        // erasing an empty line & overlong lines are ignored
+        editor.clear();
+        assert_eq!(editor.push(0x08), Edit::Ignore);
+        for _ in 0..LINE_MAX {
+            editor.push(b'x');
+        }
+        assert_eq!(editor.push(b'x'), Edit::Ignore);
+        assert_eq!(editor.line().len(), LINE_MAX);
+    }
+
+    #[test]
+    fn hex_dump() {
+        let mut out = String::new();
+        write_hex_dump(&mut out, 0x1000, b"AB\n").unwrap();
+        assert_eq!(
+            out,
+            std::format!("0x0000000000001000: 41 42 0A{} |AB.|\n", "   ".repeat(13))
+        );
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Locks that are safe to take from interrupt handlers.
+//!
+//! A `spin::Mutex` held by code that gets interrupted deadlocks the cpu as soon as the handler
+//! tries to take it too. `IrqMutex` disables interrupts while it is held, so the holder can never
+//! be interrupted on its own cpu.
+
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+
+/// `spin::Mutex` that disables interrupts while locked & restores them on unlock
+pub struct IrqMutex<T: ?Sized> {
+    inner: Mutex<T>,
+}
+
+/// Guard of an `IrqMutex`, interrupts are restored after the lock is released
+pub struct IrqMutexGuard<'a, T: ?Sized + 'a> {
+    guard: Option<MutexGuard<'a, T>>,
+    /// interrupts were enabled before locking
+    enabled: bool,
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> IrqMutex<T> {
+    /// Disables interrupts & spins until the lock is free
+    pub fn lock(&self) -> IrqMutexGuard<T> {
+        let enabled = irq_save();
+        IrqMutexGuard {
+            guard: Some(self.inner.lock()),
+            enabled,
+        }
+    }
+
+    /// Like `lock()` but returns `None` instead of spinning, use it where waiting could deadlock
+    /// e.g. in the panic handler
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<T>> {
+        let enabled = irq_save();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqMutexGuard {
+                guard: Some(guard),
+                enabled,
+            }),
+            None => {
+                irq_restore(enabled);
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+}
+
+impl<T: ?Sized> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<T: ?Sized> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // unlock before an interrupt can arrive
+        drop(self.guard.take());
+        irq_restore(self.enabled);
+    }
+}
+
+/// disables interrupts & returns if they were enabled
+#[cfg(target_os = "none")]
+fn irq_save() -> bool {
+    use crate::arch::cpu;
+    let enabled = cpu::interrupts_enabled();
+    // SAFETY: the kernel runs in ring 0 / EL1
+    unsafe { cpu::disable_interrupts() };
+    enabled
+}
+
+#[cfg(target_os = "none")]
+fn irq_restore(enabled: bool) {
+    if enabled {
+        // SAFETY: the kernel runs in ring 0 / EL1
+        unsafe { crate::arch::cpu::enable_interrupts() };
+    }
+}
+
+/// host unit tests run in user mode & can not mask interrupts
+#[cfg(not(target_os = "none"))]
+fn irq_save() -> bool {
+    false
+}
+
+#[cfg(not(target_os = "none"))]
+fn irq_restore(_enabled: bool) {}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_and_try_lock() {
+        let mutex = IrqMutex::new(5);
+        {
+            let mut guard = mutex.lock();
+            *guard += 1;
+            assert!(mutex.is_locked());
+            assert!(mutex.try_lock().is_none());
+        }
+        assert!(!mutex.is_locked());
+        assert_eq!(*mutex.try_lock().unwrap(), 6);
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Architecture independent system call table. The `arch` entry code collects the syscall
+//! number and arguments from the registers and passes them to `dispatch()`.
+
+use crate::arch;
+use crate::limine;
+use crate::log;
+use alloc::vec::Vec;
+
+/// Arguments of a syscall, in the order of the architecture calling convention
+pub type SyscallArgs = [usize; 6];
+
+/// Signature of every entry in `SYSCALL_TABLE`
+type SyscallHandler = fn(&SyscallArgs) -> Result<usize, SyscallError>;
+
+/// First address that does not belong to the user half of the address space
+pub const USER_ADDRESS_LIMIT: usize = 0x0000_8000_0000_0000;
+
+/// User access is checked per page of this size, the smallest one of every architecture
+const PAGE_SIZE: usize = 4096;
+
+/// Max length of a message passed to `sys_log`
+const LOG_MAX_LENGTH: usize = 4096;
+
+/// Handlers indexed by the syscall number
+static SYSCALL_TABLE: &[SyscallHandler] = &[
+    sys_nop, // 0
+    sys_log, // 1
+];
+
+/// Error returned by a syscall handler, passed back to user space as a negative number
+///
+/// ## Variants:
+/// - `UnknownSyscall` : No handler is registered for the requested number
+/// - `InvalidArgument` : An argument is out of range, e.g. a pointer into kernel memory
+/// - `OutOfMemory` : The kernel heap can not hold a copy of the arguments
+#[derive(Debug, PartialEq, Eq)]
+pub enum SyscallError {
+    UnknownSyscall,
+    InvalidArgument,
+    OutOfMemory,
+}
+
+impl SyscallError {
+    /// value returned to user space
+    const fn code(&self) -> isize {
+        match self {
+            SyscallError::UnknownSyscall => -1,
+            SyscallError::InvalidArgument => -2,
+            SyscallError::OutOfMemory => -3,
+        }
+    }
+}
+
+/// Runs the handler of syscall `number`, returns its result or a negative error code
+pub fn dispatch(number: usize, args: &SyscallArgs) -> isize {
+    let result = match SYSCALL_TABLE.get(number) {
+        Some(handler) => handler(args),
+        None => Err(SyscallError::UnknownSyscall),
+    };
+    match result {
+        Ok(value) => value as isize,
+        Err(e) => e.code(),
+    }
+}
+
+/// Checks if `(start, start + length)` lies entirely in the user half
+fn validate_user_region(start: usize, length: usize) -> Result<(), SyscallError> {
+    match start.checked_add(length) {
+        Some(end) if end <= USER_ADDRESS_LIMIT => Ok(()),
+        _ => Err(SyscallError::InvalidArgument),
+    }
+}
+
+/// Copies `length` bytes of user memory at `start` to the heap. Every page must be mapped with
+/// user access, the lower half also holds supervisor mappings. The bytes are read through the
+/// HHDM, so a missing page is an error instead of a page fault.
+fn copy_from_user(start: usize, length: usize) -> Result<Vec<u8>, SyscallError> {
+    validate_user_region(start, length)?;
+    let mut bytes = Vec::new();
+    bytes
+        .try_reserve_exact(length)
+        .map_err(|_| SyscallError::OutOfMemory)?;
+    while bytes.len() < length {
+        let virt = start + bytes.len();
+        let count = (PAGE_SIZE - virt % PAGE_SIZE).min(length - bytes.len());
+        let phys = arch::cpu::translate_user(virt).ok_or(SyscallError::InvalidArgument)?;
+        // SAFETY: user pages are backed by RAM, which the HHDM maps
+        let page =
+            unsafe { core::slice::from_raw_parts((phys + limine::hhdm()) as *const u8, count) };
+        bytes.extend_from_slice(page);
+    }
+    Ok(bytes)
+}
+
+// ========== Handlers
+
+/// does nothing, useful to measure the syscall overhead
+fn sys_nop(_args: &SyscallArgs) -> Result<usize, SyscallError> {
+    Ok(0)
+}
+
+/// writes an utf8 string to the kernel log, args: (pointer, length)
+fn sys_log(args: &SyscallArgs) -> Result<usize, SyscallError> {
+    let (ptr, length) = (args[0], args[1]);
+    if length > LOG_MAX_LENGTH {
+        return Err(SyscallError::InvalidArgument);
+    }
+    // nothing to read, `ptr` may even be null
+    if length == 0 {
+        return Ok(0);
+    }
+    let bytes = copy_from_user(ptr, length)?;
+    let msg = core::str::from_utf8(&bytes).map_err(|_| SyscallError::InvalidArgument)?;
+    log!("{}", msg);
+    Ok(length)
+}
+
+// kernel tests, see `make test-x86_64`
+#[cfg(all(test, target_os = "none"))]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn log_rejects_kernel_memory() {
+        let message = "kernel";
+        // low memory is not mapped for user mode
+        let args = [0x1000, 16, 0, 0, 0, 0];
+        assert_eq!(sys_log(&args), Err(SyscallError::InvalidArgument));
+        let args = [message.as_ptr() as usize, message.len(), 0, 0, 0, 0];
+        assert_eq!(sys_log(&args), Err(SyscallError::InvalidArgument));
+        let args = [USER_ADDRESS_LIMIT - 8, 16, 0, 0, 0, 0];
+        assert_eq!(sys_log(&args), Err(SyscallError::InvalidArgument));
+        assert_eq!(sys_log(&[0; 6]), Ok(0));
+    }
+}
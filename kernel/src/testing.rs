@@ -0,0 +1,77 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Custom test framework for the kernel tests booted in QEMU with `make test-x86_64`.
+//!
+//! `kmain()` calls the generated `test_main()` once the kernel is initialised, which passes every
+//! `#[test_case]` function to `runner()`. Results are written to `arch::serial::SERIAL0` and QEMU
+//! is closed through the isa-debug-exit device with a pass/fail code.
+
+#[cfg(not(target_arch = "x86_64"))]
+compile_error!("Kernel tests are only supported on x86_64!");
+
+use crate::arch::{cpu, portio, serial};
+use core::fmt::{Arguments, Write};
+use core::panic::PanicInfo;
+
+/// I/O port of the isa-debug-exit device, must match the QEMU arguments in `scripts/test/`
+const DEBUG_EXIT_PORT: u16 = 0xF4;
+
+/// Value written to isa-debug-exit, QEMU then exits with `(value << 1) | 1`
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// Closes QEMU with `code`
+pub fn exit_qemu(code: QemuExitCode) -> ! {
+    unsafe { portio::output_long(DEBUG_EXIT_PORT, code as u32) };
+    // the device is missing if the test image was booted by hand
+    loop {
+        unsafe { cpu::halt() };
+    }
+}
+
+/// Implemented for every `#[test_case]` function
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        print(format_args!("{} ... ", core::any::type_name::<T>()));
+        self();
+        print(format_args!("ok\n"));
+    }
+}
+
+/// Runs all tests, a failing test panics & ends the run in `panic()`
+pub fn runner(tests: &[&dyn Testable]) {
+    print(format_args!("running {} tests\n", tests.len()));
+    for test in tests {
+        test.run();
+    }
+    print(format_args!("test result: ok. {} passed\n", tests.len()));
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// Called by the kernel panic handler in test builds
+pub fn panic(info: &PanicInfo) -> ! {
+    // the panicking test may hold `SERIAL0`
+    serial::emergency_writer()
+        .write_fmt(format_args!("FAILED\n\n{}\n", info))
+        .ok();
+    exit_qemu(QemuExitCode::Failed);
+}
+
+// ========== Serial output
+
+/// Writes to COM1 directly, test results must not depend on the kernel log
+fn print(msg: Arguments) {
+    serial::SERIAL0.lock().write_fmt(msg).ok();
+}
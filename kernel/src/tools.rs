@@ -117,9 +117,15 @@ where
     u64: ~const From<T>,
 {
     // (x >> lower) -> cuts out the stuff we dont care about to the right
-    // ( (1 << (higher + 1 - lower)) - 1) -> creates a binary number full of ones with length (higher+1-lower), that then get used as a mask
+    // bin_mask() -> creates a binary number full of ones with length (higher+1-lower), that then get used as a mask
     // for bitwise and ('&') to remove all the stuff we dont care about to the left
-    (target.into() >> lower) & ((1 << (higher + 1 - lower)) - 1)
+    (target.into() >> lower) & bin_mask(higher, lower)
+}
+
+/// binary number full of ones with length (higher+1-lower), works for the full 64 bits
+const fn bin_mask(higher: usize, lower: usize) -> u64 {
+    // (1 << 64) - 1 would overflow
+    u64::MAX >> (63 - (higher - lower))
 }
 
 /// inserts a binary sequence into another one
@@ -140,7 +146,8 @@ where
     u64: ~const From<T>,
 {
     // this mask enables all bits that have to be with a BitOr
-    let enable = (payload.into() << lower) as u64;
+    // the payload is cut to the length of the range, so it can not overwrite bits above `higher`
+    let enable = ((payload.into() & bin_mask(higher, lower)) << lower) as u64;
     // this mask disables all bits that must not be with a BitAnd
     // same as the enable mask, but full of ones on the left & the right to preserve the rest of
     // the data, except for our payload that can have some zeroes for disabling
@@ -154,3 +161,96 @@ where
     // apply the masks
     ((target.into() | enable) & disable).into()
 }
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_ranges() {
+        assert_eq!(bin_extract(0b11110011_u64, 4, 1), 0b1001);
+        assert_eq!(bin_extract(0b1000_u64, 3, 3), 1);
+        assert_eq!(bin_extract(0b0111_u64, 3, 3), 0);
+        assert_eq!(bin_extract(0xABCD_u16, 15, 8), 0xAB);
+        assert_eq!(bin_extract(0xABCD_u16, 7, 0), 0xCD);
+    }
+
+    #[test]
+    fn extract_bit_63() {
+        assert_eq!(bin_extract(u64::MAX, 63, 63), 1);
+        assert_eq!(bin_extract(u64::MAX >> 1, 63, 63), 0);
+        assert_eq!(bin_extract(0x8000_0000_0000_0001_u64, 63, 0), 0x8000_0000_0000_0001);
+        assert_eq!(bin_extract(0xF000_0000_0000_0000_u64, 63, 60), 0xF);
+    }
+
+    #[test]
+    fn insert_ranges() {
+        assert_eq!(bin_insert(0_u64, 0b00001111_u64, 6, 3), 0b0000000001111000);
+        assert_eq!(bin_insert(u64::MAX, 0_u64, 7, 4), !0xF0);
+        assert_eq!(bin_insert(0xFF00_u64, 0xA_u64, 11, 8), 0xFA00);
+        assert_eq!(bin_insert(0_u64, true, 5, 5), 0b100000);
+        assert_eq!(bin_insert(u64::MAX, false, 0, 0), u64::MAX - 1);
+    }
+
+    #[test]
+    fn insert_bit_63() {
+        assert_eq!(bin_insert(0_u64, true, 63, 63), 1 << 63);
+        assert_eq!(bin_insert(u64::MAX, false, 63, 63), u64::MAX >> 1);
+        assert_eq!(bin_insert(0x1234_u64, u64::MAX, 63, 0), u64::MAX);
+        assert_eq!(bin_insert(u64::MAX, 0_u64, 63, 0), 0);
+        assert_eq!(bin_insert(0_u64, 0b101_u64, 63, 61), 0b101 << 61);
+    }
+
+    #[test]
+    fn insert_truncates_payload() {
+        // payload is wider than the range, bits above `higher` must be kept
+        assert_eq!(bin_insert(0_u64, 0xFF_u64, 3, 0), 0xF);
+        assert_eq!(bin_insert(0xF0_u64, 0xFF_u64, 3, 0), 0xFF);
+        assert_eq!(bin_insert(0_u64, 0b11_u64, 62, 62), 1 << 62);
+    }
+
+    #[test]
+    fn insert_extract_roundtrip() {
+        for lower in 0..64 {
+            for higher in lower..64 {
+                let payload = 0x5555_5555_5555_5555_u64 & bin_mask(higher, lower);
+                let value = bin_insert(u64::MAX, payload, higher, lower);
+                assert_eq!(bin_extract(value, higher, lower), payload);
+                // bits outside the range are untouched
+                assert_eq!(value | (bin_mask(higher, lower) << lower), u64::MAX);
+            }
+        }
+    }
+
+    struct Token(u64);
+
+    impl Token {
+        bitfield!(set_low, u16, 15, 0);
+        bitfield!(set_top, get_top, u64, 63, 60);
+        bitfield!(set_flag, bool, 17);
+        bitfield!(set_last, get_last, u64, 63);
+    }
+
+    #[test]
+    fn bitfield_setters() {
+        let mut token = Token(0);
+        token.set_low(0xBEEF);
+        token.set_flag(true);
+        assert_eq!(token.0, 0x2_BEEF);
+        token.set_flag(false);
+        token.set_low(0);
+        assert_eq!(token.0, 0);
+    }
+
+    #[test]
+    fn bitfield_getters() {
+        let mut token = Token(0);
+        token.set_top(0xA);
+        assert_eq!(token.get_top(), 0xA);
+        assert_eq!(token.get_last(), 1);
+        token.set_last(0);
+        assert_eq!(token.get_top(), 0x2);
+        assert_eq!(token.0, 0x2000_0000_0000_0000);
+    }
+}
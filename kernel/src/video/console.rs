@@ -0,0 +1,695 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Text console on a framebuffer, it replaces the limine terminal as log sink.
+//! <br> main source: https://vt100.net/docs/vt100-ug/chapter3.html (escape sequences)
+//!
+//! The limine terminal lives in bootloader reclaimable memory & its `write` callback is not
+//! reentrant. `init()` takes framebuffer 0, registers `ConsoleSink` as "console", removes the
+//! "terminal" sink & on x86_64 the identity map it needed.
+//!
+//! Text is drawn with a PSF2 font (see `font.rs`) into a grid of cells. The cells of the last
+//! `config::CONSOLE_SCROLLBACK` lines are kept on the heap, `scroll_view()` shows older lines. New
+//! output keeps the view in place, typing in the debug monitor returns to the bottom.
+//!
+//! With a backdrop image (see `splash.rs`) the cells with the default background show the image
+//! instead, scrolling then redraws the whole screen. `kmain()` removes the boot splash once it is
+//! done, so only the boot log pays for it.
+//!
+//! Supported escape sequences: `ESC [ ... m` with the 16 ANSI colors, bold as bright colors &
+//! reset, `ESC [ row ; column H`, `ESC [ n J` & `ESC [ n K`. Others are dropped.
+
+use super::font::{self, Font};
+use super::framebuffer::{Color, FrameBuffer, FrameBufferError};
+use super::image::Image;
+use super::splash;
+use crate::config::{BOOT_SPLASH, CONSOLE_SCROLLBACK};
+use crate::log::{self, LevelFilter, Sink, SinkError};
+use crate::sync::IrqMutex;
+use crate::warn;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The console of `init()`, None before
+static CONSOLE: IrqMutex<Option<Console>> = IrqMutex::new(None);
+
+/// VGA text mode palette, indexed by the ANSI color number, 8 - 15 are the bright colors
+const PALETTE: [Color; 16] = [
+    Color::rgb(0x00, 0x00, 0x00),
+    Color::rgb(0xAA, 0x00, 0x00),
+    Color::rgb(0x00, 0xAA, 0x00),
+    Color::rgb(0xAA, 0x55, 0x00),
+    Color::rgb(0x00, 0x00, 0xAA),
+    Color::rgb(0xAA, 0x00, 0xAA),
+    Color::rgb(0x00, 0xAA, 0xAA),
+    Color::rgb(0xAA, 0xAA, 0xAA),
+    Color::rgb(0x55, 0x55, 0x55),
+    Color::rgb(0xFF, 0x55, 0x55),
+    Color::rgb(0x55, 0xFF, 0x55),
+    Color::rgb(0xFF, 0xFF, 0x55),
+    Color::rgb(0x55, 0x55, 0xFF),
+    Color::rgb(0xFF, 0x55, 0xFF),
+    Color::rgb(0x55, 0xFF, 0xFF),
+    Color::rgb(0xFF, 0xFF, 0xFF),
+];
+
+/// light gray
+const DEFAULT_FOREGROUND: u8 = 7;
+/// black
+const DEFAULT_BACKGROUND: u8 = 0;
+
+/// Columns between tab stops
+const TAB_WIDTH: usize = 8;
+
+/// Max ammount of parameters of an escape sequence, further ones are ignored
+const PARAMS_MAX: usize = 4;
+
+/// Error returned by `init()` & `Console::new()`
+///
+/// ## Variants:
+/// - `FrameBuffer` : Framebuffer 0 can not be used, contains the cause
+/// - `TooSmall` : Not a single glyph fits on the framebuffer
+/// - `OutOfMemory` : The heap can not hold the scrollback
+/// - `Sink` : The log sink could not be registered, contains the cause
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConsoleError {
+    FrameBuffer(FrameBufferError),
+    TooSmall,
+    OutOfMemory,
+    Sink(SinkError),
+}
+
+/// Character & `PALETTE` colors of a grid position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    /// Latin-1 character, the glyph index of the font
+    byte: u8,
+    foreground: u8,
+    background: u8,
+}
+
+impl Cell {
+    const BLANK: Self = Self {
+        byte: b' ',
+        foreground: DEFAULT_FOREGROUND,
+        background: DEFAULT_BACKGROUND,
+    };
+}
+
+/// Output of `Parser::push()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// the character is part of an escape sequence
+    None,
+    Print(char),
+    /// a control sequence with its final byte, its parameters & their count, missing parameters
+    /// are 0
+    Csi(u8, [u16; PARAMS_MAX], usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// Splits the output into characters & escape sequences
+struct Parser {
+    state: State,
+    params: [u16; PARAMS_MAX],
+    /// index of the parameter being read
+    param: usize,
+}
+
+impl Parser {
+    const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            params: [0; PARAMS_MAX],
+            param: 0,
+        }
+    }
+
+    fn push(&mut self, c: char) -> Action {
+        match (self.state, c) {
+            (State::Ground, '\x1B') => {
+                self.state = State::Escape;
+                Action::None
+            }
+            (State::Ground, c) => Action::Print(c),
+            (State::Escape, '[') => {
+                self.state = State::Csi;
+                self.params = [0; PARAMS_MAX];
+                self.param = 0;
+                Action::None
+            }
+            // other escape sequences are not supported, the byte behind ESC is dropped
+            (State::Escape, _) => {
+                self.state = State::Ground;
+                Action::None
+            }
+            (State::Csi, '0'..='9') => {
+                if let Some(param) = self.params.get_mut(self.param) {
+                    let digit = c as u16 - '0' as u16;
+                    *param = param.saturating_mul(10).saturating_add(digit);
+                }
+                Action::None
+            }
+            (State::Csi, ';') => {
+                self.param += 1;
+                Action::None
+            }
+            (State::Csi, '\x40'..='\x7E') => {
+                self.state = State::Ground;
+                let count = (self.param + 1).min(PARAMS_MAX);
+                Action::Csi(c as u8, self.params, count)
+            }
+            // private & intermediate bytes are skipped, anything else aborts the sequence
+            (State::Csi, '\x20'..='\x3F') => Action::None,
+            (State::Csi, _) => {
+                self.state = State::Ground;
+                Action::None
+            }
+        }
+    }
+}
+
+/// Text grid with a scrollback on a `FrameBuffer`
+pub struct Console {
+    fb: FrameBuffer,
+    font: &'static Font,
+    columns: usize,
+    rows: usize,
+    /// ring of `capacity` lines of `columns` cells, line n is at `n % capacity`
+    cells: Vec<Cell>,
+    capacity: usize,
+    /// line shown in the top row of the screen
+    top: usize,
+    /// lines the view is scrolled back
+    view: usize,
+    column: usize,
+    row: usize,
+    foreground: u8,
+    background: u8,
+    bold: bool,
+    parser: Parser,
+    /// image behind the cells & the position of its top left corner
+    backdrop: Option<(Image, isize, isize)>,
+}
+
+impl Console {
+    /// Clears `fb` & fills it with as many cells of `font` as fit, `scrollback` lines are kept
+    /// including the ones on the screen
+    pub fn new(
+        mut fb: FrameBuffer,
+        font: &'static Font,
+        scrollback: usize,
+    ) -> Result<Self, ConsoleError> {
+        let columns = fb.width() / font.width();
+        let rows = fb.height() / font.height();
+        if columns == 0 || rows == 0 {
+            return Err(ConsoleError::TooSmall);
+        }
+        let capacity = scrollback.max(rows);
+        let mut cells = Vec::new();
+        cells
+            .try_reserve_exact(capacity * columns)
+            .map_err(|_| ConsoleError::OutOfMemory)?;
+        cells.resize(capacity * columns, Cell::BLANK);
+        fb.clear(PALETTE[DEFAULT_BACKGROUND as usize]);
+        let mut console = Self {
+            fb,
+            font,
+            columns,
+            rows,
+            cells,
+            capacity,
+            top: 0,
+            view: 0,
+            column: 0,
+            row: 0,
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            bold: false,
+            parser: Parser::new(),
+            backdrop: None,
+        };
+        console.draw_cursor(true);
+        Ok(console)
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// (column, row) of the next character on the screen
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.column.min(self.columns - 1), self.row)
+    }
+
+    /// Writes text & handles the escape sequences in it
+    pub fn write(&mut self, s: &str) {
+        self.draw_cursor(false);
+        for c in s.chars() {
+            match self.parser.push(c) {
+                Action::None => {}
+                Action::Print(c) => self.print(c),
+                Action::Csi(byte, params, count) => self.control(byte, params, count),
+            }
+        }
+        self.draw_cursor(true);
+    }
+
+    /// Shows the screen `lines` lines back in the scrollback, 0 is the bottom. Returns the
+    /// ammount of lines actually scrolled back, it is limited by the lines stored.
+    pub fn scroll_view(&mut self, lines: usize) -> usize {
+        let lines = lines.min(self.max_view());
+        if lines != self.view {
+            self.view = lines;
+            self.redraw();
+            self.draw_cursor(true);
+        }
+        self.view
+    }
+
+    /// Centers `image` on the screen behind the text, None removes the backdrop
+    pub fn set_backdrop(&mut self, image: Option<Image>) {
+        let background = PALETTE[DEFAULT_BACKGROUND as usize];
+        self.fb.clear(background);
+        self.backdrop = image.map(|image| {
+            let x = (self.fb.width() as isize - image.width() as isize) / 2;
+            let y = (self.fb.height() as isize - image.height() as isize) / 2;
+            image.draw(&mut self.fb, x, y);
+            (image, x, y)
+        });
+        self.redraw();
+        self.draw_cursor(true);
+    }
+
+    fn print(&mut self, c: char) {
+        match c {
+            '\n' => {
+                self.column = 0;
+                self.line_feed();
+            }
+            '\r' => self.column = 0,
+            '\x08' => self.column = self.column.saturating_sub(1),
+            '\t' => {
+                let stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.column = stop.min(self.columns - 1);
+            }
+            c if c.is_control() => {}
+            c => {
+                // wrap once the next character is printed, so a full line needs no extra line
+                if self.column >= self.columns {
+                    self.column = 0;
+                    self.line_feed();
+                }
+                let byte = u8::try_from(c as u32).unwrap_or(b'?');
+                let foreground = match self.bold && self.foreground < 8 {
+                    true => self.foreground + 8,
+                    false => self.foreground,
+                };
+                let cell = Cell {
+                    byte,
+                    foreground,
+                    background: self.background,
+                };
+                self.set_cell(self.column, self.row, cell);
+                self.column += 1;
+            }
+        }
+    }
+
+    fn control(&mut self, byte: u8, params: [u16; PARAMS_MAX], count: usize) {
+        match byte {
+            b'm' => {
+                for &param in &params[..count] {
+                    self.select_graphic_rendition(param);
+                }
+            }
+            b'H' | b'f' => {
+                self.row = (params[0].max(1) as usize - 1).min(self.rows - 1);
+                self.column = (params[1].max(1) as usize - 1).min(self.columns - 1);
+            }
+            b'J' => {
+                let (column, row) = self.cursor();
+                let (from, to) = match params[0] {
+                    0 => ((column, row), (self.columns, self.rows - 1)),
+                    1 => ((0, 0), (column + 1, row)),
+                    _ => ((0, 0), (self.columns, self.rows - 1)),
+                };
+                for r in from.1..=to.1 {
+                    let start = if r == from.1 { from.0 } else { 0 };
+                    let end = if r == to.1 { to.0 } else { self.columns };
+                    self.erase(r, start, end);
+                }
+            }
+            b'K' => {
+                let (column, row) = self.cursor();
+                match params[0] {
+                    0 => self.erase(row, column, self.columns),
+                    1 => self.erase(row, 0, column + 1),
+                    _ => self.erase(row, 0, self.columns),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies an SGR parameter, 0 & a missing parameter reset all attributes
+    fn select_graphic_rendition(&mut self, param: u16) {
+        match param {
+            0 => {
+                self.foreground = DEFAULT_FOREGROUND;
+                self.background = DEFAULT_BACKGROUND;
+                self.bold = false;
+            }
+            1 => self.bold = true,
+            22 => self.bold = false,
+            30..=37 => self.foreground = (param - 30) as u8,
+            39 => self.foreground = DEFAULT_FOREGROUND,
+            40..=47 => self.background = (param - 40) as u8,
+            49 => self.background = DEFAULT_BACKGROUND,
+            90..=97 => self.foreground = (param - 90) as u8 + 8,
+            100..=107 => self.background = (param - 100) as u8 + 8,
+            _ => {}
+        }
+    }
+
+    /// Moves the cursor to the next row, the screen scrolls at the bottom
+    fn line_feed(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+        self.top += 1;
+        let line = self.line(self.top + self.rows - 1);
+        self.cells[line].fill(Cell::BLANK);
+        if self.view == 0 && self.backdrop.is_none() {
+            let background = PALETTE[DEFAULT_BACKGROUND as usize];
+            self.fb.scroll_up(self.font.height(), background);
+        } else if self.view == 0 {
+            // the backdrop stays in place
+            self.redraw();
+        } else if self.view < self.max_view() {
+            // keep showing the same lines
+            self.view += 1;
+        } else {
+            // the oldest shown line was overwritten
+            self.redraw();
+        }
+    }
+
+    /// Sets the cells `start..end` of `row` to blanks with the current background
+    fn erase(&mut self, row: usize, start: usize, end: usize) {
+        let blank = Cell {
+            background: self.background,
+            ..Cell::BLANK
+        };
+        for column in start..end.min(self.columns) {
+            self.set_cell(column, row, blank);
+        }
+    }
+
+    /// most lines the view can be scrolled back
+    fn max_view(&self) -> usize {
+        self.top.min(self.capacity - self.rows)
+    }
+
+    /// range of line `n` in `cells`
+    fn line(&self, n: usize) -> core::ops::Range<usize> {
+        let start = n % self.capacity * self.columns;
+        start..start + self.columns
+    }
+
+    /// the cell at (column, row) of the screen, without the view
+    fn cell(&self, column: usize, row: usize) -> Cell {
+        self.cells[self.line(self.top + row).start + column]
+    }
+
+    fn set_cell(&mut self, column: usize, row: usize, cell: Cell) {
+        let index = self.line(self.top + row).start + column;
+        self.cells[index] = cell;
+        if self.view == 0 {
+            self.draw_cell(column, row, cell, false);
+        }
+    }
+
+    /// Shows or hides the cursor, it is only drawn at the bottom of the scrollback
+    fn draw_cursor(&mut self, visible: bool) {
+        if self.view == 0 {
+            let (column, row) = self.cursor();
+            self.draw_cell(column, row, self.cell(column, row), visible);
+        }
+    }
+
+    /// Draws every row of the view, without the cursor
+    fn redraw(&mut self) {
+        let first = self.top - self.view;
+        for row in 0..self.rows {
+            let line = self.line(first + row);
+            for column in 0..self.columns {
+                let cell = self.cells[line.start + column];
+                self.draw_cell(column, row, cell, false);
+            }
+        }
+    }
+
+    /// the color of the backdrop at a framebuffer position, None outside of it
+    fn backdrop_pixel(&self, x: usize, y: usize) -> Option<Color> {
+        let (image, image_x, image_y) = self.backdrop.as_ref()?;
+        let x = usize::try_from(x as isize - image_x).ok()?;
+        let y = usize::try_from(y as isize - image_y).ok()?;
+        image.pixel(x, y)
+    }
+
+    /// Draws the glyph of `cell` at a screen position, `inverted` swaps its colors. The default
+    /// background shows the backdrop.
+    fn draw_cell(&mut self, column: usize, row: usize, cell: Cell, inverted: bool) {
+        let (mut foreground, mut background) = (
+            PALETTE[cell.foreground as usize],
+            PALETTE[cell.background as usize],
+        );
+        if inverted {
+            core::mem::swap(&mut foreground, &mut background);
+        }
+        let transparent = !inverted && cell.background == DEFAULT_BACKGROUND;
+        let glyph = self.font.glyph(cell.byte as char);
+        let (x, y) = (column * self.font.width(), row * self.font.height());
+        for glyph_y in 0..self.font.height() {
+            for glyph_x in 0..self.font.width() {
+                let (pixel_x, pixel_y) = (x + glyph_x, y + glyph_y);
+                let color = match self.font.is_set(glyph, glyph_x, glyph_y) {
+                    true => foreground,
+                    false if transparent => {
+                        self.backdrop_pixel(pixel_x, pixel_y).unwrap_or(background)
+                    }
+                    false => background,
+                };
+                self.fb.set_pixel(pixel_x, pixel_y, color);
+            }
+        }
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s);
+        Ok(())
+    }
+}
+
+/// Takes framebuffer 0 for the console & replaces the limine terminal sink with `ConsoleSink`,
+/// with `BOOT_SPLASH` set the splash is the backdrop
+pub fn init() -> Result<(), ConsoleError> {
+    let fb = FrameBuffer::take(0).map_err(ConsoleError::FrameBuffer)?;
+    let (width, height) = (fb.width(), fb.height());
+    let mut console = Console::new(fb, &font::DEFAULT, CONSOLE_SCROLLBACK)?;
+    if BOOT_SPLASH {
+        match splash::load(width, height) {
+            Ok(image) => console.set_backdrop(Some(image)),
+            Err(e) => warn!("Boot splash unavailable: {:?}", e),
+        }
+    }
+    *CONSOLE.lock() = Some(console);
+    if let Err(e) = log::add_sink("console", &ConsoleSink, LevelFilter::Trace) {
+        // releases the framebuffer
+        *CONSOLE.lock() = None;
+        return Err(ConsoleError::Sink(e));
+    }
+    log::remove_sink("terminal");
+    // only the limine terminal used it
+    #[cfg(target_arch = "x86_64")]
+    crate::memman::paging::remove_identity_map();
+    Ok(())
+}
+
+/// true once `init()` succeeded
+pub fn is_active() -> bool {
+    CONSOLE.lock().is_some()
+}
+
+/// `Console::scroll_view()` of the console, 0 if it is not active
+pub fn scroll_view(lines: usize) -> usize {
+    CONSOLE
+        .lock()
+        .as_mut()
+        .map_or(0, |console| console.scroll_view(lines))
+}
+
+/// Removes the backdrop of the console of `init()`, its text scrolls cheaply again
+pub fn remove_backdrop() {
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        if console.backdrop.is_some() {
+            console.set_backdrop(None);
+        }
+    }
+}
+
+/// Writes to the console of `init()`
+pub struct ConsoleSink;
+
+impl Sink for ConsoleSink {
+    fn write(&self, s: &str) {
+        if let Some(console) = CONSOLE.lock().as_mut() {
+            console.write(s);
+        }
+    }
+
+    fn write_emergency(&self, s: &str) {
+        if let Some(mut console) = CONSOLE.try_lock() {
+            if let Some(console) = console.as_mut() {
+                console.write(s);
+            }
+        }
+    }
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+    use crate::limine::{VideoMode, MEMORY_MODEL_RGB};
+    use std::vec;
+
+    /// 4 columns & 3 rows of the default 8x13 font, with a spare pixel row
+    const MODE: VideoMode = VideoMode {
+        width: 32,
+        height: 40,
+        pitch: 32 * 4,
+        bpp: 32,
+        memory_model: MEMORY_MODEL_RGB,
+        red_mask: (8, 16),
+        green_mask: (8, 8),
+        blue_mask: (8, 0),
+    };
+
+    /// console on heap memory, the memory is leaked
+    fn console(scrollback: usize) -> Console {
+        let memory = vec![0xAA_u8; MODE.pitch * MODE.height].leak();
+        let fb = unsafe { FrameBuffer::from_raw(memory.as_mut_ptr(), &MODE).unwrap() };
+        Console::new(fb, &font::DEFAULT, scrollback).unwrap()
+    }
+
+    /// characters of a screen row, or a line of the view
+    fn text(console: &Console, row: usize) -> std::string::String {
+        let line = console.line(console.top - console.view + row);
+        console.cells[line].iter().map(|c| c.byte as char).collect()
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let mut parser = Parser::new();
+        let actions: std::vec::Vec<Action> = "a\x1B[1;31mb\x1B[H\x1B]c\x1B[?25l"
+            .chars()
+            .map(|c| parser.push(c))
+            .filter(|action| *action != Action::None)
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                Action::Print('a'),
+                Action::Csi(b'm', [1, 31, 0, 0], 2),
+                Action::Print('b'),
+                Action::Csi(b'H', [0; PARAMS_MAX], 1),
+                // the unsupported `ESC ]` only drops the `]`
+                Action::Print('c'),
+                Action::Csi(b'l', [25, 0, 0, 0], 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_and_colors() {
+        let mut console = console(10);
+        assert_eq!((console.columns(), console.rows()), (4, 3));
+        console.write("ab\tc\x1B[1;34;47mdef\x1B[0m");
+        assert_eq!(text(&console, 0), "ab c");
+        assert_eq!(text(&console, 1), "def ");
+        assert_eq!(console.cursor(), (3, 1));
+        let cell = console.cell(0, 1);
+        assert_eq!((cell.foreground, cell.background), (12, 7));
+
+        // the glyph of 'd' is drawn in its colors, the cursor is inverted
+        let fb = &console.fb;
+        let pixels: std::vec::Vec<Color> = (0..8).map(|x| fb.pixel(x, 13 + 6).unwrap()).collect();
+        assert!(pixels.contains(&PALETTE[12]) && pixels.contains(&PALETTE[7]));
+        assert_eq!(fb.pixel(24, 13), Some(PALETTE[DEFAULT_FOREGROUND as usize]));
+
+        console.write("\r\x1B[K\x1B[1;2H\x1B[2K");
+        assert_eq!(text(&console, 0), "    ");
+        assert_eq!(text(&console, 1), "    ");
+        assert_eq!(console.cursor(), (1, 0));
+    }
+
+    #[test]
+    fn scrollback() {
+        let mut console = console(5);
+        console.write("1\n2\n3\n4\n5\n6\n");
+        assert_eq!(text(&console, 0), "5   ");
+        assert_eq!(text(&console, 1), "6   ");
+        assert_eq!(console.cursor(), (0, 2));
+        // the glyph of '5' moved up to the first row of the framebuffer
+        let foreground = Some(PALETTE[DEFAULT_FOREGROUND as usize]);
+        assert!((0..13).any(|y| (0..8).any(|x| console.fb.pixel(x, y) == foreground)));
+
+        // lines 1 & 2 were overwritten
+        assert_eq!(console.scroll_view(10), 2);
+        assert_eq!(text(&console, 0), "3   ");
+        // the oldest line is overwritten by new output, the view moves on to the next one
+        console.write("7\n");
+        assert_eq!(console.view, 2);
+        assert_eq!(text(&console, 0), "4   ");
+        assert_eq!(console.scroll_view(0), 0);
+        assert_eq!(text(&console, 1), "7   ");
+    }
+
+    #[test]
+    fn backdrop() {
+        let mut console = console(10);
+        let mut image = Image::new(2, 2).unwrap();
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            image.set_pixel(x, y, Color::WHITE);
+        }
+        // centered at (15, 19), the pixels in cell (1, 1) & (2, 1)
+        console.set_backdrop(Some(image));
+        console.write("\n \x1B[41m \x1B[0m\n");
+        assert_eq!(console.fb.pixel(15, 19), Some(PALETTE[1]));
+        assert_eq!(console.fb.pixel(16, 19), Some(Color::WHITE));
+        assert_eq!(console.fb.pixel(17, 19), Some(PALETTE[0]));
+
+        // the text scrolls over the backdrop
+        console.write("\n");
+        assert_eq!(console.fb.pixel(15, 19), Some(Color::WHITE));
+        assert_eq!(console.fb.pixel(15, 6), Some(PALETTE[1]));
+    }
+}
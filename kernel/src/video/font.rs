@@ -0,0 +1,162 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Bitmap fonts in the PC Screen Font 2 format, as used by the linux console.
+//! <br> main source: https://www.win.tue.nl/~aeb/linux/kbd/font-formats-1.html
+//!
+//! `DEFAULT` is the 8x13 X11 "misc-fixed" font (public domain) with its Latin-1 glyphs at their
+//! codepoints, embedded from `font.psf`.
+
+/// PSF2 magic number, little endian
+const PSF2_MAGIC: u32 = 0x864A_B572;
+
+/// Size of the PSF2 header, glyphs may start behind a bigger one
+const PSF2_HEADER_SIZE: usize = 32;
+
+/// Replaces characters without a glyph
+const REPLACEMENT: char = '?';
+
+/// Font of the framebuffer console
+pub static DEFAULT: Font = match Font::parse(include_bytes!("font.psf")) {
+    Ok(font) => font,
+    Err(_) => panic!("font.psf is no valid PSF2 font"),
+};
+
+/// Error returned when a font can not be parsed
+///
+/// ## Variants:
+/// - `Magic` : The data does not start with the PSF2 magic number
+/// - `Truncated` : The header or the glyphs are cut off
+/// - `GlyphSize` : The glyph size does not match width & height
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    Magic,
+    Truncated,
+    GlyphSize,
+}
+
+/// Glyphs of a PSF2 font, every glyph is `height` rows of `(width + 7) / 8` bytes with the
+/// leftmost pixel in the highest bit
+#[derive(Debug, Clone, Copy)]
+pub struct Font {
+    data: &'static [u8],
+    /// offset of the first glyph in `data`
+    header_size: usize,
+    count: usize,
+    glyph_size: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Font {
+    /// Reads the header of a PSF2 font, the unicode table is ignored as glyphs are indexed by
+    /// codepoint
+    pub const fn parse(data: &'static [u8]) -> Result<Self, FontError> {
+        if data.len() < PSF2_HEADER_SIZE {
+            return Err(FontError::Truncated);
+        }
+        if read_u32(data, 0) != PSF2_MAGIC {
+            return Err(FontError::Magic);
+        }
+        let header_size = read_u32(data, 2) as usize;
+        let count = read_u32(data, 4) as usize;
+        let glyph_size = read_u32(data, 5) as usize;
+        let height = read_u32(data, 6) as usize;
+        let width = read_u32(data, 7) as usize;
+        if width == 0 || height == 0 || glyph_size != (width + 7) / 8 * height {
+            return Err(FontError::GlyphSize);
+        }
+        if header_size < PSF2_HEADER_SIZE
+            || count == 0
+            || data.len() < header_size + count * glyph_size
+        {
+            return Err(FontError::Truncated);
+        }
+        Ok(Self {
+            data,
+            header_size,
+            count,
+            glyph_size,
+            width,
+            height,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Bitmap of `c`, `REPLACEMENT` if the font has no glyph for it
+    pub fn glyph(&self, c: char) -> &'static [u8] {
+        let index = match c as usize {
+            i if i < self.count => i,
+            _ => (REPLACEMENT as usize).min(self.count - 1),
+        };
+        let start = self.header_size + index * self.glyph_size;
+        &self.data[start..start + self.glyph_size]
+    }
+
+    /// true if pixel (x, y) of a glyph from `glyph()` is set
+    pub fn is_set(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        let row = (self.width + 7) / 8;
+        glyph[y * row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+/// `index`-th little endian u32 of `data`
+const fn read_u32(data: &[u8], index: usize) -> u32 {
+    let i = index * 4;
+    u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_font() {
+        assert_eq!((DEFAULT.width(), DEFAULT.height()), (8, 13));
+        // 'I' is a vertical bar in the middle of the cell
+        let glyph = DEFAULT.glyph('I');
+        assert!((0..8).any(|x| DEFAULT.is_set(glyph, x, 6)));
+        assert!(!DEFAULT.is_set(glyph, 0, 6));
+        assert!(DEFAULT.glyph(' ').iter().all(|&row| row == 0));
+        assert_eq!(DEFAULT.glyph('\u{263A}'), DEFAULT.glyph(REPLACEMENT));
+    }
+
+    /// PSF2 font without glyph data, its header declares `count` glyphs of `glyph_size` bytes
+    fn header(width: u32, height: u32, glyph_size: u32, count: u32) -> std::vec::Vec<u8> {
+        [PSF2_MAGIC, 0, 32, 0, count, glyph_size, height, width]
+            .iter()
+            .flat_map(|field| field.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn invalid_fonts() {
+        let font = std::vec::Vec::leak(header(8, 2, 2, 1));
+        assert_eq!(Font::parse(&font[..16]).unwrap_err(), FontError::Truncated);
+        // the only glyph is missing
+        assert_eq!(Font::parse(font).unwrap_err(), FontError::Truncated);
+        assert_eq!(Font::parse(&[0; 40]).unwrap_err(), FontError::Magic);
+        // 9 pixel wide rows need 2 bytes
+        let wide = std::vec::Vec::leak(header(9, 2, 2, 1));
+        assert_eq!(Font::parse(wide).unwrap_err(), FontError::GlyphSize);
+
+        let mut valid = header(8, 2, 2, 1);
+        valid.extend([0x80, 0x01]);
+        let font = Font::parse(std::vec::Vec::leak(valid)).unwrap();
+        // every character falls back to the only glyph
+        let glyph = font.glyph('A');
+        assert!(font.is_set(glyph, 0, 0) && font.is_set(glyph, 7, 1));
+        assert!(!font.is_set(glyph, 7, 0));
+    }
+}
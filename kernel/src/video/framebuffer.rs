@@ -0,0 +1,446 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Drawing on the framebuffers set up by limine.
+//! <br> main source: https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md#framebuffer-feature
+//!
+//! `FrameBuffer::take()` hands out every framebuffer of `limine::framebuffers()` once, until the
+//! `FrameBuffer` is dropped. Colors are converted to the pixel format of the video mode, with the
+//! bytes per pixel, pitch & channel masks limine reports. Drawing outside the framebuffer is
+//! clipped.
+
+use crate::limine::{self, LimineError, VideoMode, MEMORY_MODEL_RGB};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Bit `i` is set while framebuffer `i` of `limine::framebuffers()` is taken
+static TAKEN: AtomicU64 = AtomicU64::new(0);
+
+/// Error returned when a `FrameBuffer` can not be created
+///
+/// ## Variants:
+/// - `Limine` : The bootloader provides no framebuffers, contains the cause
+/// - `NotFound` : There is no framebuffer with this index
+/// - `Taken` : The framebuffer is already used by another `FrameBuffer`
+/// - `UnsupportedFormat` : The video mode is not RGB with 1 - 4 bytes per pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameBufferError {
+    Limine(LimineError),
+    NotFound,
+    Taken,
+    UnsupportedFormat,
+}
+
+/// 24 bit color, converted to the pixel format when drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub const BLACK: Self = Self::rgb(0, 0, 0);
+    pub const WHITE: Self = Self::rgb(0xFF, 0xFF, 0xFF);
+
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+}
+
+/// (size, shift) in bits of a color channel in a pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Channel {
+    size: u8,
+    shift: u8,
+}
+
+impl Channel {
+    fn encode(&self, value: u8) -> u32 {
+        let value = value as u32;
+        let scaled = match self.size {
+            0..=8 => value >> (8 - self.size),
+            _ => value << (self.size - 8),
+        };
+        scaled << self.shift
+    }
+
+    fn decode(&self, pixel: u32) -> u8 {
+        let value = (pixel >> self.shift) & ((1 << self.size) - 1);
+        match self.size {
+            0..=8 => (value << (8 - self.size)) as u8,
+            _ => (value >> (self.size - 8)) as u8,
+        }
+    }
+}
+
+/// Layout of a pixel in the framebuffer memory, little endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelFormat {
+    bytes: usize,
+    red: Channel,
+    green: Channel,
+    blue: Channel,
+}
+
+impl PixelFormat {
+    /// Checks that the pixels of `mode` can be drawn
+    pub fn new(mode: &VideoMode) -> Result<Self, FrameBufferError> {
+        if mode.memory_model != MEMORY_MODEL_RGB {
+            return Err(FrameBufferError::UnsupportedFormat);
+        }
+        // e.g. 15 bit pixels use 2 bytes
+        let bytes = (mode.bpp as usize + 7) / 8;
+        if !(1..=4).contains(&bytes) {
+            return Err(FrameBufferError::UnsupportedFormat);
+        }
+        let channel = |(size, shift): (u8, u8)| match size {
+            1..=16 if size as u16 + shift as u16 <= mode.bpp => Ok(Channel { size, shift }),
+            _ => Err(FrameBufferError::UnsupportedFormat),
+        };
+        Ok(Self {
+            bytes,
+            red: channel(mode.red_mask)?,
+            green: channel(mode.green_mask)?,
+            blue: channel(mode.blue_mask)?,
+        })
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bytes
+    }
+
+    /// the pixel value of `color`, the lowest `bytes_per_pixel()` bytes are stored
+    pub fn encode(&self, color: Color) -> u32 {
+        self.red.encode(color.red) | self.green.encode(color.green) | self.blue.encode(color.blue)
+    }
+
+    /// the color of a pixel value, channels with less than 8 bits lose their low bits
+    pub fn decode(&self, pixel: u32) -> Color {
+        Color::rgb(
+            self.red.decode(pixel),
+            self.green.decode(pixel),
+            self.blue.decode(pixel),
+        )
+    }
+}
+
+/// Exclusive access to the pixels of a framebuffer
+pub struct FrameBuffer {
+    base: *mut u8,
+    width: usize,
+    height: usize,
+    pitch: usize,
+    format: PixelFormat,
+    /// bit in `TAKEN`, cleared on drop
+    index: Option<usize>,
+}
+
+// SAFETY: the framebuffer memory is only reached through this object
+unsafe impl Send for FrameBuffer {}
+
+impl FrameBuffer {
+    /// Takes framebuffer `index` of `limine::framebuffers()` for drawing, until the `FrameBuffer`
+    /// is dropped
+    pub fn take(index: usize) -> Result<Self, FrameBufferError> {
+        let item = limine::framebuffers()
+            .map_err(FrameBufferError::Limine)?
+            .nth(index)
+            .ok_or(FrameBufferError::NotFound)?;
+        if index >= 64 {
+            return Err(FrameBufferError::NotFound);
+        }
+        // SAFETY: limine maps the framebuffer memory, `TAKEN` keeps it exclusive
+        let mut fb = unsafe { Self::from_raw(item.address as *mut u8, &item.mode)? };
+        if TAKEN.fetch_or(1 << index, Ordering::AcqRel) & (1 << index) != 0 {
+            return Err(FrameBufferError::Taken);
+        }
+        fb.index = Some(index);
+        Ok(fb)
+    }
+
+    /// Draws into the `mode.pitch * mode.height` bytes at `base`
+    ///
+    /// ## SAFETY: the memory must be writable & must not be used by anything else while the
+    /// `FrameBuffer` exists
+    pub unsafe fn from_raw(base: *mut u8, mode: &VideoMode) -> Result<Self, FrameBufferError> {
+        let format = PixelFormat::new(mode)?;
+        if mode.pitch < mode.width * format.bytes {
+            return Err(FrameBufferError::UnsupportedFormat);
+        }
+        Ok(Self {
+            base,
+            width: mode.width,
+            height: mode.height,
+            pitch: mode.pitch,
+            format,
+            index: None,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn format(&self) -> &PixelFormat {
+        &self.format
+    }
+
+    /// Sets a pixel, nothing is drawn outside of the framebuffer
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            self.write(self.offset(x, y), self.format.encode(color));
+        }
+    }
+
+    /// The color of a pixel, None outside of the framebuffer
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Color> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.format.decode(self.read(self.offset(x, y))))
+    }
+
+    /// Fills a rectangle, clipped to the framebuffer
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        let pixel = self.format.encode(color);
+        let x_end = x.saturating_add(width).min(self.width);
+        let y_end = y.saturating_add(height).min(self.height);
+        for row in y..y_end {
+            for column in x..x_end {
+                self.write(self.offset(column, row), pixel);
+            }
+        }
+    }
+
+    /// Fills the whole framebuffer
+    pub fn clear(&mut self, color: Color) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+
+    /// Draws an image of `width` pixels per row at (x, y), clipped to the framebuffer
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, pixels: &[Color]) {
+        if width == 0 {
+            return;
+        }
+        for (i, row) in pixels.chunks(width).enumerate() {
+            let row_y = y.saturating_add(i);
+            if row_y >= self.height {
+                break;
+            }
+            for (j, color) in row.iter().enumerate() {
+                let column = x.saturating_add(j);
+                if column >= self.width {
+                    break;
+                }
+                self.write(self.offset(column, row_y), self.format.encode(*color));
+            }
+        }
+    }
+
+    /// Moves the content up by `lines` pixel rows & fills the rows freed at the bottom
+    pub fn scroll_up(&mut self, lines: usize, color: Color) {
+        let lines = lines.min(self.height);
+        let row_bytes = self.width * self.format.bytes;
+        for row in 0..self.height - lines {
+            let to = self.offset(0, row);
+            let from = self.offset(0, row + lines);
+            // SAFETY: both rows are inside the framebuffer, the source row is below the target
+            unsafe {
+                let (to, from) = (self.base.add(to), self.base.add(from));
+                if row_bytes % 4 == 0 && to as usize % 4 == 0 && from as usize % 4 == 0 {
+                    for i in (0..row_bytes).step_by(4) {
+                        let pixels = core::ptr::read_volatile(from.add(i) as *const u32);
+                        core::ptr::write_volatile(to.add(i) as *mut u32, pixels);
+                    }
+                } else {
+                    for i in 0..row_bytes {
+                        core::ptr::write_volatile(to.add(i), core::ptr::read_volatile(from.add(i)));
+                    }
+                }
+            }
+        }
+        self.fill_rect(0, self.height - lines, self.width, lines, color);
+    }
+
+    /// byte offset of a pixel, which must be inside the framebuffer
+    fn offset(&self, x: usize, y: usize) -> usize {
+        y * self.pitch + x * self.format.bytes
+    }
+
+    /// Stores the lowest `bytes_per_pixel()` bytes of `pixel`, volatile as the framebuffer is
+    /// device memory
+    fn write(&mut self, offset: usize, pixel: u32) {
+        // SAFETY: callers only pass offsets of pixels inside the framebuffer
+        unsafe {
+            let ptr = self.base.add(offset);
+            if self.format.bytes == 4 && ptr as usize % 4 == 0 {
+                core::ptr::write_volatile(ptr as *mut u32, pixel);
+                return;
+            }
+            for i in 0..self.format.bytes {
+                core::ptr::write_volatile(ptr.add(i), (pixel >> (i * 8)) as u8);
+            }
+        }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        let mut pixel = 0;
+        // SAFETY: see `write()`
+        unsafe {
+            let ptr = self.base.add(offset);
+            for i in 0..self.format.bytes {
+                pixel |= (core::ptr::read_volatile(ptr.add(i)) as u32) << (i * 8);
+            }
+        }
+        pixel
+    }
+}
+
+impl Drop for FrameBuffer {
+    fn drop(&mut self) {
+        if let Some(index) = self.index {
+            TAKEN.fetch_and(!(1 << index), Ordering::AcqRel);
+        }
+    }
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+    use std::vec;
+
+    /// 32 bit xRGB, as set up by QEMU's VGA & bochs displays
+    const XRGB: VideoMode = VideoMode {
+        width: 4,
+        height: 3,
+        pitch: 20,
+        bpp: 32,
+        memory_model: MEMORY_MODEL_RGB,
+        red_mask: (8, 16),
+        green_mask: (8, 8),
+        blue_mask: (8, 0),
+    };
+
+    const RGB565: VideoMode = VideoMode {
+        width: 4,
+        height: 3,
+        pitch: 8,
+        bpp: 16,
+        red_mask: (5, 11),
+        green_mask: (6, 5),
+        blue_mask: (5, 0),
+        ..XRGB
+    };
+
+    #[test]
+    fn pixel_formats() {
+        let xrgb = PixelFormat::new(&XRGB).unwrap();
+        assert_eq!(xrgb.bytes_per_pixel(), 4);
+        assert_eq!(xrgb.encode(Color::rgb(0x12, 0x34, 0x56)), 0x0012_3456);
+        assert_eq!(xrgb.decode(0x0012_3456), Color::rgb(0x12, 0x34, 0x56));
+
+        let rgb565 = PixelFormat::new(&RGB565).unwrap();
+        assert_eq!(rgb565.encode(Color::WHITE), 0xFFFF);
+        assert_eq!(rgb565.encode(Color::rgb(0xFF, 0, 0)), 0xF800);
+        assert_eq!(rgb565.decode(0x07E0), Color::rgb(0, 0xFC, 0));
+
+        let text_mode = VideoMode {
+            memory_model: 0,
+            ..XRGB
+        };
+        assert_eq!(
+            PixelFormat::new(&text_mode),
+            Err(FrameBufferError::UnsupportedFormat)
+        );
+        let outside = VideoMode {
+            red_mask: (8, 30),
+            ..XRGB
+        };
+        assert_eq!(
+            PixelFormat::new(&outside),
+            Err(FrameBufferError::UnsupportedFormat)
+        );
+    }
+
+    #[test]
+    fn drawing_is_clipped() {
+        let mut memory = vec![0xAA_u8; XRGB.pitch * XRGB.height];
+        let mut fb = unsafe { FrameBuffer::from_raw(memory.as_mut_ptr(), &XRGB).unwrap() };
+        fb.clear(Color::BLACK);
+        fb.set_pixel(3, 2, Color::WHITE);
+        fb.set_pixel(4, 0, Color::WHITE);
+        fb.fill_rect(2, 1, 10, 10, Color::rgb(1, 2, 3));
+        assert_eq!(fb.pixel(0, 0), Some(Color::BLACK));
+        assert_eq!(fb.pixel(3, 2), Some(Color::rgb(1, 2, 3)));
+        assert_eq!(fb.pixel(4, 0), None);
+
+        let image = [
+            Color::WHITE,
+            Color::rgb(9, 9, 9),
+            Color::WHITE,
+            Color::WHITE,
+        ];
+        fb.blit(3, 0, 2, &image);
+        assert_eq!(fb.pixel(3, 0), Some(Color::WHITE));
+        assert_eq!(fb.pixel(3, 1), Some(Color::WHITE));
+        assert_eq!(fb.pixel(2, 0), Some(Color::BLACK));
+        drop(fb);
+
+        // the padding behind every line is not touched
+        for line in memory.chunks(XRGB.pitch) {
+            assert_eq!(line[16..], [0xAA; 4]);
+        }
+    }
+
+    #[test]
+    fn packed_pixels() {
+        let mut memory = vec![0_u8; RGB565.pitch * RGB565.height];
+        let mut fb = unsafe { FrameBuffer::from_raw(memory.as_mut_ptr(), &RGB565).unwrap() };
+        fb.set_pixel(1, 1, Color::rgb(0xFF, 0, 0));
+        drop(fb);
+        assert_eq!(memory[10..12], [0x00, 0xF8]);
+
+        // the red pixel moves up by a row, the last row is filled
+        let mut fb = unsafe { FrameBuffer::from_raw(memory.as_mut_ptr(), &RGB565).unwrap() };
+        fb.scroll_up(1, Color::WHITE);
+        assert_eq!(fb.pixel(1, 0), Some(Color::rgb(0xF8, 0, 0)));
+        assert_eq!(fb.pixel(1, 1), Some(Color::BLACK));
+        assert_eq!(fb.pixel(0, 2), Some(Color::rgb(0xF8, 0xFC, 0xF8)));
+        drop(fb);
+
+        let narrow = VideoMode { pitch: 6, ..RGB565 };
+        assert!(matches!(
+            unsafe { FrameBuffer::from_raw(memory.as_mut_ptr(), &narrow) },
+            Err(FrameBufferError::UnsupportedFormat)
+        ));
+    }
+}
+
+// kernel tests, see `make test-x86_64`
+#[cfg(all(test, target_os = "none"))]
+mod kernel_tests {
+    use super::*;
+
+    #[test_case]
+    fn qemu_display() {
+        let mut fb = FrameBuffer::take(0).unwrap();
+        assert!(fb.width() > 0 && fb.height() > 0);
+        assert!(matches!(FrameBuffer::take(0), Err(FrameBufferError::Taken)));
+
+        let color = Color::rgb(0x40, 0x80, 0xC0);
+        fb.fill_rect(fb.width() - 8, fb.height() - 8, 16, 16, color);
+        assert_eq!(fb.pixel(fb.width() - 1, fb.height() - 1), Some(color));
+        assert_eq!(fb.pixel(fb.width(), 0), None);
+
+        drop(fb);
+        assert!(FrameBuffer::take(0).is_ok());
+    }
+}
@@ -0,0 +1,284 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Images decoded into `Color`s on the heap, for drawing on a `FrameBuffer`.
+//! <br> main source: https://docs.rs/tinybmp/0.3.3/tinybmp/
+//!
+//! BMP files are decoded with `tinybmp`, which draws them onto the `DrawTarget` of `Image`: 1 & 8
+//! bit images with a color table, 16 bit RGB555/565, 24 bit & 32 bit RGB888. Images can be scaled
+//! (nearest neighbour) & are clipped when drawn.
+
+use super::framebuffer::{Color, FrameBuffer};
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics::prelude::{DrawTarget, ImageDrawable, OriginDimensions, Pixel, Size};
+use tinybmp::{Bmp, Bpp, DynamicBmp, ParseError, RawBmp};
+
+/// Size of the BMP file header, it ends with the offset of the pixel data
+const BMP_FILE_HEADER_SIZE: usize = 14;
+
+/// `embedded-graphics` calls the C `fmodf` through `micromath`, the kernel has no libc providing it
+#[cfg(target_os = "none")]
+#[no_mangle]
+extern "C" fn fmodf(x: f32, y: f32) -> f32 {
+    libm::fmodf(x, y)
+}
+
+/// Error returned when an image can not be created
+///
+/// ## Variants:
+/// - `Bmp` : The data is no BMP file `tinybmp` can decode, contains the cause
+/// - `Empty` : The image has no pixels
+/// - `OutOfMemory` : The heap can not hold the pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    Bmp(ParseError),
+    Empty,
+    OutOfMemory,
+}
+
+/// Pixels of an image, row by row from the top left corner
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    /// Image of `width * height` black pixels
+    pub fn new(width: usize, height: usize) -> Result<Self, ImageError> {
+        let count = width.checked_mul(height).ok_or(ImageError::OutOfMemory)?;
+        if count == 0 {
+            return Err(ImageError::Empty);
+        }
+        let mut pixels = Vec::new();
+        pixels
+            .try_reserve_exact(count)
+            .map_err(|_| ImageError::OutOfMemory)?;
+        pixels.resize(count, Color::BLACK);
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Decodes a BMP file
+    pub fn from_bmp(data: &[u8]) -> Result<Self, ImageError> {
+        // tinybmp panics if the pixel data offset lies behind the file
+        let offset = data
+            .get(BMP_FILE_HEADER_SIZE - 4..BMP_FILE_HEADER_SIZE)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize);
+        if !offset.map_or(false, |offset| offset <= data.len()) {
+            return Err(ImageError::Bmp(ParseError::Header));
+        }
+
+        let raw = RawBmp::from_slice(data).map_err(ImageError::Bmp)?;
+        let size = raw.size();
+        let mut image = Self::new(size.width as usize, size.height as usize)?;
+        // `DynamicBmp` would map color tables to gray
+        let result = match raw.color_bpp() {
+            Bpp::Bits1 | Bpp::Bits8 => Bmp::<Rgb888>::from_slice(data)
+                .map_err(ImageError::Bmp)?
+                .draw(&mut image),
+            _ => DynamicBmp::<Rgb888>::from_slice(data)
+                .map_err(ImageError::Bmp)?
+                .draw(&mut image),
+        };
+        // drawing on an `Image` never fails
+        result.ok();
+        Ok(image)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The color of a pixel, None outside of the image
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Color> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.pixels[y * self.width + x])
+    }
+
+    /// Sets a pixel, nothing is drawn outside of the image
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    /// The largest size that fits into `width * height` & keeps the aspect ratio
+    pub fn fit(&self, width: usize, height: usize) -> (usize, usize) {
+        // compare width / self.width & height / self.height without rounding
+        match width * self.height <= height * self.width {
+            true => (width, (self.height * width / self.width).max(1)),
+            false => ((self.width * height / self.height).max(1), height),
+        }
+    }
+
+    /// Copy resized to `width * height`, every pixel takes the color of the nearest source pixel
+    pub fn scaled(&self, width: usize, height: usize) -> Result<Self, ImageError> {
+        let mut image = Self::new(width, height)?;
+        for y in 0..height {
+            let source = y * self.height / height * self.width;
+            for x in 0..width {
+                image.pixels[y * width + x] = self.pixels[source + x * self.width / width];
+            }
+        }
+        Ok(image)
+    }
+
+    /// Draws the image with its top left corner at (x, y), which may lie outside of the
+    /// framebuffer. Only the part on the framebuffer is drawn.
+    pub fn draw(&self, fb: &mut FrameBuffer, x: isize, y: isize) {
+        // columns left of the framebuffer
+        let skip = x.min(0).unsigned_abs().min(self.width);
+        for (row, pixels) in self.pixels.chunks(self.width).enumerate() {
+            let row_y = y + row as isize;
+            if row_y < 0 {
+                continue;
+            }
+            if row_y as usize >= fb.height() {
+                break;
+            }
+            let pixels = &pixels[skip..];
+            fb.blit(x.max(0) as usize, row_y as usize, pixels.len(), pixels);
+        }
+    }
+}
+
+impl OriginDimensions for Image {
+    fn size(&self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+}
+
+/// Used by `tinybmp` to decode, pixels outside of the image are dropped
+impl DrawTarget for Image {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) {
+                self.set_pixel(x, y, Color::rgb(color.r(), color.g(), color.b()));
+            }
+        }
+        Ok(())
+    }
+}
+
+// host unit tests, see `make test-host`
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+    use super::*;
+    use crate::limine::{VideoMode, MEMORY_MODEL_RGB};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// BMP file with a 40 byte info header & the pixel data right behind the color table
+    fn bmp(width: i32, height: i32, bpp: u16, table: &[u32], data: &[u8]) -> Vec<u8> {
+        let offset = (14 + 40 + table.len() * 4) as u32;
+        let mut file = vec![b'B', b'M'];
+        file.extend((offset + data.len() as u32).to_le_bytes());
+        file.extend([0; 4]);
+        file.extend(offset.to_le_bytes());
+        file.extend(40_u32.to_le_bytes());
+        file.extend(width.to_le_bytes());
+        file.extend(height.to_le_bytes());
+        file.extend(1_u16.to_le_bytes());
+        file.extend(bpp.to_le_bytes());
+        // compression, data size & resolution
+        file.extend([0; 16]);
+        file.extend((table.len() as u32).to_le_bytes());
+        file.extend([0; 4]);
+        for entry in table {
+            file.extend(entry.to_le_bytes());
+        }
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn decode_bmp() {
+        // 2x2, 24 bit BGR, rows are stored bottom up & padded to 4 bytes
+        let data = [
+            0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0, 0, // red, green
+            0xFF, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0, 0, // blue, white
+        ];
+        let image = Image::from_bmp(&bmp(2, 2, 24, &[], &data)).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 2));
+        assert_eq!(image.pixel(0, 0), Some(Color::rgb(0, 0, 0xFF)));
+        assert_eq!(image.pixel(1, 0), Some(Color::WHITE));
+        assert_eq!(image.pixel(0, 1), Some(Color::rgb(0xFF, 0, 0)));
+        assert_eq!(image.pixel(2, 0), None);
+
+        // 8 bit with a color table
+        let table = [0x0012_3456, 0x00AB_CDEF];
+        let image = Image::from_bmp(&bmp(2, 1, 8, &table, &[1, 0, 0, 0])).unwrap();
+        assert_eq!(image.pixel(0, 0), Some(Color::rgb(0xAB, 0xCD, 0xEF)));
+        assert_eq!(image.pixel(1, 0), Some(Color::rgb(0x12, 0x34, 0x56)));
+
+        let splash = Image::from_bmp(include_bytes!("splash.bmp")).unwrap();
+        assert_eq!((splash.width(), splash.height()), (64, 24));
+    }
+
+    #[test]
+    fn invalid_bmp() {
+        assert!(matches!(Image::from_bmp(b"BM"), Err(ImageError::Bmp(_))));
+        let mut file = bmp(1, 1, 24, &[], &[0; 4]);
+        assert!(Image::from_bmp(&file).is_ok());
+        // pixel data behind the end of the file
+        file[10] = 0xFF;
+        assert_eq!(
+            Image::from_bmp(&file).err(),
+            Some(ImageError::Bmp(ParseError::Header))
+        );
+        assert_eq!(
+            Image::from_bmp(&bmp(0, 1, 24, &[], &[])).err(),
+            Some(ImageError::Empty)
+        );
+    }
+
+    #[test]
+    fn scale_and_draw() {
+        let mut image = Image::new(2, 1).unwrap();
+        image.set_pixel(1, 0, Color::WHITE);
+        assert_eq!(image.fit(8, 8), (8, 4));
+        assert_eq!(image.fit(8, 2), (4, 2));
+        let scaled = image.scaled(4, 2).unwrap();
+        assert_eq!(scaled.pixel(1, 1), Some(Color::BLACK));
+        assert_eq!(scaled.pixel(2, 1), Some(Color::WHITE));
+
+        let mode = VideoMode {
+            width: 3,
+            height: 2,
+            pitch: 12,
+            bpp: 32,
+            memory_model: MEMORY_MODEL_RGB,
+            red_mask: (8, 16),
+            green_mask: (8, 8),
+            blue_mask: (8, 0),
+        };
+        let mut memory = vec![0xAA_u8; mode.pitch * mode.height];
+        let mut fb = unsafe { FrameBuffer::from_raw(memory.as_mut_ptr(), &mode).unwrap() };
+        // only the bottom left 2x1 pixels of the scaled image are on the framebuffer
+        scaled.draw(&mut fb, -2, 1);
+        assert_eq!(fb.pixel(0, 1), Some(Color::WHITE));
+        assert_eq!(fb.pixel(2, 1), Some(Color::rgb(0xAA, 0xAA, 0xAA)));
+        assert_eq!(fb.pixel(0, 0), Some(Color::rgb(0xAA, 0xAA, 0xAA)));
+    }
+}
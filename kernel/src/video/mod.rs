@@ -0,0 +1,16 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+pub mod console;
+pub mod font;
+pub mod framebuffer;
+pub mod image;
+pub mod splash;
+
+pub use console::{Console, ConsoleError, ConsoleSink};
+pub use font::{Font, FontError};
+pub use framebuffer::{Color, FrameBuffer, FrameBufferError, PixelFormat};
+pub use image::{Image, ImageError};
@@ -0,0 +1,30 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Boot splash behind the framebuffer console.
+//!
+//! With `BOOT_SPLASH` set in `config/` the console draws its text over the splash. The image is
+//! the limine module `BOOT_SPLASH_MODULE` (`MODULE_PATH=boot:///splash.bmp` in `limine.cfg`) or
+//! the `splash.bmp` embedded in the kernel, scaled to fit the screen.
+
+use super::image::{Image, ImageError};
+use crate::config::BOOT_SPLASH_MODULE;
+use crate::limine;
+
+/// Used without the module
+static EMBEDDED: &[u8] = include_bytes!("splash.bmp");
+
+/// The splash image scaled to fit into `width * height` pixels
+pub fn load(width: usize, height: usize) -> Result<Image, ImageError> {
+    let data = match limine::module(BOOT_SPLASH_MODULE) {
+        Ok(Some(module)) => module.data,
+        // the boot report lists the module feature if it is unavailable
+        Ok(None) | Err(_) => EMBEDDED,
+    };
+    let image = Image::from_bmp(data)?;
+    let (width, height) = image.fit(width, height);
+    image.scaled(width, height)
+}
@@ -0,0 +1,47 @@
+#!/bin/bash
+
+# This Source Code Form is subject to the terms of the Mozilla Public
+# License, v. 2.0. If a copy of the MPL was not distributed with this
+# file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+# Post-link step of the kernel: writes the function symbols of the linked kernel ($1) into its
+# reserved .ksymtab section, which kernel/src/backtrace.rs uses to name the backtrace frames.
+# One "ADDRESS SIZE NAME" line per function (hex, sorted by address), the rest is zero filled.
+# The section keeps its size, so no address in the kernel changes.
+
+set -e
+
+KERNEL="$1"
+NM="${NM:-llvm-nm}"
+OBJCOPY="${OBJCOPY:-llvm-objcopy}"
+TABLE="$KERNEL.ksymtab"
+
+# size of the reserved section, see SYMBOL_TABLE_SIZE in config/profiles/
+"$OBJCOPY" --dump-section .ksymtab="$TABLE" "$KERNEL"
+SIZE=$(stat -c %s "$TABLE")
+
+# function symbols only, rust hashes are cut off the demangled names
+"$NM" --defined-only --numeric-sort --print-size --demangle "$KERNEL" \
+  | awk -v size="$SIZE" '
+      BEGIN { hash = "::h"; for (i = 0; i < 16; i++) hash = hash "[0-9a-f]"; hash = hash "$" }
+      # with size: ADDRESS SIZE TYPE NAME, without: ADDRESS TYPE NAME
+      NF >= 4 && $3 ~ /^[tTwW]$/ { address = $1; length_ = $2; start = 4 }
+      NF >= 3 && $2 ~ /^[tTwW]$/ { address = $1; length_ = "0"; start = 3 }
+      start {
+        name = $start
+        for (i = start + 1; i <= NF; i++) name = name " " $i
+        sub(hash, "", name)
+        sub(/^0+/, "", length_)
+        line = sprintf("%s %s %s\n", address, length_ == "" ? "0" : length_, name)
+        # the table is zero terminated
+        if (used + length(line) >= size) { dropped++; start = 0; next }
+        used += length(line)
+        printf "%s", line
+        start = 0
+      }
+      END { if (dropped) printf "[WARNING] ksymtab: %d symbols do not fit, increase SYMBOL_TABLE_SIZE\n", dropped > "/dev/stderr" }
+    ' > "$TABLE"
+
+truncate -s "$SIZE" "$TABLE"
+"$OBJCOPY" --update-section .ksymtab="$TABLE" "$KERNEL"
+rm "$TABLE"
@@ -0,0 +1,52 @@
+#!/bin/bash
+
+# This Source Code Form is subject to the terms of the Mozilla Public
+# License, v. 2.0. If a copy of the MPL was not distributed with this
+# file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+# Cargo runner used by `make test-x86_64`: packs the kernel test binary ($1) into an ISO, boots it
+# in QEMU and turns the isa-debug-exit code written by kernel/src/testing.rs into the exit status
+
+set -e
+
+ROOT="$(cd "$(dirname "$0")/../.." && pwd)"
+PATH_LIMINE_BIN="${PATH_LIMINE_BIN:-limine/bin/}"
+case "$PATH_LIMINE_BIN" in
+  /*) ;;
+  *) PATH_LIMINE_BIN="$ROOT/$PATH_LIMINE_BIN" ;;
+esac
+ISOROOT="$ROOT/build/isoroot_test_x86_64"
+ISO="$ROOT/build/RezOS-test-x86_64.iso"
+# seconds until a hanging test run is killed
+TEST_TIMEOUT="${TEST_TIMEOUT:-120}"
+
+mkdir -p "$ISOROOT"
+cp "$1" "$ISOROOT/kernel.bin"
+cp "$ROOT/kernel/limine.cfg" "$ISOROOT/limine.cfg"
+cp "$PATH_LIMINE_BIN/limine-cd.bin" "$PATH_LIMINE_BIN/limine-cd-efi.bin" "$PATH_LIMINE_BIN/limine.sys" "$ISOROOT/"
+
+xorriso -as mkisofs -b limine-cd.bin \
+        -no-emul-boot \
+        -boot-load-size 4 \
+        -boot-info-table --efi-boot limine-cd-efi.bin -efi-boot-part \
+        --efi-boot-image \
+        "$ISOROOT" -o "$ISO" 2> /dev/null
+"$PATH_LIMINE_BIN/limine-deploy" "$ISO" > /dev/null
+
+set +e
+timeout "$TEST_TIMEOUT" qemu-system-x86_64 -cdrom "$ISO" \
+        -device isa-debug-exit,iobase=0xf4,iosize=0x04 \
+        -serial stdio \
+        -vga std \
+        -display none \
+        -no-reboot \
+        $QEMU_ARGS
+STATUS=$?
+set -e
+
+# isa-debug-exit exits with (value << 1) | 1, testing::QemuExitCode::Success is 0x10
+if [ $STATUS -eq 33 ]; then
+  exit 0
+fi
+echo "Kernel tests failed! (QEMU exit status $STATUS)"
+exit 1